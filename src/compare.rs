@@ -0,0 +1,131 @@
+use crate::error::Error;
+use crate::subtitle::get_video_data;
+use crate::{gemini, log, metrics, parallel};
+use std::fmt::Write as _;
+
+pub const MIN_VIDEOS: usize = 2;
+pub const MAX_VIDEOS: usize = 8;
+/// Above this many transcript characters in total, each video is summarized on its own first and
+/// the comparison works from the summaries. Keeps well inside the model's context window.
+const MAX_DIRECT_CHARS: usize = 400_000;
+
+/// Used for the individual summaries when neither the request nor the config has a system prompt
+pub const VIDEO_PROMPT: &str = "Summarize this video transcript in Markdown. Keep every claim, \
+    argument, number and recommendation the speaker makes, and note who makes them.";
+
+const COMPARE_PROMPT: &str = "You are given several YouTube videos on a related topic, numbered \
+    [1], [2] and so on, each with its title and either its transcript or a summary of it. Write a \
+    single comparative brief in Markdown with these sections:
+
+## Overview
+What the videos cover together, in a few sentences.
+
+## Agreements
+Points several videos make, citing each video that makes them, like [1][3].
+
+## Disagreements
+Where the videos contradict each other or weigh things differently, with each side attributed.
+
+## Unique points
+For each video, what only it brings up.
+
+## Verdict
+A short synthesis of what a viewer should take away from all of them.
+
+Attribute every claim to the videos it comes from with their numbers. Don't invent claims a video \
+doesn't make, and say so when the videos simply don't overlap.";
+
+pub struct Video {
+    pub url: String,
+    pub title: String,
+    pub transcript: String,
+}
+
+pub struct Llm<'a> {
+    pub api_key: &'a str,
+    pub model: &'a str,
+    /// For summarizing videos one by one when they're too long to compare directly
+    pub video_prompt: &'a str,
+}
+
+pub struct Comparison {
+    pub synthesis: String,
+    /// Each video's own summary, in order, when they were too long to compare directly
+    pub summaries: Option<Vec<String>>,
+}
+
+/// Fetches the transcripts in parallel. Fails with the first video that couldn't be fetched.
+pub fn fetch(urls: &[String], language: &str) -> Result<Vec<Video>, Error> {
+    let results = parallel::map(urls, |url| {
+        log::stage("transcript_fetch", &metrics::TRANSCRIPT_FETCH, || {
+            get_video_data(url, language)
+        })
+    });
+
+    urls.iter()
+        .zip(results)
+        .map(|(url, result)| {
+            let data = result.inspect_err(|e| {
+                log::warn!("Fetching a video to compare failed"; url = url, error = e);
+            })?;
+            Ok(Video {
+                url: url.clone(),
+                title: data.title,
+                transcript: data.transcript,
+            })
+        })
+        .collect()
+}
+
+/// Asks the model for a comparative brief of `videos`, with `focus` as an optional extra
+/// instruction like the question to answer
+pub fn compare(videos: &[Video], llm: &Llm, focus: Option<&str>) -> Result<Comparison, Error> {
+    let total: usize = videos.iter().map(|v| v.transcript.len()).sum();
+    let summaries = if total > MAX_DIRECT_CHARS {
+        log::info!("Summarizing videos before comparing"; videos = videos.len(), chars = total);
+        Some(summarize_each(videos, llm)?)
+    } else {
+        None
+    };
+
+    let mut input = String::new();
+    for (i, video) in videos.iter().enumerate() {
+        let (kind, text) = summaries
+            .as_ref()
+            .map_or(("Transcript", video.transcript.as_str()), |summaries| {
+                ("Summary", summaries[i].as_str())
+            });
+        let _ = write!(
+            input,
+            "[{}] {}\nURL: {}\n{kind}:\n{}\n\n",
+            i + 1,
+            video.title,
+            video.url,
+            text.trim()
+        );
+    }
+
+    let mut prompt = COMPARE_PROMPT.to_string();
+    if let Some(focus) = focus.map(str::trim).filter(|f| !f.is_empty()) {
+        let _ = write!(prompt, "\n\nFocus the comparison on this: {focus}");
+    }
+
+    let synthesis = log::stage("llm_call", &metrics::LLM_CALL, || {
+        gemini::summarize(llm.api_key, llm.model, &prompt, &input)
+    })?;
+    Ok(Comparison {
+        synthesis,
+        summaries,
+    })
+}
+
+fn summarize_each(videos: &[Video], llm: &Llm) -> Result<Vec<String>, Error> {
+    parallel::map(videos, |video| {
+        log::stage("llm_call", &metrics::LLM_CALL, || {
+            gemini::summarize(llm.api_key, llm.model, llm.video_prompt, &video.transcript)
+        })
+    })
+    .into_iter()
+    .map(|result| Ok(result?))
+    .collect()
+}
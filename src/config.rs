@@ -0,0 +1,931 @@
+use crate::{log, subscriptions, webhook};
+use miniserde::Serialize;
+use miniserde::json::{self, Number, Value};
+use std::fmt::{self, Write as _};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::Duration;
+use std::{env, fs, io};
+
+pub const USAGE: &str = "Usage: YouTubeTLDR [--config <file.json>] [--print-config]
+
+  --config <file>   Read settings from a JSON config file (or set TLDR_CONFIG)
+  --print-config    Print the effective configuration and exit
+  --help            Show this message
+
+Environment variables override the config file, see the README for the full list.";
+
+/// Every setting as `section.key` in the config file, with the environment variable that overrides it
+const SETTINGS: &[(&str, &str)] = &[
+    ("server.ip", "TLDR_IP"),
+    ("server.port", "TLDR_PORT"),
+    ("server.workers", "TLDR_WORKERS"),
+    ("server.read_timeout_secs", "TLDR_READ_TIMEOUT"),
+    ("server.write_timeout_secs", "TLDR_WRITE_TIMEOUT"),
+    ("server.keep_alive_timeout_secs", "TLDR_KEEP_ALIVE_TIMEOUT"),
+    ("server.shutdown_timeout_secs", "TLDR_SHUTDOWN_TIMEOUT"),
+    ("server.request_timeout_secs", "TLDR_REQUEST_TIMEOUT"),
+    ("server.tls_cert", "TLDR_TLS_CERT"),
+    ("server.tls_key", "TLDR_TLS_KEY"),
+    ("server.redirect_port", "TLDR_REDIRECT_PORT"),
+    ("server.cors_origins", "TLDR_CORS_ORIGINS"),
+    ("server.cors_credentials", "TLDR_CORS_CREDENTIALS"),
+    ("server.log_level", "TLDR_LOG_LEVEL"),
+    ("server.log_format", "TLDR_LOG_FORMAT"),
+    ("youtube.cookies_file", "TLDR_COOKIES"),
+    ("youtube.default_language", "TLDR_DEFAULT_LANGUAGE"),
+    ("youtube.timeout_secs", "TLDR_YOUTUBE_TIMEOUT"),
+    ("llm.api_key", "TLDR_LLM_API_KEY"),
+    ("llm.model", "TLDR_LLM_MODEL"),
+    ("llm.system_prompt", "TLDR_LLM_SYSTEM_PROMPT"),
+    ("llm.timeout_secs", "TLDR_LLM_TIMEOUT"),
+    ("llm.share_api_key", "TLDR_LLM_SHARE_API_KEY"),
+    ("cache.player_config_ttl_secs", "TLDR_PLAYER_CONFIG_TTL"),
+    ("limits.max_connections", "TLDR_MAX_CONNECTIONS"),
+    ("limits.max_body_size", "TLDR_MAX_BODY_SIZE"),
+    ("limits.queue_capacity", "TLDR_QUEUE_CAPACITY"),
+    (
+        "limits.max_requests_per_connection",
+        "TLDR_MAX_REQUESTS_PER_CONNECTION",
+    ),
+    ("limits.max_live_jobs", "TLDR_MAX_LIVE_JOBS"),
+    ("limits.max_parallel_fetches", "TLDR_MAX_PARALLEL_FETCHES"),
+    ("history.file", "TLDR_HISTORY_FILE"),
+    ("history.permalinks", "TLDR_PERMALINKS"),
+    ("history.admin_token", "TLDR_ADMIN_TOKEN"),
+    ("history.public_feeds", "TLDR_PUBLIC_FEEDS"),
+    ("webhook.url", "TLDR_WEBHOOK_URL"),
+    ("webhook.secret", "TLDR_WEBHOOK_SECRET"),
+    ("webhook.timeout_secs", "TLDR_WEBHOOK_TIMEOUT"),
+    ("webhook.retries", "TLDR_WEBHOOK_RETRIES"),
+    ("webhook.allowed_hosts", "TLDR_WEBHOOK_ALLOWED_HOSTS"),
+    ("subscriptions.channels", "TLDR_SUBSCRIPTIONS"),
+    ("subscriptions.api_key", "TLDR_SUBSCRIPTIONS_API_KEY"),
+    (
+        "subscriptions.interval_minutes",
+        "TLDR_SUBSCRIPTION_INTERVAL",
+    ),
+];
+
+#[derive(Debug)]
+pub enum Error {
+    Usage(String),
+    Read(PathBuf, io::Error),
+    Parse(PathBuf),
+    UnknownSetting(String),
+    Invalid { setting: String, reason: String },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Usage(msg) => write!(f, "{msg}\n\n{USAGE}"),
+            Self::Read(path, e) => write!(f, "Can't read config file {}: {e}", path.display()),
+            Self::Parse(path) => write!(f, "Config file {} is not valid JSON", path.display()),
+            Self::UnknownSetting(setting) => write!(f, "Unknown setting {setting}"),
+            Self::Invalid { setting, reason } => write!(f, "Invalid {setting}: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Read(_, e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+pub struct Args {
+    pub config_file: Option<PathBuf>,
+    pub print_config: bool,
+    pub help: bool,
+}
+
+impl Args {
+    pub fn parse(mut args: impl Iterator<Item = String>) -> Result<Self, Error> {
+        let mut parsed = Self {
+            config_file: env::var_os("TLDR_CONFIG")
+                .filter(|p| !p.is_empty())
+                .map(PathBuf::from),
+            print_config: false,
+            help: false,
+        };
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--config" | "-c" => {
+                    let path = args
+                        .next()
+                        .ok_or_else(|| Error::Usage("--config needs a file path".into()))?;
+                    parsed.config_file = Some(path.into());
+                }
+                "--print-config" => parsed.print_config = true,
+                "--help" | "-h" => parsed.help = true,
+                _ => return Err(Error::Usage(format!("Unknown argument {arg}"))),
+            }
+        }
+
+        Ok(parsed)
+    }
+}
+
+#[derive(Serialize)]
+pub struct Config {
+    pub server: Server,
+    pub youtube: YouTube,
+    pub llm: Llm,
+    pub cache: Cache,
+    pub limits: Limits,
+    pub history: History,
+    pub webhook: Webhook,
+    pub subscriptions: Subscriptions,
+}
+
+#[derive(Serialize)]
+pub struct Server {
+    pub ip: String,
+    pub port: u16,
+    pub workers: usize,
+    pub read_timeout_secs: u64,
+    pub write_timeout_secs: u64,
+    pub keep_alive_timeout_secs: u64,
+    pub shutdown_timeout_secs: u64,
+    /// From receiving an API request to answering it, with a `504` once it's up
+    pub request_timeout_secs: u64,
+    pub tls_cert: Option<String>,
+    pub tls_key: Option<String>,
+    pub redirect_port: Option<u16>,
+    pub cors_origins: Vec<String>,
+    pub cors_credentials: bool,
+    pub log_level: String,
+    pub log_format: String,
+}
+
+#[derive(Serialize)]
+pub struct YouTube {
+    pub cookies_file: Option<String>,
+    pub default_language: String,
+    /// Each request while fetching a transcript
+    pub timeout_secs: u64,
+}
+
+/// Used when a request doesn't bring its own
+#[derive(Serialize)]
+pub struct Llm {
+    pub api_key: Option<String>,
+    pub model: Option<String>,
+    pub system_prompt: Option<String>,
+    /// Each call to the model. Always applies, not only when requests use the defaults above.
+    pub timeout_secs: u64,
+    /// Lets requests without an API key of their own use `api_key`, and so spend its quota.
+    /// Off by default, as anyone who can reach the server could.
+    pub share_api_key: bool,
+}
+
+#[derive(Serialize)]
+pub struct Cache {
+    pub player_config_ttl_secs: u64,
+}
+
+#[derive(Serialize)]
+pub struct Limits {
+    /// Open client connections, idle keep-alive ones included
+    pub max_connections: usize,
+    pub max_body_size: usize,
+    pub queue_capacity: usize,
+    pub max_requests_per_connection: usize,
+    pub max_live_jobs: usize,
+    /// Transcript or model requests a single API call may have in flight at once
+    pub max_parallel_fetches: usize,
+}
+
+/// Off unless a file is set, summaries then stay only in the browser
+#[derive(Serialize)]
+pub struct History {
+    pub file: Option<String>,
+    /// Whether entries can be shared at `/s/{token}`
+    pub permalinks: bool,
+    /// Bearer token for the `/api/history` routes, which refuse every request while it's unset
+    pub admin_token: Option<String>,
+    /// Lets anyone read the feeds under `/feeds`, which otherwise need the admin token like the API
+    pub public_feeds: bool,
+}
+
+/// Notifications when a summary finishes. Requests can name their own URL, this one is for the rest.
+#[derive(Serialize)]
+pub struct Webhook {
+    pub url: Option<String>,
+    /// Signs deliveries to `url` with HMAC-SHA256 when set
+    pub secret: Option<String>,
+    pub timeout_secs: u64,
+    /// Further attempts after a failed delivery
+    pub retries: u32,
+    /// Hosts the URLs requests name may point at. Without any, requests can't name one.
+    pub allowed_hosts: Vec<String>,
+}
+
+/// Channels whose new uploads are summarized in the background
+#[derive(Serialize)]
+pub struct Subscriptions {
+    pub channels: Vec<Subscription>,
+    /// How often to check for uploads, unless a channel sets its own
+    pub interval_minutes: u64,
+    /// The key background summaries are made with, apart from the one requests may share
+    pub api_key: Option<String>,
+}
+
+#[derive(Serialize, Clone)]
+pub struct Subscription {
+    pub channel_id: String,
+    /// Replaces the system prompt for this channel's videos
+    pub prompt: Option<String>,
+    pub language: Option<String>,
+    pub interval_minutes: Option<u64>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            server: Server {
+                ip: "0.0.0.0".into(),
+                port: 8000,
+                workers: 4,
+                read_timeout_secs: 15,
+                write_timeout_secs: 15,
+                keep_alive_timeout_secs: 5,
+                shutdown_timeout_secs: 30,
+                request_timeout_secs: 300,
+                tls_cert: None,
+                tls_key: None,
+                redirect_port: None,
+                cors_origins: Vec::new(),
+                cors_credentials: false,
+                log_level: "info".into(),
+                log_format: "text".into(),
+            },
+            youtube: YouTube {
+                cookies_file: None,
+                default_language: "en".into(),
+                timeout_secs: 30,
+            },
+            llm: Llm {
+                api_key: None,
+                model: None,
+                system_prompt: None,
+                timeout_secs: 120,
+                share_api_key: false,
+            },
+            cache: Cache {
+                player_config_ttl_secs: 3600,
+            },
+            limits: Limits {
+                max_connections: 1000,
+                max_body_size: 10 * 1024 * 1024,
+                queue_capacity: 100,
+                max_requests_per_connection: 100,
+                max_live_jobs: 8,
+                max_parallel_fetches: 4,
+            },
+            history: History {
+                file: None,
+                permalinks: false,
+                admin_token: None,
+                public_feeds: false,
+            },
+            webhook: Webhook {
+                url: None,
+                secret: None,
+                timeout_secs: 10,
+                retries: 3,
+                allowed_hosts: Vec::new(),
+            },
+            subscriptions: Subscriptions {
+                channels: Vec::new(),
+                interval_minutes: 60,
+                api_key: None,
+            },
+        }
+    }
+}
+
+static CONFIG: OnceLock<Config> = OnceLock::new();
+
+/// Makes `config` the one returned by `get`. Has to come before anything reads the config, which
+/// would have settled on the defaults, so a second call panics rather than being ignored.
+pub fn init(config: Config) -> &'static Config {
+    assert!(
+        CONFIG.set(config).is_ok(),
+        "config::init called after the config was already in use"
+    );
+    get()
+}
+
+/// The active config, or the defaults before `init`
+pub fn get() -> &'static Config {
+    CONFIG.get_or_init(Config::default)
+}
+
+/// Where a setting's value came from, which decides how it's parsed and how errors name it
+enum Raw<'a> {
+    Json(&'a Value),
+    Env(&'a str),
+}
+
+impl Config {
+    /// Defaults, overridden by the config file if given, overridden by environment variables
+    pub fn load(file: Option<&Path>) -> Result<Self, Error> {
+        Self::load_with(file, |var| env::var(var).ok())
+    }
+
+    /// `load` with `var` standing in for the environment
+    fn load_with(file: Option<&Path>, var: impl Fn(&str) -> Option<String>) -> Result<Self, Error> {
+        let mut config = Self::default();
+
+        if let Some(path) = file {
+            let content = fs::read(path).map_err(|e| Error::Read(path.to_path_buf(), e))?;
+            let root: Value =
+                json::from_slice(&content).map_err(|_| Error::Parse(path.to_path_buf()))?;
+            config.apply_file(&root, path)?;
+        }
+
+        for (key, name) in SETTINGS {
+            if let Some(value) = var(name) {
+                config
+                    .set(key, &Raw::Env(&value))
+                    .map_err(|reason| Error::Invalid {
+                        setting: (*name).to_string(),
+                        reason,
+                    })?;
+            }
+        }
+
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn apply_file(&mut self, root: &Value, path: &Path) -> Result<(), Error> {
+        let Value::Object(sections) = root else {
+            return Err(Error::Parse(path.to_path_buf()));
+        };
+
+        for (section, fields) in sections.iter() {
+            let Value::Object(fields) = fields else {
+                return Err(Error::Invalid {
+                    setting: format!("{section} in {}", path.display()),
+                    reason: "expected an object of settings".into(),
+                });
+            };
+
+            for (field, value) in fields.iter() {
+                let key = format!("{section}.{field}");
+                if !SETTINGS.iter().any(|(k, _)| *k == key) {
+                    return Err(Error::UnknownSetting(format!(
+                        "{key} in {}",
+                        path.display()
+                    )));
+                }
+                self.set(&key, &Raw::Json(value))
+                    .map_err(|reason| Error::Invalid {
+                        setting: format!("{key} in {}", path.display()),
+                        reason,
+                    })?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn set(&mut self, key: &str, raw: &Raw) -> Result<(), String> {
+        let server = &mut self.server;
+        match key {
+            "server.ip" => server.ip = raw.string()?,
+            "server.port" => server.port = raw.number()?,
+            "server.workers" => server.workers = raw.number()?,
+            "server.read_timeout_secs" => server.read_timeout_secs = raw.number()?,
+            "server.write_timeout_secs" => server.write_timeout_secs = raw.number()?,
+            "server.keep_alive_timeout_secs" => server.keep_alive_timeout_secs = raw.number()?,
+            "server.shutdown_timeout_secs" => server.shutdown_timeout_secs = raw.number()?,
+            "server.request_timeout_secs" => server.request_timeout_secs = raw.number()?,
+            "server.tls_cert" => server.tls_cert = raw.optional_string()?,
+            "server.tls_key" => server.tls_key = raw.optional_string()?,
+            "server.redirect_port" => server.redirect_port = raw.optional_number()?,
+            "server.cors_origins" => {
+                server.cors_origins = raw
+                    .list()?
+                    .iter()
+                    .map(|o| o.trim().trim_end_matches('/').to_string())
+                    .filter(|o| !o.is_empty())
+                    .collect();
+            }
+            "server.cors_credentials" => server.cors_credentials = raw.boolean()?,
+            "server.log_level" => server.log_level = raw.string()?,
+            "server.log_format" => server.log_format = raw.string()?,
+            "youtube.cookies_file" => self.youtube.cookies_file = raw.optional_string()?,
+            "youtube.default_language" => self.youtube.default_language = raw.string()?,
+            "youtube.timeout_secs" => self.youtube.timeout_secs = raw.number()?,
+            "llm.api_key" => self.llm.api_key = raw.optional_string()?,
+            "llm.model" => self.llm.model = raw.optional_string()?,
+            "llm.system_prompt" => self.llm.system_prompt = raw.optional_string()?,
+            "llm.timeout_secs" => self.llm.timeout_secs = raw.number()?,
+            "llm.share_api_key" => self.llm.share_api_key = raw.boolean()?,
+            "cache.player_config_ttl_secs" => self.cache.player_config_ttl_secs = raw.number()?,
+            "limits.max_connections" => self.limits.max_connections = raw.number()?,
+            "limits.max_body_size" => self.limits.max_body_size = raw.number()?,
+            "limits.queue_capacity" => self.limits.queue_capacity = raw.number()?,
+            "limits.max_requests_per_connection" => {
+                self.limits.max_requests_per_connection = raw.number()?;
+            }
+            "limits.max_live_jobs" => self.limits.max_live_jobs = raw.number()?,
+            "limits.max_parallel_fetches" => self.limits.max_parallel_fetches = raw.number()?,
+            "history.file" => self.history.file = raw.optional_string()?,
+            "history.permalinks" => self.history.permalinks = raw.boolean()?,
+            "history.admin_token" => self.history.admin_token = raw.optional_string()?,
+            "history.public_feeds" => self.history.public_feeds = raw.boolean()?,
+            "webhook.url" => self.webhook.url = raw.optional_string()?,
+            "webhook.secret" => self.webhook.secret = raw.optional_string()?,
+            "webhook.timeout_secs" => self.webhook.timeout_secs = raw.number()?,
+            "webhook.retries" => self.webhook.retries = raw.number()?,
+            "webhook.allowed_hosts" => {
+                self.webhook.allowed_hosts = raw
+                    .list()?
+                    .iter()
+                    .map(|h| h.trim().to_string())
+                    .filter(|h| !h.is_empty())
+                    .collect();
+            }
+            "subscriptions.channels" => self.subscriptions.channels = raw.subscriptions()?,
+            "subscriptions.api_key" => self.subscriptions.api_key = raw.optional_string()?,
+            "subscriptions.interval_minutes" => {
+                self.subscriptions.interval_minutes = raw.number()?;
+            }
+            _ => return Err("unknown setting".into()),
+        }
+        Ok(())
+    }
+
+    fn validate(&self) -> Result<(), Error> {
+        let invalid = |key: &str, reason: &str| {
+            let var = SETTINGS
+                .iter()
+                .find(|(k, _)| *k == key)
+                .map_or("", |(_, var)| var);
+            Err(Error::Invalid {
+                setting: format!("{key} ({var})"),
+                reason: reason.into(),
+            })
+        };
+
+        let server = &self.server;
+        if server.ip.parse::<IpAddr>().is_err() && !is_hostname(&server.ip) {
+            return invalid("server.ip", "expected an IP address or a hostname");
+        }
+        let positive = [
+            ("server.workers", server.workers as u64),
+            ("server.read_timeout_secs", server.read_timeout_secs),
+            ("server.write_timeout_secs", server.write_timeout_secs),
+            (
+                "server.keep_alive_timeout_secs",
+                server.keep_alive_timeout_secs,
+            ),
+            ("server.request_timeout_secs", server.request_timeout_secs),
+            ("youtube.timeout_secs", self.youtube.timeout_secs),
+            ("llm.timeout_secs", self.llm.timeout_secs),
+            ("limits.max_connections", self.limits.max_connections as u64),
+            ("limits.max_body_size", self.limits.max_body_size as u64),
+            ("limits.queue_capacity", self.limits.queue_capacity as u64),
+            (
+                "limits.max_requests_per_connection",
+                self.limits.max_requests_per_connection as u64,
+            ),
+            (
+                "limits.max_parallel_fetches",
+                self.limits.max_parallel_fetches as u64,
+            ),
+            ("webhook.timeout_secs", self.webhook.timeout_secs),
+            (
+                "subscriptions.interval_minutes",
+                self.subscriptions.interval_minutes,
+            ),
+        ];
+        if let Some((key, _)) = positive.iter().find(|(_, value)| *value == 0) {
+            return invalid(key, "must be at least 1");
+        }
+        if server.tls_cert.is_some() != server.tls_key.is_some() {
+            return invalid(
+                "server.tls_key",
+                "the TLS certificate and key must be set together",
+            );
+        }
+        if server.cors_credentials && server.cors_origins.iter().any(|o| o == "*") {
+            return invalid(
+                "server.cors_credentials",
+                "can't be combined with `*` in server.cors_origins, list the origins instead",
+            );
+        }
+        if server.redirect_port.is_some() && server.tls_cert.is_none() {
+            return invalid("server.redirect_port", "only works with TLS enabled");
+        }
+        if log::Level::parse(&server.log_level).is_none() {
+            return invalid("server.log_level", "expected error, warn, info or debug");
+        }
+        if log::Format::parse(&server.log_format).is_none() {
+            return invalid("server.log_format", "expected text, logfmt or json");
+        }
+        if self.youtube.default_language.is_empty() {
+            return invalid("youtube.default_language", "must not be empty");
+        }
+        if let Some(url) = &self.webhook.url
+            && !webhook::is_valid_url(url)
+        {
+            return invalid("webhook.url", "expected an http or https URL");
+        }
+        if !self.subscriptions.channels.is_empty() {
+            if self.history.file.is_none() {
+                return invalid(
+                    "subscriptions.channels",
+                    "summaries are stored in the history, set history.file too",
+                );
+            }
+            if self.subscriptions.api_key.is_none() {
+                return invalid(
+                    "subscriptions.api_key",
+                    "summarizing in the background needs an API key",
+                );
+            }
+        }
+        let max = subscriptions::MAX_INTERVAL_MINUTES;
+        let channel_intervals = self.subscriptions.channels.iter();
+        if let Some(minutes) = channel_intervals
+            .filter_map(|channel| channel.interval_minutes)
+            .chain([self.subscriptions.interval_minutes])
+            .find(|&minutes| minutes > max)
+        {
+            let reason = format!("{minutes} minutes is more than a week, the most is {max}");
+            return invalid("subscriptions.interval_minutes", &reason);
+        }
+
+        Ok(())
+    }
+
+    pub fn log_level(&self) -> log::Level {
+        log::Level::parse(&self.server.log_level).unwrap_or(log::Level::Info)
+    }
+
+    pub fn log_format(&self) -> log::Format {
+        log::Format::parse(&self.server.log_format).unwrap_or(log::Format::Text)
+    }
+
+    /// The effective config as an indented config file, with the API key and other secrets hidden
+    pub fn to_pretty_json(&self) -> String {
+        let mut value: Value = json::from_str(&json::to_string(self)).unwrap_or_default();
+        for (section, field) in [
+            ("llm", "api_key"),
+            ("subscriptions", "api_key"),
+            ("history", "admin_token"),
+            ("webhook", "secret"),
+        ] {
+            if let Value::Object(sections) = &mut value
+                && let Some(Value::Object(fields)) = sections.get_mut(section)
+                && let Some(secret) = fields.get_mut(field)
+                && !matches!(secret, Value::Null)
+            {
+                *secret = Value::String("[REDACTED]".into());
+            }
+        }
+
+        let mut out = String::new();
+        write_pretty(&mut out, &value, 0);
+        out
+    }
+}
+
+/// Letters, digits and hyphens in dot-separated labels, like `localhost` or `tldr.internal`.
+/// What it resolves to is up to the system when the server binds to it.
+fn is_hostname(host: &str) -> bool {
+    host.len() <= 253
+        && host.split('.').all(|label| {
+            (1..=63).contains(&label.len())
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label
+                    .bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || b == b'-')
+        })
+}
+
+impl Server {
+    /// What to bind to, a hostname being resolved by the system then
+    pub fn addr(&self) -> String {
+        match self.ip.parse::<IpAddr>() {
+            Ok(IpAddr::V6(ip)) => format!("[{ip}]:{}", self.port),
+            _ => format!("{}:{}", self.ip, self.port),
+        }
+    }
+
+    pub const fn read_timeout(&self) -> Duration {
+        Duration::from_secs(self.read_timeout_secs)
+    }
+
+    pub const fn write_timeout(&self) -> Duration {
+        Duration::from_secs(self.write_timeout_secs)
+    }
+
+    pub const fn keep_alive_timeout(&self) -> Duration {
+        Duration::from_secs(self.keep_alive_timeout_secs)
+    }
+
+    pub const fn shutdown_timeout(&self) -> Duration {
+        Duration::from_secs(self.shutdown_timeout_secs)
+    }
+}
+
+impl Raw<'_> {
+    fn string(&self) -> Result<String, String> {
+        match self {
+            Self::Env(s) => Ok((*s).to_string()),
+            Self::Json(Value::String(s)) => Ok(s.clone()),
+            Self::Json(_) => Err("expected a string".into()),
+        }
+    }
+
+    /// Empty strings and `null` both mean unset
+    fn optional_string(&self) -> Result<Option<String>, String> {
+        match self {
+            Self::Json(Value::Null) => Ok(None),
+            _ => self.string().map(|s| Some(s).filter(|s| !s.is_empty())),
+        }
+    }
+
+    fn number<T: TryFrom<u64>>(&self) -> Result<T, String> {
+        let n = match self {
+            Self::Env(s) => s
+                .trim()
+                .parse::<u64>()
+                .map_err(|_| format!("expected a whole number, got \"{s}\""))?,
+            Self::Json(Value::Number(Number::U64(n))) => *n,
+            Self::Json(_) => return Err("expected a whole number".into()),
+        };
+        T::try_from(n).map_err(|_| format!("{n} is out of range"))
+    }
+
+    fn optional_number<T: TryFrom<u64>>(&self) -> Result<Option<T>, String> {
+        match self {
+            Self::Json(Value::Null) | Self::Env("") => Ok(None),
+            _ => self.number().map(Some),
+        }
+    }
+
+    fn boolean(&self) -> Result<bool, String> {
+        match self {
+            Self::Env(s) => match s.to_ascii_lowercase().as_str() {
+                "1" | "true" | "yes" => Ok(true),
+                "0" | "false" | "no" | "" => Ok(false),
+                _ => Err(format!("expected true or false, got \"{s}\"")),
+            },
+            Self::Json(Value::Bool(b)) => Ok(*b),
+            Self::Json(_) => Err("expected true or false".into()),
+        }
+    }
+
+    /// Channel ids, or in a config file also objects with per-channel settings
+    fn subscriptions(&self) -> Result<Vec<Subscription>, String> {
+        let subscription = |channel_id: &str| {
+            let channel_id = channel_id.trim();
+            if channel_id.is_empty()
+                || !channel_id
+                    .bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+            {
+                return Err(format!("\"{channel_id}\" is not a channel id"));
+            }
+            Ok(Subscription {
+                channel_id: channel_id.to_string(),
+                prompt: None,
+                language: None,
+                interval_minutes: None,
+            })
+        };
+
+        let Self::Json(Value::Array(items)) = self else {
+            return self
+                .list()?
+                .iter()
+                .filter(|id| !id.trim().is_empty())
+                .map(|id| subscription(id))
+                .collect();
+        };
+        items
+            .iter()
+            .map(|item| match item {
+                Value::String(id) => subscription(id),
+                Value::Object(fields) => {
+                    let field = |name: &str| match fields.get(name) {
+                        None | Some(Value::Null) => Ok(None),
+                        Some(value) => Raw::Json(value).optional_string(),
+                    };
+                    for name in fields.keys() {
+                        if !["channel_id", "prompt", "language", "interval_minutes"]
+                            .contains(&name.as_str())
+                        {
+                            return Err(format!("unknown channel setting {name}"));
+                        }
+                    }
+                    Ok(Subscription {
+                        prompt: field("prompt")?,
+                        language: field("language")?,
+                        interval_minutes: fields
+                            .get("interval_minutes")
+                            .map_or(Ok(None), |value| Raw::Json(value).optional_number())?
+                            .filter(|&minutes| minutes > 0),
+                        ..subscription(&field("channel_id")?.unwrap_or_default())?
+                    })
+                }
+                _ => Err("expected channel ids or objects with a channel_id".into()),
+            })
+            .collect()
+    }
+
+    /// A JSON array of strings, or a comma-separated environment variable
+    fn list(&self) -> Result<Vec<String>, String> {
+        match self {
+            Self::Env(s) => Ok(s.split(',').map(str::to_string).collect()),
+            Self::Json(Value::Array(items)) => items
+                .iter()
+                .map(|item| match item {
+                    Value::String(s) => Ok(s.clone()),
+                    _ => Err("expected a list of strings".to_string()),
+                })
+                .collect(),
+            Self::Json(_) => Err("expected a list of strings".into()),
+        }
+    }
+}
+
+fn write_pretty(out: &mut String, value: &Value, indent: usize) {
+    let pad = "  ".repeat(indent + 1);
+
+    match value {
+        Value::Object(object) if !object.is_empty() => {
+            out.push_str("{\n");
+            for (i, (key, value)) in object.iter().enumerate() {
+                let _ = write!(out, "{pad}{}: ", json::to_string(key));
+                write_pretty(out, value, indent + 1);
+                out.push_str(if i + 1 < object.len() { ",\n" } else { "\n" });
+            }
+            let _ = write!(out, "{}}}", "  ".repeat(indent));
+        }
+        Value::Array(array) if !array.is_empty() => {
+            out.push_str("[\n");
+            for (i, value) in array.iter().enumerate() {
+                out.push_str(&pad);
+                write_pretty(out, value, indent + 1);
+                out.push_str(if i + 1 < array.len() { ",\n" } else { "\n" });
+            }
+            let _ = write!(out, "{}]", "  ".repeat(indent));
+        }
+        other => out.push_str(&json::to_string(other)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::process;
+
+    /// Loads `file`, written to a temporary path if given, with `vars` as the environment
+    fn load(name: &str, file: Option<&str>, vars: &[(&str, &str)]) -> Result<Config, Error> {
+        let vars: HashMap<_, _> = vars.iter().copied().collect();
+        let env = |var: &str| vars.get(var).map(|value| (*value).to_string());
+        let Some(content) = file else {
+            return Config::load_with(None, env);
+        };
+
+        let path = env::temp_dir().join(format!("tldr-config-{}-{name}.json", process::id()));
+        fs::write(&path, content).expect("write the config file");
+        let config = Config::load_with(Some(&path), env);
+        let _ = fs::remove_file(&path);
+        config
+    }
+
+    fn invalid_setting(result: Result<Config, Error>) -> String {
+        match result {
+            Err(Error::Invalid { setting, .. }) => setting,
+            Err(e) => panic!("expected an invalid setting, got {e}"),
+            Ok(_) => panic!("expected an invalid setting, got a config"),
+        }
+    }
+
+    #[test]
+    fn environment_overrides_the_file() {
+        let config = load(
+            "precedence",
+            Some(r#"{"server": {"port": 9000, "workers": 3}, "youtube": {"default_language": "de"}}"#),
+            &[("TLDR_PORT", "9100"), ("TLDR_DEFAULT_LANGUAGE", "fr")],
+        )
+        .expect("a valid config");
+
+        assert_eq!(config.server.port, 9100);
+        assert_eq!(config.server.workers, 3);
+        assert_eq!(config.youtube.default_language, "fr");
+        assert_eq!(config.server.ip, Config::default().server.ip);
+    }
+
+    #[test]
+    fn names_the_variable_that_is_malformed() {
+        let result = load("malformed", None, &[("TLDR_WORKERS", "four")]);
+        let Err(Error::Invalid { setting, reason }) = result else {
+            panic!("expected TLDR_WORKERS to be rejected");
+        };
+        assert_eq!(setting, "TLDR_WORKERS");
+        assert_eq!(reason, "expected a whole number, got \"four\"");
+
+        let result = load("out-of-range", None, &[("TLDR_PORT", "70000")]);
+        assert_eq!(invalid_setting(result), "TLDR_PORT");
+    }
+
+    #[test]
+    fn rejects_unknown_and_mistyped_file_settings() {
+        assert!(matches!(
+            load("unknown", Some(r#"{"server": {"prot": 80}}"#), &[]),
+            Err(Error::UnknownSetting(_))
+        ));
+        assert!(matches!(
+            load("not-json", Some("{"), &[]),
+            Err(Error::Parse(_))
+        ));
+        assert!(
+            invalid_setting(load("mistyped", Some(r#"{"server": {"port": "80"}}"#), &[]))
+                .starts_with("server.port in ")
+        );
+    }
+
+    #[test]
+    fn validates_the_combined_settings() {
+        for (vars, setting) in [
+            (
+                &[("TLDR_WORKERS", "0")][..],
+                "server.workers (TLDR_WORKERS)",
+            ),
+            (&[("TLDR_IP", "not a host")], "server.ip (TLDR_IP)"),
+            (&[("TLDR_IP", "-bad.example")], "server.ip (TLDR_IP)"),
+            (
+                &[("TLDR_TLS_CERT", "cert.pem")],
+                "server.tls_key (TLDR_TLS_KEY)",
+            ),
+            (
+                &[
+                    ("TLDR_CORS_ORIGINS", "*"),
+                    ("TLDR_CORS_CREDENTIALS", "true"),
+                ],
+                "server.cors_credentials (TLDR_CORS_CREDENTIALS)",
+            ),
+            (
+                &[("TLDR_LOG_LEVEL", "loud")],
+                "server.log_level (TLDR_LOG_LEVEL)",
+            ),
+            (
+                &[("TLDR_WEBHOOK_URL", "ftp://x")],
+                "webhook.url (TLDR_WEBHOOK_URL)",
+            ),
+            (
+                &[("TLDR_SUBSCRIPTIONS", "UC123")],
+                "subscriptions.channels (TLDR_SUBSCRIPTIONS)",
+            ),
+            (
+                &[
+                    ("TLDR_SUBSCRIPTIONS", "UC123"),
+                    ("TLDR_HISTORY_FILE", "h.jsonl"),
+                ],
+                "subscriptions.api_key (TLDR_SUBSCRIPTIONS_API_KEY)",
+            ),
+            (
+                &[("TLDR_SUBSCRIPTION_INTERVAL", "10081")],
+                "subscriptions.interval_minutes (TLDR_SUBSCRIPTION_INTERVAL)",
+            ),
+        ] {
+            assert_eq!(
+                invalid_setting(load("invalid", None, vars)),
+                setting,
+                "{vars:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn binds_to_addresses_and_hostnames() {
+        for (ip, addr) in [
+            ("127.0.0.1", "127.0.0.1:8000"),
+            ("::1", "[::1]:8000"),
+            ("localhost", "localhost:8000"),
+            ("tldr.internal", "tldr.internal:8000"),
+        ] {
+            let config = load("ip", None, &[("TLDR_IP", ip), ("TLDR_PORT", "8000")])
+                .expect("a valid address");
+            assert_eq!(config.server.addr(), addr);
+        }
+    }
+}
@@ -0,0 +1,101 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Cookies loaded from a Netscape `cookies.txt` export, as produced by browser extensions and yt-dlp.
+pub struct CookieJar {
+    cookies: Vec<(String, String)>,
+}
+
+impl CookieJar {
+    /// Loads all unexpired cookies set for `domain` or its subdomains.
+    pub fn load(path: &Path, domain: &str) -> io::Result<Self> {
+        let contents = fs::read_to_string(path)?;
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+
+        let mut cookies = Vec::new();
+        for (line_number, line) in contents.lines().enumerate() {
+            // HttpOnly cookies are written as comments with this prefix
+            let line = line.strip_prefix("#HttpOnly_").unwrap_or(line).trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let fields: Vec<&str> = line.split('\t').collect();
+            let [cookie_domain, _, _, _, expires, name, value] = fields[..] else {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "Malformed cookie on line {}: expected 7 tab-separated fields",
+                        line_number + 1
+                    ),
+                ));
+            };
+
+            let expires: u64 = expires.parse().unwrap_or(0);
+            if (expires != 0 && expires < now) || !matches_domain(cookie_domain, domain) {
+                continue;
+            }
+
+            cookies.push((name.to_string(), value.to_string()));
+        }
+
+        Ok(Self { cookies })
+    }
+
+    pub const fn len(&self) -> usize {
+        self.cookies.len()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.cookies
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn header(&self) -> String {
+        self.cookies
+            .iter()
+            .map(|(name, value)| format!("{name}={value}"))
+            .collect::<Vec<_>>()
+            .join("; ")
+    }
+}
+
+/// Whether a cookie for `cookie_domain` belongs to `domain`. A plain suffix match would also let
+/// in lookalikes like `evilyoutube.com`.
+fn matches_domain(cookie_domain: &str, domain: &str) -> bool {
+    let cookie_domain = cookie_domain
+        .strip_prefix('.')
+        .unwrap_or(cookie_domain)
+        .as_bytes();
+    let domain = domain.as_bytes();
+    match cookie_domain.len().checked_sub(domain.len()) {
+        Some(0) => cookie_domain.eq_ignore_ascii_case(domain),
+        Some(split) => {
+            let (subdomain, rest) = cookie_domain.split_at(split);
+            subdomain.ends_with(b".") && rest.eq_ignore_ascii_case(domain)
+        }
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::matches_domain;
+
+    #[test]
+    fn matches_domain_and_subdomains_only() {
+        assert!(matches_domain(".youtube.com", "youtube.com"));
+        assert!(matches_domain("youtube.com", "youtube.com"));
+        assert!(matches_domain("www.youtube.com", "youtube.com"));
+        assert!(matches_domain(".M.YouTube.com", "youtube.com"));
+        assert!(!matches_domain("evilyoutube.com", "youtube.com"));
+        assert!(!matches_domain(".evilyoutube.com", "youtube.com"));
+        assert!(!matches_domain("youtube.com.evil.net", "youtube.com"));
+    }
+}
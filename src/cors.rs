@@ -0,0 +1,76 @@
+use crate::error::Error;
+use crate::http::{Request, Response};
+use crate::router::Next;
+use std::sync::OnceLock;
+
+const ALLOWED_METHODS: &str = "GET, POST, DELETE, HEAD";
+const DEFAULT_ALLOWED_HEADERS: &str = "Content-Type";
+const PREFLIGHT_MAX_AGE_SECS: u32 = 600;
+
+pub struct CorsConfig {
+    /// Exact origins like `https://www.youtube.com`, or `*` for any
+    pub origins: Vec<String>,
+    /// Lets browsers send cookies and read the response. Never combined with a literal `*`.
+    pub credentials: bool,
+}
+
+static CONFIG: OnceLock<CorsConfig> = OnceLock::new();
+
+/// Sets the allowed origins. Without this call no CORS headers are sent, so only same-origin pages can use the API.
+pub fn configure(config: CorsConfig) {
+    let _ = CONFIG.set(config);
+}
+
+/// Answers preflight requests and adds CORS headers to every response for allowed origins, error responses included
+pub fn middleware(request: &Request, next: Next) -> Result<Response, Error> {
+    let Some((config, origin)) = CONFIG
+        .get()
+        .zip(request.header("origin"))
+        .filter(|(config, origin)| is_allowed(config, origin))
+    else {
+        return next(request);
+    };
+
+    let is_preflight =
+        request.method == "OPTIONS" && request.header("access-control-request-method").is_some();
+
+    let response = if is_preflight {
+        Response::new("204 No Content", "text/plain", Vec::new())
+            .with_header("Access-Control-Allow-Methods", ALLOWED_METHODS)
+            .with_header(
+                "Access-Control-Allow-Headers",
+                request
+                    .header("access-control-request-headers")
+                    .unwrap_or(DEFAULT_ALLOWED_HEADERS),
+            )
+            .with_header("Access-Control-Max-Age", PREFLIGHT_MAX_AGE_SECS.to_string())
+    } else {
+        next(request).unwrap_or_else(|e| Response::error(&e))
+    };
+
+    Ok(with_cors_headers(response, config, origin))
+}
+
+fn is_allowed(config: &CorsConfig, origin: &str) -> bool {
+    config.origins.iter().any(|o| o == "*" || o == origin)
+}
+
+fn with_cors_headers(response: Response, config: &CorsConfig, origin: &str) -> Response {
+    let any_origin = config.origins.iter().any(|o| o == "*");
+
+    // Config validation rules out `*` with credentials, which would trust every site with cookies
+    if any_origin {
+        return response.with_header("Access-Control-Allow-Origin", "*");
+    }
+
+    // Echoing the origin makes the response differ per origin, so caches must key on it
+    let response = response
+        .with_header("Access-Control-Allow-Origin", origin)
+        .with_header("Vary", "Origin");
+
+    if config.credentials {
+        response.with_header("Access-Control-Allow-Credentials", "true")
+    } else {
+        response
+    }
+}
@@ -0,0 +1,49 @@
+use std::cell::Cell;
+use std::time::Instant;
+
+thread_local! {
+    /// When the work running on this thread has to be done by, like the request being served
+    static DEADLINE: Cell<Option<Instant>> = const { Cell::new(None) };
+}
+
+/// Runs `f` with everything it does having to finish by `deadline`, or by the current deadline
+/// if that's sooner. `None` adds no limit of its own.
+pub fn until<T>(deadline: Option<Instant>, f: impl FnOnce() -> T) -> T {
+    struct Guard(Option<Instant>);
+    impl Drop for Guard {
+        fn drop(&mut self) {
+            DEADLINE.set(self.0);
+        }
+    }
+
+    let outer = DEADLINE.get();
+    let _guard = Guard(outer);
+    DEADLINE.set(match (outer, deadline) {
+        (Some(outer), Some(deadline)) => Some(outer.min(deadline)),
+        (outer, deadline) => outer.or(deadline),
+    });
+    f()
+}
+
+/// The deadline of this thread, to carry over to threads it starts
+pub fn current() -> Option<Instant> {
+    DEADLINE.get()
+}
+
+pub fn expired() -> bool {
+    current().is_some_and(|deadline| deadline <= Instant::now())
+}
+
+/// The timeout in seconds for one outgoing request: `stage_secs`, cut short by the deadline.
+/// `None` once the deadline has passed.
+pub fn timeout_secs(stage_secs: u64) -> Option<u64> {
+    let Some(deadline) = current() else {
+        return Some(stage_secs);
+    };
+    let left = deadline.checked_duration_since(Instant::now())?;
+    if left.is_zero() {
+        return None;
+    }
+    // Requests take whole seconds, and rounding down would leave zero for the last one
+    Some(stage_secs.min(left.as_secs() + u64::from(left.subsec_nanos() > 0)))
+}
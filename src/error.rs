@@ -0,0 +1,294 @@
+use crate::{gemini, log, subtitle, zip};
+use miniserde::{Serialize, json};
+use std::fmt;
+use std::io;
+
+/// Every way a request can fail, with the HTTP status and stable machine-readable code it maps to
+#[derive(Debug)]
+pub enum Error {
+    BadRequest(String),
+    InvalidJson(String),
+    PayloadTooLarge,
+    NotFound,
+    HistoryDisabled,
+    PermalinksDisabled,
+    /// The export would be more than one zip file can or should hold
+    ExportTooLarge,
+    /// A route for the admin didn't come with the admin token
+    Unauthorized,
+    AdminTokenNotSet,
+    /// Carries the methods the path does support, for the `Allow` header
+    MethodNotAllowed(Vec<&'static str>),
+    MissingApiKey,
+    MissingField(&'static str),
+    NotLive,
+    TooManyJobs,
+    /// Every worker is busy and the queue is full
+    Overloaded,
+    Transcript(subtitle::Error),
+    Gemini(gemini::Error),
+    Io(io::Error),
+}
+
+#[derive(Serialize)]
+struct ErrorBody<'a> {
+    error: String,
+    code: &'a str,
+}
+
+impl Error {
+    pub fn status(&self) -> &'static str {
+        match self.status_code() {
+            400 => "400 Bad Request",
+            401 => "401 Unauthorized",
+            403 => "403 Forbidden",
+            404 => "404 Not Found",
+            405 => "405 Method Not Allowed",
+            413 => "413 Payload Too Large",
+            422 => "422 Unprocessable Entity",
+            429 => "429 Too Many Requests",
+            502 => "502 Bad Gateway",
+            503 => "503 Service Unavailable",
+            504 => "504 Gateway Timeout",
+            _ => "500 Internal Server Error",
+        }
+    }
+
+    pub fn status_code(&self) -> u16 {
+        self.classify().0
+    }
+
+    pub fn code(&self) -> &'static str {
+        self.classify().1
+    }
+
+    fn classify(&self) -> (u16, &'static str) {
+        match self {
+            Self::BadRequest(_) => (400, "bad_request"),
+            Self::InvalidJson(_) => (400, "invalid_json"),
+            Self::PayloadTooLarge => (413, "payload_too_large"),
+            Self::NotFound => (404, "not_found"),
+            Self::HistoryDisabled => (404, "history_disabled"),
+            Self::PermalinksDisabled => (404, "permalinks_disabled"),
+            Self::ExportTooLarge => (422, "export_too_large"),
+            Self::Unauthorized => (401, "unauthorized"),
+            Self::AdminTokenNotSet => (403, "admin_token_not_set"),
+            Self::MethodNotAllowed(_) => (405, "method_not_allowed"),
+            Self::MissingApiKey => (401, "missing_api_key"),
+            Self::MissingField(_) => (422, "missing_field"),
+            Self::NotLive => (422, "video_not_live"),
+            Self::TooManyJobs => (429, "too_many_jobs"),
+            Self::Overloaded => (503, "overloaded"),
+            Self::Transcript(e) => match e {
+                subtitle::Error::InvalidUrl(_) => (400, "invalid_url"),
+                subtitle::Error::Request(e) if is_timeout(e) => (504, "youtube_timeout"),
+                subtitle::Error::DeadlineExceeded => (504, "deadline_exceeded"),
+                subtitle::Error::Status(429) => (429, "youtube_rate_limited"),
+                subtitle::Error::ConsentRequired => (502, "youtube_consent_required"),
+                subtitle::Error::LoginRequired(_) => (422, "video_login_required"),
+                subtitle::Error::AgeRestricted => (422, "video_age_restricted"),
+                subtitle::Error::Private => (422, "video_private"),
+                subtitle::Error::RegionBlocked(_) => (422, "video_region_blocked"),
+                subtitle::Error::Unplayable(_) => (422, "video_unplayable"),
+                subtitle::Error::Upcoming { .. } => (422, "video_upcoming"),
+                subtitle::Error::LiveInProgress => (422, "video_live"),
+                subtitle::Error::Unavailable(_) | subtitle::Error::VideoDetailsMissing => {
+                    (404, "video_not_found")
+                }
+                subtitle::Error::NoCaptions(_) => (422, "no_captions"),
+                subtitle::Error::LanguageUnavailable { .. } => (422, "language_unavailable"),
+                subtitle::Error::Cookies(_) => (500, "internal_error"),
+                subtitle::Error::Request(_)
+                | subtitle::Error::Status(_)
+                | subtitle::Error::Json(_)
+                | subtitle::Error::PlayerConfig(_) => (502, "youtube_error"),
+            },
+            Self::Gemini(e) => match e {
+                gemini::Error::Request(e) if is_timeout(e) => (504, "llm_timeout"),
+                gemini::Error::DeadlineExceeded => (504, "deadline_exceeded"),
+                gemini::Error::Api { status: 401 | 403, .. } => (401, "invalid_api_key"),
+                gemini::Error::Api { status: 400, body } if is_invalid_key(body) => {
+                    (401, "invalid_api_key")
+                }
+                gemini::Error::Api { status: 404, .. } => (404, "model_not_found"),
+                gemini::Error::Api { status: 429, .. } => (429, "llm_rate_limited"),
+                gemini::Error::Request(_)
+                | gemini::Error::Api { .. }
+                | gemini::Error::Json(_)
+                | gemini::Error::NoTextInResponse => (502, "llm_error"),
+            },
+            Self::Io(_) => (500, "internal_error"),
+        }
+    }
+
+    pub fn to_json(&self) -> Vec<u8> {
+        json::to_vec(&ErrorBody {
+            // Transport errors can carry the request URL, which has the API key in it for Gemini
+            error: log::redact(&self.to_string()).into_owned(),
+            code: self.code(),
+        })
+    }
+}
+
+fn is_timeout(e: &minreq::Error) -> bool {
+    matches!(e, minreq::Error::IoError(io) if matches!(io.kind(), io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock))
+}
+
+fn is_invalid_key(body: &str) -> bool {
+    body.contains("API_KEY_INVALID")
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::BadRequest(msg) => write!(f, "Bad request: {msg}"),
+            Self::InvalidJson(msg) => write!(f, "Invalid JSON: {msg}"),
+            Self::PayloadTooLarge => write!(f, "Request body too large"),
+            Self::NotFound => write!(f, "Not Found"),
+            Self::HistoryDisabled => write!(f, "Server-side history is disabled on this server"),
+            Self::PermalinksDisabled => write!(f, "Sharing summaries is disabled on this server"),
+            Self::ExportTooLarge => write!(
+                f,
+                "Too large to export as one file, export fewer summaries at a time"
+            ),
+            Self::Unauthorized => write!(f, "Missing or wrong admin token"),
+            Self::AdminTokenNotSet => write!(
+                f,
+                "The history API and feeds need an admin token, set TLDR_ADMIN_TOKEN on the server"
+            ),
+            Self::MethodNotAllowed(_) => write!(f, "Method Not Allowed"),
+            Self::MissingApiKey => write!(
+                f,
+                "Missing Gemini API key. Get one here: https://aistudio.google.com/app/apikey"
+            ),
+            Self::MissingField(field) => write!(f, "Missing {field}"),
+            Self::NotLive => write!(
+                f,
+                "This video is not a live stream in progress, summarize it normally instead"
+            ),
+            Self::TooManyJobs => write!(f, "Too many live summaries are running, stop one first"),
+            Self::Overloaded => write!(f, "The server is busy, try again in a moment"),
+            Self::Transcript(e) => write!(f, "Transcript error: {e}"),
+            Self::Gemini(e) => write!(f, "API error: {e}"),
+            Self::Io(e) => write!(f, "I/O error: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Transcript(e) => Some(e),
+            Self::Gemini(e) => Some(e),
+            Self::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<subtitle::Error> for Error {
+    fn from(e: subtitle::Error) -> Self {
+        Self::Transcript(e)
+    }
+}
+
+impl From<gemini::Error> for Error {
+    fn from(e: gemini::Error) -> Self {
+        Self::Gemini(e)
+    }
+}
+
+impl From<zip::TooLarge> for Error {
+    fn from(_: zip::TooLarge) -> Self {
+        Self::ExportTooLarge
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn io_error(kind: io::ErrorKind) -> minreq::Error {
+        minreq::Error::IoError(io::Error::from(kind))
+    }
+
+    fn api(status: u16, body: &str) -> Error {
+        Error::Gemini(gemini::Error::Api { status, body: body.into() })
+    }
+
+    #[test]
+    fn every_variant_has_a_status_and_code() {
+        let cases = [
+            (Error::BadRequest("x".into()), 400, "bad_request"),
+            (Error::InvalidJson("x".into()), 400, "invalid_json"),
+            (Error::PayloadTooLarge, 413, "payload_too_large"),
+            (Error::NotFound, 404, "not_found"),
+            (Error::HistoryDisabled, 404, "history_disabled"),
+            (Error::PermalinksDisabled, 404, "permalinks_disabled"),
+            (Error::ExportTooLarge, 422, "export_too_large"),
+            (Error::Unauthorized, 401, "unauthorized"),
+            (Error::AdminTokenNotSet, 403, "admin_token_not_set"),
+            (Error::MethodNotAllowed(vec!["GET"]), 405, "method_not_allowed"),
+            (Error::MissingApiKey, 401, "missing_api_key"),
+            (Error::MissingField("url"), 422, "missing_field"),
+            (Error::NotLive, 422, "video_not_live"),
+            (Error::TooManyJobs, 429, "too_many_jobs"),
+            (Error::Overloaded, 503, "overloaded"),
+            (Error::Io(io::Error::other("x")), 500, "internal_error"),
+        ];
+        let transcript = [
+            (subtitle::Error::InvalidUrl("x".into()), 400, "invalid_url"),
+            (subtitle::Error::Request(io_error(io::ErrorKind::TimedOut)), 504, "youtube_timeout"),
+            (subtitle::Error::Request(io_error(io::ErrorKind::WouldBlock)), 504, "youtube_timeout"),
+            (subtitle::Error::Request(io_error(io::ErrorKind::ConnectionRefused)), 502, "youtube_error"),
+            (subtitle::Error::DeadlineExceeded, 504, "deadline_exceeded"),
+            (subtitle::Error::Status(429), 429, "youtube_rate_limited"),
+            (subtitle::Error::Status(500), 502, "youtube_error"),
+            (subtitle::Error::Json(miniserde::Error), 502, "youtube_error"),
+            (subtitle::Error::PlayerConfig("x"), 502, "youtube_error"),
+            (subtitle::Error::Cookies(io::Error::other("x")), 500, "internal_error"),
+            (subtitle::Error::ConsentRequired, 502, "youtube_consent_required"),
+            (subtitle::Error::LoginRequired("x".into()), 422, "video_login_required"),
+            (subtitle::Error::AgeRestricted, 422, "video_age_restricted"),
+            (subtitle::Error::Private, 422, "video_private"),
+            (subtitle::Error::RegionBlocked("x".into()), 422, "video_region_blocked"),
+            (subtitle::Error::Unplayable("x".into()), 422, "video_unplayable"),
+            (subtitle::Error::Unavailable("x".into()), 404, "video_not_found"),
+            (subtitle::Error::Upcoming { reason: "x".into(), scheduled_start: None }, 422, "video_upcoming"),
+            (subtitle::Error::LiveInProgress, 422, "video_live"),
+            (subtitle::Error::VideoDetailsMissing, 404, "video_not_found"),
+            (subtitle::Error::NoCaptions("x".into()), 422, "no_captions"),
+            (
+                subtitle::Error::LanguageUnavailable { language: "de".into(), available: vec!["en".into()] },
+                422,
+                "language_unavailable",
+            ),
+        ]
+        .map(|(e, status, code)| (Error::Transcript(e), status, code));
+        let llm = [
+            (Error::Gemini(gemini::Error::Request(io_error(io::ErrorKind::TimedOut))), 504, "llm_timeout"),
+            (Error::Gemini(gemini::Error::Request(io_error(io::ErrorKind::ConnectionReset))), 502, "llm_error"),
+            (Error::Gemini(gemini::Error::DeadlineExceeded), 504, "deadline_exceeded"),
+            (api(401, ""), 401, "invalid_api_key"),
+            (api(403, ""), 401, "invalid_api_key"),
+            (api(400, r#"{"reason": "API_KEY_INVALID"}"#), 401, "invalid_api_key"),
+            (api(400, "bad prompt"), 502, "llm_error"),
+            (api(404, ""), 404, "model_not_found"),
+            (api(429, ""), 429, "llm_rate_limited"),
+            (api(500, ""), 502, "llm_error"),
+            (Error::Gemini(gemini::Error::Json(miniserde::Error)), 502, "llm_error"),
+            (Error::Gemini(gemini::Error::NoTextInResponse), 502, "llm_error"),
+        ];
+
+        for (error, status, code) in cases.into_iter().chain(transcript).chain(llm) {
+            assert_eq!((error.status_code(), error.code()), (status, code), "{error:?}");
+            assert!(error.status().starts_with(&status.to_string()), "{error:?}");
+        }
+    }
+}
@@ -0,0 +1,1065 @@
+use crate::config::{self, Config};
+use crate::error::Error;
+use crate::http::{self, Connection, Parsed, Request, Response};
+use crate::router::Router;
+#[cfg(any(feature = "native-tls", feature = "rustls-tls"))]
+use crate::tls;
+use crate::{deadline, digest, log, metrics, shutdown};
+use flume::{Receiver, Sender, TrySendError};
+use std::io::{self, Read, Write};
+use std::net::{Ipv4Addr, SocketAddr, TcpListener, TcpStream};
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const READ_CHUNK_SIZE: usize = 16 * 1024;
+const MAX_POLL_WAIT: Duration = Duration::from_secs(1);
+// Running out of file descriptors makes every accept fail right away, so back off instead of spinning
+const ACCEPT_ERROR_BACKOFF: Duration = Duration::from_millis(100);
+
+/// A parsed request waiting for a worker thread, with what's needed to send the response back
+pub struct Job {
+    token: usize,
+    request: Request,
+    request_id: String,
+    keep_alive: bool,
+    received: Instant,
+}
+
+impl Job {
+    /// Runs the request through the router and serializes the response
+    pub fn run(self, router: &Router) -> Done {
+        // Counted from when the request came in, so time spent queued for a worker counts too
+        let deadline =
+            self.received + Duration::from_secs(config::get().server.request_timeout_secs);
+        let response = deadline::until(Some(deadline), || {
+            log::scope("request_id", &self.request_id, || {
+                router.handle(&self.request)
+            })
+        });
+        let route = router.pattern(&self.request);
+        self.respond(response, route)
+    }
+
+    fn respond(self, response: Response, route: &'static str) -> Done {
+        // A shutdown may have started while the request was with a worker
+        let keep_alive = self.keep_alive && !shutdown::requested();
+        let response = response.with_header("X-Request-Id", self.request_id.clone());
+        metrics::record_request(route, response.status);
+
+        log::info!(
+            "Request";
+            request_id = self.request_id,
+            method = self.request.method,
+            path = self.request.target,
+            status = response.status.split(' ').next().unwrap_or_default(),
+            duration_ms = self.received.elapsed().as_millis(),
+            peer = self.request.peer,
+        );
+
+        let mut output = Vec::new();
+        // Writing to a Vec can't fail
+        let _ = http::write_response(
+            &mut output,
+            &response,
+            self.request.method == "HEAD",
+            keep_alive,
+        );
+
+        Done {
+            token: self.token,
+            output,
+            keep_alive,
+        }
+    }
+}
+
+/// A serialized response on its way back to the event loop
+pub struct Done {
+    token: usize,
+    output: Vec<u8>,
+    keep_alive: bool,
+}
+
+/// Hands finished jobs back to the event loop and wakes it up. Every worker holds a clone.
+#[derive(Clone)]
+pub struct Completions {
+    sender: Sender<Done>,
+    waker: Arc<TcpStream>,
+}
+
+impl Completions {
+    pub fn send(&self, done: Done) {
+        if self.sender.send(done).is_ok() {
+            // Fails when the socket buffer is full, which means a wake-up is pending anyway
+            let _ = (&*self.waker).write(&[1]);
+        }
+    }
+}
+
+enum State {
+    /// Waiting for a request, or the rest of one
+    Reading,
+    /// A worker is handling the request
+    Dispatched,
+    /// Sending a response, then reading the next request if the connection is kept alive
+    Writing,
+}
+
+struct Client {
+    stream: Box<dyn Connection>,
+    peer: SocketAddr,
+    state: State,
+    input: Vec<u8>,
+    output: Vec<u8>,
+    written: usize,
+    keep_alive: bool,
+    continue_sent: bool,
+    served: usize,
+    last_active: Instant,
+}
+
+impl Client {
+    fn interest(&self) -> sys::Events {
+        let mut events = 0;
+        if matches!(self.state, State::Reading) {
+            events |= sys::READABLE;
+        }
+        if !self.is_flushed() {
+            events |= sys::WRITABLE;
+        }
+        events
+    }
+
+    /// When the connection gets closed unless something happens. Requests with a worker never time out here.
+    fn deadline(&self, server: &config::Server) -> Option<Instant> {
+        let timeout = match self.state {
+            State::Dispatched => return None,
+            State::Writing => server.write_timeout(),
+            State::Reading if self.input.is_empty() && self.served > 0 => {
+                server.keep_alive_timeout().min(server.read_timeout())
+            }
+            State::Reading => server.read_timeout(),
+        };
+        Some(self.last_active + timeout)
+    }
+
+    /// Reads whatever has arrived, up to `limit` buffered bytes. Returns whether the client closed its side.
+    fn fill(&mut self, limit: usize) -> io::Result<bool> {
+        let mut chunk = [0; READ_CHUNK_SIZE];
+
+        while self.input.len() < limit {
+            match self.stream.read(&mut chunk) {
+                Ok(0) => return Ok(true),
+                Ok(read) => {
+                    self.input.extend_from_slice(&chunk[..read]);
+                    self.last_active = Instant::now();
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(false)
+    }
+
+    /// Sends as much of the pending output as the socket takes
+    fn flush(&mut self) -> io::Result<()> {
+        while self.written < self.output.len() {
+            match self.stream.write(&self.output[self.written..]) {
+                Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+                Ok(written) => {
+                    self.written += written;
+                    self.last_active = Instant::now();
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        self.output.clear();
+        self.written = 0;
+        match self.stream.flush() {
+            Err(e) if e.kind() != io::ErrorKind::WouldBlock => Err(e),
+            _ => Ok(()),
+        }
+    }
+
+    fn is_flushed(&self) -> bool {
+        self.written == self.output.len() && !self.stream.wants_write()
+    }
+
+    fn is_idle(&self) -> bool {
+        matches!(self.state, State::Reading) && self.input.is_empty() && self.is_flushed()
+    }
+}
+
+/// Owns every client connection and does all their network I/O on one thread with non-blocking sockets,
+/// so slow clients cost a little memory rather than a thread. Handlers that wait on the network, like
+/// fetching transcripts or calling the LLM, are queued for the worker threads. Everything else is answered here.
+pub struct EventLoop {
+    listener: Option<TcpListener>,
+    accept_paused_until: Option<Instant>,
+    config: &'static Config,
+    router: Arc<Router>,
+    jobs: Sender<Job>,
+    done: Receiver<Done>,
+    completions: Completions,
+    wake_receiver: TcpStream,
+    clients: Vec<Option<Client>>,
+    free_tokens: Vec<usize>,
+}
+
+impl EventLoop {
+    pub fn new(
+        listener: TcpListener,
+        config: &'static Config,
+        router: Arc<Router>,
+        jobs: Sender<Job>,
+    ) -> io::Result<Self> {
+        listener.set_nonblocking(true)?;
+        let (wake_sender, wake_receiver) = wake_pair()?;
+        let (sender, done) = flume::unbounded();
+
+        Ok(Self {
+            listener: Some(listener),
+            accept_paused_until: None,
+            config,
+            router,
+            jobs,
+            done,
+            completions: Completions {
+                sender,
+                waker: Arc::new(wake_sender),
+            },
+            wake_receiver,
+            clients: Vec::new(),
+            free_tokens: Vec::new(),
+        })
+    }
+
+    pub fn completions(&self) -> Completions {
+        self.completions.clone()
+    }
+
+    /// Serves connections until a shutdown is requested
+    pub fn run(&mut self) {
+        while !shutdown::requested() {
+            self.turn();
+        }
+    }
+
+    /// Stops accepting connections, closes idle ones and keeps serving the rest until they're done
+    /// or `deadline` passes. Returns how many were still open.
+    pub fn drain(mut self, deadline: Instant) -> usize {
+        self.listener = None;
+
+        let idle: Vec<usize> = self
+            .tokens()
+            .filter(|&t| self.client(t).is_some_and(Client::is_idle))
+            .collect();
+        for token in idle {
+            self.close(token);
+        }
+
+        while self.open_connections() > 0 && Instant::now() < deadline {
+            self.turn();
+        }
+        self.open_connections()
+    }
+
+    const fn open_connections(&self) -> usize {
+        self.clients.len() - self.free_tokens.len()
+    }
+
+    fn tokens(&self) -> impl Iterator<Item = usize> + use<> {
+        0..self.clients.len()
+    }
+
+    fn client(&self, token: usize) -> Option<&Client> {
+        self.clients.get(token).and_then(Option::as_ref)
+    }
+
+    fn client_mut(&mut self, token: usize) -> Option<&mut Client> {
+        self.clients.get_mut(token).and_then(Option::as_mut)
+    }
+
+    /// Waits for something to happen and handles it
+    fn turn(&mut self) {
+        let now = Instant::now();
+        let accepting =
+            self.listener.is_some() && self.accept_paused_until.is_none_or(|t| now >= t);
+
+        let mut fds = vec![sys::PollFd::new(&self.wake_receiver, sys::READABLE)];
+        if let Some(listener) = self.listener.as_ref().filter(|_| accepting) {
+            fds.push(sys::PollFd::new(listener, sys::READABLE));
+        }
+        let first_client = fds.len();
+
+        let mut tokens = Vec::new();
+        let mut timeout = MAX_POLL_WAIT;
+        for (token, client) in self.clients.iter().enumerate() {
+            let Some(client) = client else {
+                continue;
+            };
+            if let Some(deadline) = client.deadline(&self.config.server) {
+                timeout = timeout.min(deadline.saturating_duration_since(now));
+            }
+            let events = client.interest();
+            if events != 0 {
+                fds.push(sys::PollFd::new(client.stream.tcp(), events));
+                tokens.push(token);
+            }
+        }
+
+        if let Err(e) = sys::poll(&mut fds, timeout) {
+            log::error!("Polling connections failed"; error = e);
+            thread::sleep(ACCEPT_ERROR_BACKOFF);
+            return;
+        }
+
+        if fds[0].is_ready() {
+            let mut buf = [0; 64];
+            while (&self.wake_receiver)
+                .read(&mut buf)
+                .is_ok_and(|read| read > 0)
+            {}
+        }
+        while let Ok(done) = self.done.try_recv() {
+            let token = done.token;
+            self.finish(done);
+            self.drive(token);
+        }
+
+        if accepting && fds[1].is_ready() {
+            self.accept();
+        }
+
+        for (fd, token) in fds[first_client..].iter().zip(tokens) {
+            if fd.is_ready() {
+                self.drive(token);
+            }
+        }
+
+        self.close_expired();
+    }
+
+    fn accept(&mut self) {
+        let Some(listener) = &self.listener else {
+            return;
+        };
+
+        let mut accepted = Vec::new();
+        loop {
+            match listener.accept() {
+                Ok(connection) => accepted.push(connection),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => {
+                    log::error!("Accept failed"; error = e);
+                    self.accept_paused_until = Some(Instant::now() + ACCEPT_ERROR_BACKOFF);
+                    break;
+                }
+            }
+        }
+
+        for (stream, peer) in accepted {
+            self.add_client(stream, peer);
+        }
+    }
+
+    fn add_client(&mut self, stream: TcpStream, peer: SocketAddr) {
+        if let Err(e) = stream.set_nonblocking(true) {
+            log::warn!("Connection error"; peer = peer, error = e);
+            return;
+        }
+        let _ = stream.set_nodelay(true);
+
+        if self.open_connections() >= self.config.limits.max_connections {
+            metrics::REJECTED.fetch_add(1, Ordering::Relaxed);
+            log::warn!("Too many open connections, rejecting"; peer = peer);
+            // Best effort, there's no waiting for the socket here. TLS clients only see the connection close.
+            if !tls_enabled() {
+                let mut output = Vec::new();
+                let _ = http::write_response(
+                    &mut output,
+                    &Response::error(&Error::Overloaded),
+                    false,
+                    false,
+                );
+                let _ = (&stream).write(&output);
+            }
+            return;
+        }
+
+        #[cfg(any(feature = "native-tls", feature = "rustls-tls"))]
+        let stream: Box<dyn Connection> = if tls::is_enabled() {
+            match tls::accept(stream) {
+                Ok(stream) => Box::new(stream),
+                Err(e) => {
+                    log::warn!("Connection error"; peer = peer, error = e);
+                    return;
+                }
+            }
+        } else {
+            Box::new(stream)
+        };
+        #[cfg(not(any(feature = "native-tls", feature = "rustls-tls")))]
+        let stream: Box<dyn Connection> = Box::new(stream);
+
+        let client = Client {
+            stream,
+            peer,
+            state: State::Reading,
+            input: Vec::new(),
+            output: Vec::new(),
+            written: 0,
+            keep_alive: false,
+            continue_sent: false,
+            served: 0,
+            last_active: Instant::now(),
+        };
+
+        if let Some(token) = self.free_tokens.pop() {
+            self.clients[token] = Some(client);
+        } else {
+            self.clients.push(Some(client));
+        }
+        metrics::CONNECTIONS.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn close(&mut self, token: usize) {
+        if let Some(slot) = self.clients.get_mut(token)
+            && slot.take().is_some()
+        {
+            self.free_tokens.push(token);
+            metrics::CONNECTIONS.fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+
+    fn close_with_error(&mut self, token: usize, error: &io::Error) {
+        if let Some(client) = self.client(token) {
+            log::warn!("Connection error"; peer = client.peer, error = error);
+        }
+        self.close(token);
+    }
+
+    fn close_expired(&mut self) {
+        let now = Instant::now();
+        let expired: Vec<usize> = self
+            .tokens()
+            .filter(|&token| {
+                self.client(token)
+                    .and_then(|client| client.deadline(&self.config.server))
+                    .is_some_and(|deadline| deadline <= now)
+            })
+            .collect();
+
+        for token in expired {
+            if let Some(client) = self.client(token)
+                && !client.is_idle()
+            {
+                log::debug!("Closing connection that stalled mid-request"; peer = client.peer);
+            }
+            self.close(token);
+        }
+    }
+
+    /// Moves a connection along as far as it can go without waiting: sends pending output, reads,
+    /// and parses and dispatches requests until it needs the socket or a worker.
+    fn drive(&mut self, token: usize) {
+        let max_body_size = self.config.limits.max_body_size;
+        let input_limit = max_body_size.saturating_add(http::MAX_HEAD_SIZE);
+
+        loop {
+            let Some(client) = self.client_mut(token) else {
+                return;
+            };
+
+            if let Err(e) = client.flush() {
+                self.close_with_error(token, &e);
+                return;
+            }
+
+            match client.state {
+                State::Dispatched => return,
+                State::Writing if !client.is_flushed() => return,
+                State::Writing if client.keep_alive && !shutdown::requested() => {
+                    client.state = State::Reading;
+                    client.last_active = Instant::now();
+                }
+                State::Writing => {
+                    self.close(token);
+                    return;
+                }
+                State::Reading => {
+                    let closed = match client.fill(input_limit) {
+                        Ok(closed) => closed,
+                        Err(e) => {
+                            self.close_with_error(token, &e);
+                            return;
+                        }
+                    };
+
+                    let parsed =
+                        match http::parse_request(&client.input, client.peer, max_body_size) {
+                            // Only possible when chunk framing takes up more space than the body itself
+                            Ok(Parsed::Incomplete { .. }) if client.input.len() >= input_limit => {
+                                Err(Error::PayloadTooLarge)
+                            }
+                            parsed => parsed,
+                        };
+
+                    match parsed {
+                        Ok(Parsed::Complete { request, len }) => {
+                            client.input.drain(..len);
+                            client.continue_sent = false;
+                            client.served += 1;
+                            self.dispatch(token, request);
+                        }
+                        Ok(Parsed::Incomplete { .. }) if closed => {
+                            self.close(token);
+                            return;
+                        }
+                        Ok(Parsed::Incomplete { expects_continue }) => {
+                            if !expects_continue || client.continue_sent {
+                                return;
+                            }
+                            client.continue_sent = true;
+                            client.output.extend_from_slice(http::CONTINUE);
+                        }
+                        Err(e) => {
+                            log::warn!("Rejected malformed request"; peer = client.peer, code = e.code(), error = e);
+                            // The rest of the stream can't be trusted after a parse error, so never keep it alive
+                            let _ = http::write_response(
+                                &mut client.output,
+                                &Response::error(&e),
+                                false,
+                                false,
+                            );
+                            client.keep_alive = false;
+                            client.state = State::Writing;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Answers the request right away if its route allows, otherwise queues it for a worker.
+    /// A full queue gets a 503 instead of making the client wait behind it.
+    fn dispatch(&mut self, token: usize, request: Request) {
+        let max_requests = self.config.limits.max_requests_per_connection;
+        let Some(client) = self.client_mut(token) else {
+            return;
+        };
+        client.state = State::Dispatched;
+
+        let job = Job {
+            token,
+            request_id: request_id(&request),
+            keep_alive: request.keep_alive()
+                && client.served < max_requests
+                && !shutdown::requested(),
+            received: Instant::now(),
+            request,
+        };
+
+        if self.router.is_inline(&job.request) {
+            let done = job.run(&self.router);
+            self.finish(done);
+            return;
+        }
+
+        metrics::QUEUED.fetch_add(1, Ordering::Relaxed);
+        if let Err(TrySendError::Full(mut job) | TrySendError::Disconnected(mut job)) =
+            self.jobs.try_send(job)
+        {
+            metrics::QUEUED.fetch_sub(1, Ordering::Relaxed);
+            metrics::REJECTED.fetch_add(1, Ordering::Relaxed);
+            log::warn!("Every worker is busy and the queue is full, rejecting request"; peer = job.request.peer);
+
+            job.keep_alive = false;
+            let route = self.router.pattern(&job.request);
+            let done = job.respond(Response::error(&Error::Overloaded), route);
+            self.finish(done);
+        }
+    }
+
+    fn finish(&mut self, done: Done) {
+        if let Some(client) = self.client_mut(done.token) {
+            client.output.extend(done.output);
+            client.keep_alive = done.keep_alive;
+            client.state = State::Writing;
+        }
+    }
+}
+
+/// Reuses the caller's `X-Request-Id` so logs can be correlated across services, if it looks sane
+fn request_id(request: &Request) -> String {
+    request
+        .header("x-request-id")
+        .filter(|id| {
+            (1..=64).contains(&id.len())
+                && id
+                    .bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+        })
+        .map_or_else(|| digest::random_hex(8), str::to_string)
+}
+
+#[cfg(any(feature = "native-tls", feature = "rustls-tls"))]
+fn tls_enabled() -> bool {
+    tls::is_enabled()
+}
+
+#[cfg(not(any(feature = "native-tls", feature = "rustls-tls")))]
+const fn tls_enabled() -> bool {
+    false
+}
+
+/// A connected pair of loopback sockets. Workers write a byte to one end to interrupt the poll on the other.
+fn wake_pair() -> io::Result<(TcpStream, TcpStream)> {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))?;
+    let sender = TcpStream::connect(listener.local_addr()?)?;
+
+    // Anything else on the machine could connect first, so make sure it's the right one
+    let receiver = loop {
+        let (stream, addr) = listener.accept()?;
+        if addr == sender.local_addr()? {
+            break stream;
+        }
+    };
+
+    sender.set_nodelay(true)?;
+    sender.set_nonblocking(true)?;
+    receiver.set_nonblocking(true)?;
+    Ok((sender, receiver))
+}
+
+/// `poll(2)`, or `WSAPoll` on Windows, which std doesn't expose
+mod sys {
+    use std::ffi::{c_int, c_short};
+    use std::io;
+    use std::time::Duration;
+
+    pub type Events = c_short;
+
+    #[cfg(unix)]
+    type RawSocket = c_int;
+    #[cfg(windows)]
+    type RawSocket = usize;
+
+    #[cfg(any(
+        target_vendor = "apple",
+        target_os = "freebsd",
+        target_os = "openbsd",
+        target_os = "netbsd"
+    ))]
+    type Nfds = std::ffi::c_uint;
+    #[cfg(not(any(
+        target_vendor = "apple",
+        target_os = "freebsd",
+        target_os = "openbsd",
+        target_os = "netbsd"
+    )))]
+    type Nfds = std::ffi::c_ulong;
+
+    #[cfg(unix)]
+    pub const READABLE: Events = 0x1;
+    #[cfg(unix)]
+    pub const WRITABLE: Events = 0x4;
+    #[cfg(windows)]
+    pub const READABLE: Events = 0x100;
+    #[cfg(windows)]
+    pub const WRITABLE: Events = 0x10;
+
+    #[cfg(unix)]
+    unsafe extern "C" {
+        #[link_name = "poll"]
+        fn poll_sockets(fds: *mut PollFd, nfds: Nfds, timeout: c_int) -> c_int;
+    }
+
+    #[cfg(windows)]
+    #[link(name = "ws2_32")]
+    unsafe extern "system" {
+        #[link_name = "WSAPoll"]
+        fn poll_sockets(fds: *mut PollFd, nfds: Nfds, timeout: c_int) -> c_int;
+    }
+
+    pub trait Socket {
+        fn raw(&self) -> RawSocket;
+    }
+
+    #[cfg(unix)]
+    impl<T: std::os::fd::AsRawFd> Socket for T {
+        fn raw(&self) -> RawSocket {
+            self.as_raw_fd()
+        }
+    }
+
+    #[cfg(windows)]
+    impl<T: std::os::windows::io::AsRawSocket> Socket for T {
+        fn raw(&self) -> RawSocket {
+            self.as_raw_socket() as RawSocket
+        }
+    }
+
+    /// Laid out like `struct pollfd` and `WSAPOLLFD`
+    #[repr(C)]
+    pub struct PollFd {
+        fd: RawSocket,
+        events: Events,
+        revents: Events,
+    }
+
+    impl PollFd {
+        pub fn new(socket: &impl Socket, events: Events) -> Self {
+            Self {
+                fd: socket.raw(),
+                events,
+                revents: 0,
+            }
+        }
+
+        /// Ready for what was asked, or closed or failed, which the next read or write will report
+        pub const fn is_ready(&self) -> bool {
+            self.revents != 0
+        }
+    }
+
+    /// Waits until one of `fds` is ready or `timeout` passes. Being interrupted by a signal counts as a timeout.
+    pub fn poll(fds: &mut [PollFd], timeout: Duration) -> io::Result<()> {
+        let timeout = c_int::try_from(timeout.as_micros().div_ceil(1000)).unwrap_or(c_int::MAX);
+
+        // SAFETY: `fds` is an exclusively borrowed array of `pollfd`-compatible structs, and its length is passed along
+        let result = unsafe { poll_sockets(fds.as_mut_ptr(), fds.len() as Nfds, timeout) };
+
+        if result < 0 {
+            let e = io::Error::last_os_error();
+            if e.kind() != io::ErrorKind::Interrupted {
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader};
+    use std::net::Shutdown;
+
+    struct Reply {
+        status: String,
+        headers: Vec<(String, String)>,
+        body: Vec<u8>,
+    }
+
+    impl Reply {
+        fn header(&self, name: &str) -> Option<&str> {
+            self.headers
+                .iter()
+                .find(|(n, _)| n.eq_ignore_ascii_case(name))
+                .map(|(_, v)| v.as_str())
+        }
+    }
+
+    /// Starts an event loop on a loopback port with a couple of inline routes and returns a client for it
+    fn connect(max_requests_per_connection: usize) -> BufReader<TcpStream> {
+        let mut config = Config::default();
+        config.limits.max_requests_per_connection = max_requests_per_connection;
+        let config: &'static Config = Box::leak(Box::new(config));
+
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).expect("bind a loopback port");
+        let addr = listener.local_addr().expect("listener has an address");
+        thread::spawn(move || {
+            let router = Router::new()
+                .get("/hello", |_, _| {
+                    Ok(Response::new("200 OK", "text/plain", b"hello".to_vec()))
+                })
+                .inline()
+                .post("/echo", |request, _| {
+                    Ok(Response::new("200 OK", "text/plain", request.body.clone()))
+                })
+                .inline()
+                .get("/params/{value}", |request, params| {
+                    let decoded = format!(
+                        "{}|{}",
+                        params.get("value").unwrap_or_default(),
+                        request.query_param("q").unwrap_or_default()
+                    );
+                    Ok(Response::new("200 OK", "text/plain", decoded.into_bytes()))
+                })
+                .inline()
+                .delete("/hello", |_, _| {
+                    Ok(Response::new("204 No Content", "text/plain", Vec::new()))
+                })
+                .inline();
+            // Every route is inline, so nothing is ever queued
+            let (jobs, _) = flume::bounded(1);
+            EventLoop::new(listener, config, Arc::new(router), jobs)
+                .expect("start the event loop")
+                .run();
+        });
+
+        let stream = TcpStream::connect(addr).expect("connect to the event loop");
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .expect("set a read timeout");
+        BufReader::new(stream)
+    }
+
+    fn send(client: &mut BufReader<TcpStream>, data: &[u8]) {
+        client
+            .get_mut()
+            .write_all(data)
+            .expect("send to the server");
+    }
+
+    fn read_head(client: &mut BufReader<TcpStream>) -> (String, Vec<(String, String)>) {
+        let mut lines = Vec::new();
+        loop {
+            let mut line = String::new();
+            client.read_line(&mut line).expect("read a response line");
+            assert!(!line.is_empty(), "connection closed mid-response");
+            let line = line.trim_end().to_string();
+            if line.is_empty() {
+                break;
+            }
+            lines.push(line);
+        }
+
+        let status = lines.remove(0);
+        let headers = lines
+            .into_iter()
+            .filter_map(|line| {
+                let (name, value) = line.split_once(':')?;
+                Some((name.to_string(), value.trim().to_string()))
+            })
+            .collect();
+        (status, headers)
+    }
+
+    fn read_reply(client: &mut BufReader<TcpStream>, head_only: bool) -> Reply {
+        let (status, headers) = read_head(client);
+        let mut reply = Reply {
+            status,
+            headers,
+            body: Vec::new(),
+        };
+        if !head_only {
+            let len = reply
+                .header("content-length")
+                .and_then(|len| len.parse().ok())
+                .expect("responses have a Content-Length");
+            reply.body = vec![0; len];
+            client.read_exact(&mut reply.body).expect("read the body");
+        }
+        reply
+    }
+
+    fn is_closed(client: &mut BufReader<TcpStream>) -> bool {
+        let mut rest = Vec::new();
+        client.read_to_end(&mut rest).is_ok_and(|_| rest.is_empty())
+    }
+
+    #[test]
+    fn decodes_chunked_bodies() {
+        let mut client = connect(100);
+        send(
+            &mut client,
+            b"POST /echo HTTP/1.1\r\nHost: test\r\nTransfer-Encoding: chunked\r\n\r\n\
+              4\r\nWiki\r\n5;ext=1\r\npedia\r\n0\r\nTrailer: ignored\r\n\r\n",
+        );
+
+        let reply = read_reply(&mut client, false);
+        assert_eq!(reply.status, "HTTP/1.1 200 OK");
+        assert_eq!(reply.body, b"Wikipedia");
+    }
+
+    #[test]
+    fn rejects_chunked_with_content_length() {
+        let mut client = connect(100);
+        send(
+            &mut client,
+            b"POST /echo HTTP/1.1\r\nTransfer-Encoding: chunked\r\nContent-Length: 4\r\n\r\n0\r\n\r\n",
+        );
+
+        let reply = read_reply(&mut client, false);
+        assert!(reply.status.starts_with("HTTP/1.1 400"));
+        assert_eq!(reply.header("connection"), Some("close"));
+        assert!(is_closed(&mut client));
+    }
+
+    #[test]
+    fn rejects_conflicting_content_lengths() {
+        for lengths in [
+            &b"Content-Length: 5\r\nContent-Length: 6\r\n"[..],
+            b"Content-Length: 5, 6\r\n",
+            b"Content-Length: +5\r\n",
+        ] {
+            let mut client = connect(100);
+            send(
+                &mut client,
+                &[b"POST /echo HTTP/1.1\r\n", lengths, b"\r\nhello!"].concat(),
+            );
+
+            let reply = read_reply(&mut client, false);
+            assert!(reply.status.starts_with("HTTP/1.1 400"), "{lengths:?}");
+            assert!(is_closed(&mut client));
+        }
+    }
+
+    #[test]
+    fn accepts_repeated_content_lengths_that_agree() {
+        let mut client = connect(100);
+        send(
+            &mut client,
+            b"POST /echo HTTP/1.1\r\nContent-Length: 5\r\nContent-Length: 5, 5\r\n\r\nhello",
+        );
+
+        let reply = read_reply(&mut client, false);
+        assert_eq!(reply.status, "HTTP/1.1 200 OK");
+        assert_eq!(reply.body, b"hello");
+    }
+
+    #[test]
+    fn no_content_has_no_content_length() {
+        let mut client = connect(100);
+        send(
+            &mut client,
+            b"DELETE /hello HTTP/1.1\r\n\r\nGET /hello HTTP/1.1\r\n\r\n",
+        );
+
+        let (status, headers) = read_head(&mut client);
+        assert_eq!(status, "HTTP/1.1 204 No Content");
+        assert!(
+            !headers
+                .iter()
+                .any(|(name, _)| name.eq_ignore_ascii_case("content-length"))
+        );
+        // Nothing follows the head, so the next response starts right after it
+        let reply = read_reply(&mut client, false);
+        assert_eq!(reply.status, "HTTP/1.1 200 OK");
+        assert_eq!(reply.body, b"hello");
+    }
+
+    #[test]
+    fn plus_is_only_a_space_in_query_strings() {
+        let mut client = connect(100);
+        send(
+            &mut client,
+            b"GET /params/a+b%20c%2B?q=d+e%2Bf HTTP/1.1\r\n\r\n",
+        );
+
+        let reply = read_reply(&mut client, false);
+        assert_eq!(reply.body, b"a+b c+|d e+f");
+    }
+
+    #[test]
+    fn answers_expect_continue_before_the_body() {
+        let mut client = connect(100);
+        send(
+            &mut client,
+            b"POST /echo HTTP/1.1\r\nContent-Length: 5\r\nExpect: 100-continue\r\n\r\n",
+        );
+
+        let (status, _) = read_head(&mut client);
+        assert_eq!(status, "HTTP/1.1 100 Continue");
+
+        send(&mut client, b"hello");
+        let reply = read_reply(&mut client, false);
+        assert_eq!(reply.status, "HTTP/1.1 200 OK");
+        assert_eq!(reply.body, b"hello");
+    }
+
+    #[test]
+    fn head_leaves_out_the_body() {
+        let mut client = connect(100);
+        send(
+            &mut client,
+            b"HEAD /hello HTTP/1.1\r\n\r\nGET /hello HTTP/1.1\r\n\r\n",
+        );
+
+        let head = read_reply(&mut client, true);
+        assert_eq!(head.status, "HTTP/1.1 200 OK");
+        assert_eq!(head.header("content-length"), Some("5"));
+
+        // Had the HEAD response carried a body, this would read it as the next status line
+        let get = read_reply(&mut client, false);
+        assert_eq!(get.status, "HTTP/1.1 200 OK");
+        assert_eq!(get.body, b"hello");
+    }
+
+    #[test]
+    fn closes_after_max_requests_per_connection() {
+        let mut client = connect(3);
+        for served in 1..=3 {
+            send(&mut client, b"GET /hello HTTP/1.1\r\n\r\n");
+            let reply = read_reply(&mut client, false);
+            assert_eq!(reply.status, "HTTP/1.1 200 OK");
+            let expected = if served < 3 { "keep-alive" } else { "close" };
+            assert_eq!(reply.header("connection"), Some(expected));
+        }
+        assert!(is_closed(&mut client));
+    }
+
+    #[test]
+    fn http_1_0_closes_unless_asked_to_keep_alive() {
+        let mut client = connect(100);
+        send(&mut client, b"GET /hello HTTP/1.0\r\n\r\n");
+
+        let reply = read_reply(&mut client, false);
+        assert_eq!(reply.header("connection"), Some("close"));
+        assert!(is_closed(&mut client));
+    }
+
+    #[test]
+    fn rejects_malformed_request_lines() {
+        for request in [
+            &b"GARBAGE\r\n\r\n"[..],
+            b"GET /hello\r\n\r\n",
+            b"get /hello HTTP/1.1\r\n\r\n",
+            b"GET /hello HTTP/2.0\r\n\r\n",
+            b"GET hello HTTP/1.1\r\n\r\n",
+            b"GET /hello HTTP/1.1\r\nBad Header: x\r\n\r\n",
+            b"GET /hello HTTP/1.1\r\nX-Folded: a\r\n b\r\n\r\n",
+        ] {
+            let mut client = connect(100);
+            send(&mut client, request);
+
+            let reply = read_reply(&mut client, false);
+            let request = String::from_utf8_lossy(request);
+            assert!(
+                reply.status.starts_with("HTTP/1.1 400"),
+                "{request:?} got {}",
+                reply.status
+            );
+            assert_eq!(reply.header("connection"), Some("close"), "{request:?}");
+            assert!(
+                is_closed(&mut client),
+                "{request:?} left the connection open"
+            );
+        }
+    }
+
+    #[test]
+    fn closes_when_the_client_stops_mid_request() {
+        let mut client = connect(100);
+        send(
+            &mut client,
+            b"POST /echo HTTP/1.1\r\nContent-Length: 10\r\n\r\nhalf",
+        );
+        client
+            .get_ref()
+            .shutdown(Shutdown::Write)
+            .expect("half-close the connection");
+
+        assert!(is_closed(&mut client));
+    }
+}
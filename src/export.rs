@@ -0,0 +1,446 @@
+use crate::error::Error;
+use crate::history::Entry;
+use crate::http;
+use crate::markdown::{self, escape};
+use crate::pdf::{self, Font};
+use crate::permalink;
+use crate::zip::{self, ZipWriter};
+use miniserde::json;
+use std::fmt::Write as _;
+
+const MAX_FILE_NAME_CHARS: usize = 80;
+/// The zip of the whole history is built in memory, so it stops here rather than at the 4 GiB a
+/// zip without ZIP64 could hold
+const MAX_ARCHIVE_SIZE: usize = 256 * 1024 * 1024;
+
+const EPUB_CONTAINER: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<container version="1.0" xmlns="urn:oasis:names:tc:opendocument:xmlns:container">
+  <rootfiles>
+    <rootfile full-path="OEBPS/content.opf" media-type="application/oebps-package+xml"/>
+  </rootfiles>
+</container>
+"#;
+
+#[derive(Clone, Copy)]
+pub enum Format {
+    /// With YAML front matter, for Obsidian and Logseq
+    Markdown,
+    /// The same standalone page as a permalink
+    Html,
+    Epub,
+    Pdf,
+}
+
+impl Format {
+    /// From a `format` query parameter, Markdown if there is none
+    pub fn parse(name: Option<&str>) -> Result<Self, Error> {
+        match name.unwrap_or("md") {
+            "md" | "markdown" => Ok(Self::Markdown),
+            "html" => Ok(Self::Html),
+            "epub" => Ok(Self::Epub),
+            "pdf" => Ok(Self::Pdf),
+            other => Err(Error::BadRequest(format!(
+                "unknown export format {other}, expected md, html, epub or pdf"
+            ))),
+        }
+    }
+
+    pub const fn extension(self) -> &'static str {
+        match self {
+            Self::Markdown => "md",
+            Self::Html => "html",
+            Self::Epub => "epub",
+            Self::Pdf => "pdf",
+        }
+    }
+
+    pub const fn content_type(self) -> &'static str {
+        match self {
+            Self::Markdown => "text/markdown; charset=utf-8",
+            Self::Html => "text/html; charset=utf-8",
+            Self::Epub => "application/epub+zip",
+            Self::Pdf => "application/pdf",
+        }
+    }
+}
+
+pub fn render(entry: &Entry, format: Format) -> Result<Vec<u8>, Error> {
+    Ok(match format {
+        Format::Markdown => markdown_file(entry).into_bytes(),
+        Format::Html => permalink::render(entry).into_bytes(),
+        Format::Epub => epub(entry)?,
+        Format::Pdf => pdf(entry),
+    })
+}
+
+/// A file name from the video title that's safe on every OS
+pub fn file_name(entry: &Entry, format: Format) -> String {
+    format!("{}.{}", file_stem(entry), format.extension())
+}
+
+/// Every entry in its own file, zipped. Names get the entry id so equal titles don't collide.
+pub fn archive(entries: &[Entry], format: Format) -> Result<Vec<u8>, Error> {
+    let mut zip = ZipWriter::new();
+    for entry in entries {
+        let name = format!("{} {}.{}", file_stem(entry), entry.id, format.extension());
+        zip.add(&name, &render(entry, format)?)?;
+        if zip.size() > MAX_ARCHIVE_SIZE {
+            return Err(Error::ExportTooLarge);
+        }
+    }
+    Ok(zip.finish()?)
+}
+
+/// An attachment header value. Old clients get an ASCII-only `filename`, the rest read the full
+/// name from `filename*`.
+pub fn content_disposition(name: &str) -> String {
+    let fallback: String = name
+        .chars()
+        .map(|c| if c.is_ascii() { c } else { '_' })
+        .collect();
+    format!(
+        "attachment; filename=\"{fallback}\"; filename*=UTF-8''{}",
+        http::percent_encode(name)
+    )
+}
+
+fn file_stem(entry: &Entry) -> String {
+    let mut stem = String::new();
+    for c in entry.video_name.chars() {
+        if c.is_alphanumeric() || matches!(c, '-' | '_' | '.' | ',' | '(' | ')' | '\'') {
+            stem.push(c);
+        } else if !stem.ends_with(' ') {
+            stem.push(' ');
+        }
+    }
+
+    let stem: String = stem
+        .trim_matches([' ', '.'])
+        .chars()
+        .take(MAX_FILE_NAME_CHARS)
+        .collect();
+    if stem.trim().is_empty() {
+        "summary".into()
+    } else {
+        stem.trim_end().to_string()
+    }
+}
+
+/// Source, date and model on one line
+fn byline(entry: &Entry) -> String {
+    let mut line = format!(
+        "Summarized {}",
+        entry.created_at.get(..10).unwrap_or(&entry.created_at)
+    );
+    if let Some(model) = &entry.model {
+        let _ = write!(line, " with {model}");
+    }
+    line
+}
+
+fn title(entry: &Entry) -> &str {
+    if entry.video_name.is_empty() {
+        "Video summary"
+    } else {
+        &entry.video_name
+    }
+}
+
+fn markdown_file(entry: &Entry) -> String {
+    // JSON strings are valid YAML, which saves writing a YAML quoter
+    let mut out = format!(
+        "---\ntitle: {}\nsource: {}\ncreated: {}\nlanguage: {}\n",
+        json::to_string(title(entry)),
+        json::to_string(&entry.url),
+        entry.created_at,
+        json::to_string(&entry.language),
+    );
+    if let Some(model) = &entry.model {
+        let _ = writeln!(out, "model: {}", json::to_string(model));
+    }
+    out.push_str("tags:\n  - youtube\n  - summary\n---\n\n");
+
+    let _ = write!(out, "# {}\n\n{}\n", title(entry), entry.summary.trim_end());
+    if !entry.subtitles.is_empty() {
+        let _ = write!(out, "\n## Transcript\n\n{}\n", entry.subtitles.trim_end());
+    }
+    out
+}
+
+fn epub(entry: &Entry) -> Result<Vec<u8>, zip::TooLarge> {
+    let title = escape(title(entry));
+    let language = escape(&entry.language);
+    let has_transcript = !entry.subtitles.is_empty();
+
+    let mut summary = format!("<h1>{title}</h1>\n<p>");
+    if markdown::is_safe_url(&entry.url) && entry.url.starts_with("http") {
+        let _ = write!(
+            summary,
+            "<a href=\"{}\">Watch the video</a> · ",
+            escape(&entry.url)
+        );
+    }
+    let _ = write!(
+        summary,
+        "{}</p>\n{}",
+        escape(&byline(entry)),
+        markdown::to_xhtml(&entry.summary)
+    );
+
+    let mut manifest = String::from(
+        "<item id=\"nav\" href=\"nav.xhtml\" media-type=\"application/xhtml+xml\" properties=\"nav\"/>\n\
+         <item id=\"summary\" href=\"summary.xhtml\" media-type=\"application/xhtml+xml\"/>\n",
+    );
+    let mut spine = String::from("<itemref idref=\"summary\"/>\n");
+    let mut toc = String::from("<li><a href=\"summary.xhtml\">Summary</a></li>\n");
+    if has_transcript {
+        manifest.push_str(
+            "<item id=\"transcript\" href=\"transcript.xhtml\" media-type=\"application/xhtml+xml\"/>\n",
+        );
+        spine.push_str("<itemref idref=\"transcript\"/>\n");
+        toc.push_str("<li><a href=\"transcript.xhtml\">Transcript</a></li>\n");
+    }
+
+    let package = format!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n\
+         <package xmlns=\"http://www.idpf.org/2007/opf\" version=\"3.0\" unique-identifier=\"id\">\n\
+         <metadata xmlns:dc=\"http://purl.org/dc/elements/1.1/\">\n\
+         <dc:identifier id=\"id\">urn:youtubetldr:{}</dc:identifier>\n\
+         <dc:title>{title}</dc:title>\n<dc:language>{language}</dc:language>\n\
+         <dc:source>{}</dc:source>\n\
+         <meta property=\"dcterms:modified\">{}</meta>\n</metadata>\n\
+         <manifest>\n{manifest}</manifest>\n<spine>\n{spine}</spine>\n</package>\n",
+        escape(&entry.id),
+        escape(&entry.url),
+        escape(&entry.created_at),
+    );
+    let nav = format!("<nav epub:type=\"toc\">\n<h1>Contents</h1>\n<ol>\n{toc}</ol>\n</nav>\n");
+
+    let mut zip = ZipWriter::new();
+    // Readers sniff the type from this being the first file
+    zip.add("mimetype", b"application/epub+zip")?;
+    zip.add("META-INF/container.xml", EPUB_CONTAINER.as_bytes())?;
+    zip.add("OEBPS/content.opf", package.as_bytes())?;
+    zip.add("OEBPS/nav.xhtml", xhtml(&title, &language, &nav).as_bytes())?;
+    zip.add(
+        "OEBPS/summary.xhtml",
+        xhtml(&title, &language, &summary).as_bytes(),
+    )?;
+    if has_transcript {
+        let mut transcript = String::from("<h1>Transcript</h1>\n");
+        for line in entry.subtitles.lines().filter(|l| !l.trim().is_empty()) {
+            let _ = writeln!(transcript, "<p>{}</p>", escape(line));
+        }
+        zip.add(
+            "OEBPS/transcript.xhtml",
+            xhtml(&title, &language, &transcript).as_bytes(),
+        )?;
+    }
+    zip.finish()
+}
+
+/// Wraps an already escaped body in an EPUB content document
+fn xhtml(title: &str, language: &str, body: &str) -> String {
+    format!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<!DOCTYPE html>\n\
+         <html xmlns=\"http://www.w3.org/1999/xhtml\" xmlns:epub=\"http://www.idpf.org/2007/ops\" \
+         lang=\"{language}\" xml:lang=\"{language}\">\n\
+         <head>\n<meta charset=\"utf-8\"/>\n<title>{title}</title>\n</head>\n<body>\n{body}</body>\n</html>\n"
+    )
+}
+
+fn pdf(entry: &Entry) -> Vec<u8> {
+    let mut doc = pdf::Document::new();
+    doc.paragraph("", title(entry), Font::Bold, 20.0, 0.0);
+    doc.gap(2.0);
+    doc.paragraph("", &entry.url, Font::Regular, 9.0, 0.0);
+    doc.paragraph("", &byline(entry), Font::Regular, 9.0, 0.0);
+    doc.rule();
+
+    let mut layout = PdfLayout::new(&mut doc);
+    layout.html(&markdown::to_html(&entry.summary));
+
+    if !entry.subtitles.is_empty() {
+        doc.gap(12.0);
+        doc.paragraph("", "Transcript", Font::Bold, 14.0, 0.0);
+        for line in entry.subtitles.lines().filter(|l| !l.trim().is_empty()) {
+            doc.gap(3.0);
+            doc.paragraph("", line, Font::Regular, 10.0, 0.0);
+        }
+    }
+
+    doc.finish(title(entry))
+}
+
+const BODY_SIZE: f32 = 11.0;
+const CODE_SIZE: f32 = 9.0;
+const INDENT: f32 = 14.0;
+/// Stands in for `<br>` until a block is laid out, so it survives whitespace being collapsed
+const LINE_BREAK: char = '\u{2028}';
+
+/// Lays out the markdown renderer's HTML as PDF text. The renderer only writes a known set of tags,
+/// so this doesn't need to be a real HTML parser.
+struct PdfLayout<'a> {
+    doc: &'a mut pdf::Document,
+    text: String,
+    font: Font,
+    size: f32,
+    /// Open lists, with the next number for ordered ones
+    lists: Vec<Option<u64>>,
+    quotes: usize,
+    bullet: Option<String>,
+    preformatted: bool,
+}
+
+impl<'a> PdfLayout<'a> {
+    const fn new(doc: &'a mut pdf::Document) -> Self {
+        Self {
+            doc,
+            text: String::new(),
+            font: Font::Regular,
+            size: BODY_SIZE,
+            lists: Vec::new(),
+            quotes: 0,
+            bullet: None,
+            preformatted: false,
+        }
+    }
+
+    fn html(&mut self, html: &str) {
+        let mut rest = html;
+        while let Some(start) = rest.find('<') {
+            self.push_text(&rest[..start]);
+            let Some(len) = rest[start..].find('>') else {
+                break;
+            };
+            let tag = &rest[start + 1..start + len];
+            rest = &rest[start + len + 1..];
+            self.tag(tag);
+        }
+        self.push_text(rest);
+        self.flush();
+    }
+
+    fn push_text(&mut self, text: &str) {
+        self.text.push_str(
+            &text
+                .replace("&lt;", "<")
+                .replace("&gt;", ">")
+                .replace("&quot;", "\"")
+                .replace("&#39;", "'")
+                .replace("&amp;", "&"),
+        );
+    }
+
+    fn tag(&mut self, tag: &str) {
+        let (closing, tag) = tag
+            .strip_prefix('/')
+            .map_or((false, tag), |tag| (true, tag));
+        let name = tag.split_whitespace().next().unwrap_or_default();
+
+        match (closing, name) {
+            (false, "h1" | "h2" | "h3" | "h4" | "h5" | "h6") => {
+                self.flush();
+                self.doc.gap(6.0);
+                self.font = Font::Bold;
+                self.size = match name {
+                    "h1" => 16.0,
+                    "h2" => 14.0,
+                    _ => 12.0,
+                };
+            }
+            (true, "h1" | "h2" | "h3" | "h4" | "h5" | "h6" | "pre" | "tr") => {
+                self.flush();
+                self.font = Font::Regular;
+                self.size = BODY_SIZE;
+                self.preformatted = false;
+            }
+            (_, "p") | (true, "li") | (false, "tr") => self.flush(),
+            (false, "ul") => {
+                self.flush();
+                self.lists.push(None);
+            }
+            (false, "ol") => {
+                self.flush();
+                let start = tag
+                    .split_once("start=\"")
+                    .and_then(|(_, rest)| rest.split('"').next())
+                    .and_then(|n| n.parse().ok())
+                    .unwrap_or(1);
+                self.lists.push(Some(start));
+            }
+            (true, "ul" | "ol") => {
+                self.flush();
+                self.lists.pop();
+            }
+            (false, "li") => {
+                self.flush();
+                self.bullet = Some(match self.lists.last_mut() {
+                    Some(Some(number)) => {
+                        *number += 1;
+                        format!("{}. ", *number - 1)
+                    }
+                    _ => "• ".into(),
+                });
+            }
+            (false, "pre") => {
+                self.flush();
+                self.font = Font::Mono;
+                self.size = CODE_SIZE;
+                self.preformatted = true;
+            }
+            (false, "blockquote") => {
+                self.flush();
+                self.quotes += 1;
+            }
+            (true, "blockquote") => {
+                self.flush();
+                self.quotes = self.quotes.saturating_sub(1);
+            }
+            (false, "th") => self.font = Font::Bold,
+            (true, "th" | "td") => self.text.push_str(" | "),
+            (false, "br") => self.text.push(LINE_BREAK),
+            (false, "hr") => {
+                self.flush();
+                self.doc.rule();
+            }
+            (false, "input") => {
+                self.text.push_str(if tag.contains("checked") {
+                    "[x] "
+                } else {
+                    "[ ] "
+                });
+            }
+            _ => {}
+        }
+    }
+
+    /// Lays out the text collected since the last block
+    fn flush(&mut self) {
+        let text = std::mem::take(&mut self.text);
+        let bullet = self.bullet.take();
+        let depth = self.lists.len().saturating_sub(1) + self.quotes;
+        let indent = f32::from(u16::try_from(depth).unwrap_or(u16::MAX)) * INDENT;
+
+        if self.preformatted {
+            for line in text.lines() {
+                self.doc.preformatted(line, self.size, indent + INDENT);
+            }
+            self.doc.gap(4.0);
+            return;
+        }
+
+        let text = text.trim().trim_end_matches(" |");
+        if text.is_empty() && bullet.is_none() {
+            return;
+        }
+        let mut prefix = bullet.unwrap_or_default();
+        for line in text.split(LINE_BREAK) {
+            let line = line.split_whitespace().collect::<Vec<_>>().join(" ");
+            self.doc
+                .paragraph(&prefix, &line, self.font, self.size, indent);
+            prefix.clear();
+        }
+        self.doc.gap(4.0);
+    }
+}
@@ -0,0 +1,455 @@
+use crate::error::Error;
+use crate::history::Entry;
+use crate::{datetime, markdown};
+use std::fmt::Write as _;
+
+#[derive(Clone, Copy)]
+pub enum Format {
+    Atom,
+    Rss,
+}
+
+impl Format {
+    /// From a `format` query parameter, Atom if there is none
+    pub fn parse(name: Option<&str>) -> Result<Self, Error> {
+        match name.unwrap_or("atom") {
+            "atom" => Ok(Self::Atom),
+            "rss" => Ok(Self::Rss),
+            other => Err(Error::BadRequest(format!(
+                "unknown feed format {other}, expected atom or rss"
+            ))),
+        }
+    }
+
+    pub const fn content_type(self) -> &'static str {
+        match self {
+            Self::Atom => "application/atom+xml; charset=utf-8",
+            Self::Rss => "application/rss+xml; charset=utf-8",
+        }
+    }
+}
+
+pub struct Feed<'a> {
+    pub title: &'a str,
+    pub description: &'a str,
+    /// Absolute URL of the feed itself, which also serves as its id
+    pub url: &'a str,
+    /// Absolute URL of the web UI
+    pub site_url: &'a str,
+    /// Newest first
+    pub entries: &'a [Entry],
+}
+
+impl Feed<'_> {
+    pub fn render(&self, format: Format) -> String {
+        let mut out = String::with_capacity(self.entries.iter().map(|e| e.summary.len() * 2).sum());
+        out.push_str("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n");
+        match format {
+            Format::Atom => self.atom(&mut out),
+            Format::Rss => self.rss(&mut out),
+        }
+        out
+    }
+
+    /// When the newest entry was made, or now if there isn't one
+    fn updated(&self) -> u64 {
+        self.entries.first().map_or_else(datetime::now_unix, created)
+    }
+
+    fn atom(&self, out: &mut String) {
+        let _ = write!(
+            out,
+            "<feed xmlns=\"http://www.w3.org/2005/Atom\">\n<id>{url}</id>\n<title>{}</title>\n\
+             <subtitle>{}</subtitle>\n<updated>{}</updated>\n\
+             <link rel=\"self\" type=\"application/atom+xml\" href=\"{url}\"/>\n\
+             <link rel=\"alternate\" type=\"text/html\" href=\"{}\"/>\n\
+             <author><name>YouTubeTLDR</name></author>\n<generator>YouTubeTLDR</generator>\n",
+            escape(self.title),
+            escape(self.description),
+            datetime::format_rfc3339(self.updated()),
+            escape(self.site_url),
+            url = escape(self.url),
+        );
+
+        for entry in self.entries {
+            let _ = write!(
+                out,
+                "<entry>\n<id>urn:youtubetldr:{}</id>\n<title>{}</title>\n<updated>{}</updated>\n",
+                escape(&entry.id),
+                escape(title(entry)),
+                datetime::format_rfc3339(created(entry)),
+            );
+            if let Some(link) = video_link(entry) {
+                let _ = writeln!(
+                    out,
+                    "<link rel=\"alternate\" type=\"text/html\" href=\"{}\"/>",
+                    escape(link)
+                );
+            }
+            if let Some(channel) = &entry.channel {
+                let _ = writeln!(
+                    out,
+                    "<author><name>{}</name><uri>https://www.youtube.com/channel/{}</uri></author>",
+                    escape(&channel.name),
+                    escape(&channel.id)
+                );
+            }
+            let _ = write!(
+                out,
+                "<content type=\"html\">{}</content>\n</entry>\n",
+                escape(&markdown::to_html(&entry.summary))
+            );
+        }
+
+        out.push_str("</feed>\n");
+    }
+
+    fn rss(&self, out: &mut String) {
+        let _ = write!(
+            out,
+            "<rss version=\"2.0\" xmlns:atom=\"http://www.w3.org/2005/Atom\" \
+             xmlns:dc=\"http://purl.org/dc/elements/1.1/\">\n<channel>\n\
+             <title>{}</title>\n<link>{}</link>\n<description>{}</description>\n\
+             <atom:link rel=\"self\" type=\"application/rss+xml\" href=\"{}\"/>\n\
+             <lastBuildDate>{}</lastBuildDate>\n<generator>YouTubeTLDR</generator>\n",
+            escape(self.title),
+            escape(self.site_url),
+            escape(self.description),
+            escape(self.url),
+            datetime::format_rfc2822(self.updated()),
+        );
+
+        for entry in self.entries {
+            let _ = write!(
+                out,
+                "<item>\n<title>{}</title>\n<guid isPermaLink=\"false\">urn:youtubetldr:{}</guid>\n",
+                escape(title(entry)),
+                escape(&entry.id),
+            );
+            if let Some(link) = video_link(entry) {
+                let _ = writeln!(out, "<link>{}</link>", escape(link));
+            }
+            if let Some(published) = datetime::parse_rfc3339(&entry.created_at) {
+                let _ = writeln!(
+                    out,
+                    "<pubDate>{}</pubDate>",
+                    datetime::format_rfc2822(published)
+                );
+            }
+            if let Some(channel) = &entry.channel {
+                let _ = writeln!(out, "<dc:creator>{}</dc:creator>", escape(&channel.name));
+            }
+            let _ = write!(
+                out,
+                "<description>{}</description>\n</item>\n",
+                escape(&markdown::to_html(&entry.summary))
+            );
+        }
+
+        out.push_str("</channel>\n</rss>\n");
+    }
+}
+
+/// Atom insists on a date, so one that doesn't parse is replaced with now
+fn created(entry: &Entry) -> u64 {
+    datetime::parse_rfc3339(&entry.created_at).unwrap_or_else(datetime::now_unix)
+}
+
+fn title(entry: &Entry) -> &str {
+    if entry.video_name.is_empty() {
+        "Video summary"
+    } else {
+        &entry.video_name
+    }
+}
+
+fn video_link(entry: &Entry) -> Option<&str> {
+    Some(entry.url.as_str()).filter(|url| markdown::is_safe_url(url) && url.starts_with("http"))
+}
+
+/// Escapes text for XML, dropping the characters XML 1.0 doesn't allow at all
+fn escape(text: &str) -> String {
+    let allowed: String = text
+        .chars()
+        .filter(|&c| {
+            (!c.is_control() || matches!(c, '\t' | '\n' | '\r'))
+                && !matches!(c, '\u{FFFE}' | '\u{FFFF}')
+        })
+        .collect();
+    markdown::escape(&allowed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::history::Channel;
+
+    const HOSTILE: &str = "</title><script>alert(1)</script> & &amp; \"q\" 'a' ]]> <![CDATA[x]]>\
+                           \u{0}\u{1}\u{8}\u{B}\u{C}\u{1B}\u{7F}\u{85}\u{FFFE}\u{FFFF} end";
+    /// What's left of `HOSTILE` once the characters XML can't carry are dropped
+    const HOSTILE_KEPT: &str =
+        "</title><script>alert(1)</script> & &amp; \"q\" 'a' ]]> <![CDATA[x]]> end";
+
+    fn hostile_entries() -> Vec<Entry> {
+        let summary = format!(
+            "# {HOSTILE}\n\n<img src=x onerror=alert(1)> **bold** [link](javascript:alert(1))\n\n\
+             - {HOSTILE}\n\n```\n</content>{HOSTILE}\n```\n"
+        );
+        vec![
+            Entry {
+                video_name: HOSTILE.into(),
+                summary: summary.clone(),
+                channel: Some(Channel {
+                    id: "UC\"><x>".into(),
+                    name: HOSTILE.into(),
+                }),
+                created_at: "2026-03-01T12:00:00Z".into(),
+                ..Entry::new("https://youtu.be/x?a=\"><script>&b=1", "en", None)
+                    .expect("a fresh entry")
+            },
+            Entry {
+                video_name: String::new(),
+                summary,
+                created_at: "not a date".into(),
+                ..Entry::new("javascript:alert(1)", "en", None).expect("a fresh entry")
+            },
+        ]
+    }
+
+    fn render(format: Format) -> String {
+        let entries = hostile_entries();
+        Feed {
+            title: HOSTILE,
+            description: HOSTILE,
+            url: "https://tldr.example/feeds/all.xml?a=1&b=\"2\"",
+            site_url: "https://tldr.example/",
+            entries: &entries,
+        }
+        .render(format)
+    }
+
+    /// Checks `xml` is well-formed, with only the XML 1.0 characters and entities, and returns
+    /// every element's name with its unescaped text, in the order they close
+    fn parse(xml: &str) -> Vec<(String, String)> {
+        for c in xml.chars() {
+            assert!(
+                !c.is_control() || matches!(c, '\t' | '\n' | '\r'),
+                "control character {c:?} in the output"
+            );
+            assert!(!matches!(c, '\u{FFFE}' | '\u{FFFF}'), "{c:?} in the output");
+        }
+        let body = xml
+            .strip_prefix("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n")
+            .expect("starts with the XML declaration");
+
+        let mut open: Vec<(String, String)> = Vec::new();
+        let mut closed = Vec::new();
+        let mut roots = 0;
+        let mut rest = body;
+        while !rest.is_empty() {
+            let Some(tag) = rest.strip_prefix('<') else {
+                let end = rest.find('<').unwrap_or(rest.len());
+                let text = unescape(&rest[..end]);
+                assert!(
+                    open.last_mut().map(|(_, t)| t.push_str(&text)).is_some()
+                        || text.trim().is_empty(),
+                    "text outside the root element"
+                );
+                rest = &rest[end..];
+                continue;
+            };
+
+            let end = tag_end(tag);
+            let inner = &tag[..end];
+            rest = &tag[end + 1..];
+            if let Some(name) = inner.strip_prefix('/') {
+                let (open_name, text) = open.pop().expect("a closing tag has an opening one");
+                assert_eq!(name, open_name, "tags close in the wrong order");
+                closed.push((open_name, text));
+                continue;
+            }
+
+            let self_closing = inner.ends_with('/');
+            let inner = inner.trim_end_matches('/');
+            let name_end = inner.find(char::is_whitespace).unwrap_or(inner.len());
+            let name = &inner[..name_end];
+            assert!(
+                !name.is_empty()
+                    && name
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || matches!(c, ':' | '-' | '_')),
+                "bad tag name {name:?}"
+            );
+            check_attributes(&inner[name_end..]);
+            if open.is_empty() {
+                roots += 1;
+            }
+            if self_closing {
+                closed.push((name.to_string(), String::new()));
+            } else {
+                open.push((name.to_string(), String::new()));
+            }
+        }
+
+        assert!(open.is_empty(), "unclosed tags: {open:?}");
+        assert_eq!(roots, 1, "exactly one root element");
+        closed
+    }
+
+    /// Where the tag starting after `<` ends, skipping over quoted attribute values
+    fn tag_end(tag: &str) -> usize {
+        let mut quote = None;
+        for (i, c) in tag.char_indices() {
+            match (quote, c) {
+                (None, '"' | '\'') => quote = Some(c),
+                (Some(q), _) if q == c => quote = None,
+                (None, '>') => return i,
+                (_, '<') => panic!("unescaped < inside a tag"),
+                _ => {}
+            }
+        }
+        panic!("tag never ends");
+    }
+
+    fn check_attributes(mut attributes: &str) {
+        loop {
+            attributes = attributes.trim_start();
+            if attributes.is_empty() {
+                return;
+            }
+            let (name, value) = attributes.split_once('=').expect("attributes have values");
+            assert!(!name.is_empty() && !name.contains(char::is_whitespace));
+            let quote = value.chars().next().expect("a quoted value");
+            assert!(matches!(quote, '"' | '\''), "unquoted attribute value");
+            let end = value[1..].find(quote).expect("the value's quote is closed") + 1;
+            unescape(&value[1..end]);
+            attributes = &value[end + 1..];
+        }
+    }
+
+    /// Resolves entities, insisting every `&` starts a valid one
+    fn unescape(text: &str) -> String {
+        let mut out = String::new();
+        let mut rest = text;
+        while let Some(amp) = rest.find('&') {
+            out.push_str(&rest[..amp]);
+            let entity = &rest[amp + 1..];
+            let end = entity.find(';').expect("entities end with ;");
+            let name = &entity[..end];
+            let c = match name {
+                "amp" => '&',
+                "lt" => '<',
+                "gt" => '>',
+                "quot" => '"',
+                "apos" => '\'',
+                _ => {
+                    let code = name
+                        .strip_prefix("#x")
+                        .map(|hex| u32::from_str_radix(hex, 16))
+                        .or_else(|| name.strip_prefix('#').map(str::parse))
+                        .and_then(Result::ok)
+                        .unwrap_or_else(|| panic!("unknown entity &{name};"));
+                    char::from_u32(code)
+                        .filter(|&c| !c.is_control() || matches!(c, '\t' | '\n' | '\r'))
+                        .unwrap_or_else(|| panic!("&{name}; is not an XML character"))
+                }
+            };
+            out.push(c);
+            rest = &entity[end + 1..];
+        }
+        out.push_str(rest);
+        out
+    }
+
+    fn texts<'a>(elements: &'a [(String, String)], name: &str) -> Vec<&'a str> {
+        elements
+            .iter()
+            .filter(|(n, _)| n == name)
+            .map(|(_, text)| text.as_str())
+            .collect()
+    }
+
+    fn check_summary_html(html: &str) {
+        assert!(!html.contains("<script"), "{html}");
+        assert!(!html.contains("<img"), "{html}");
+        assert!(!html.contains("javascript:"), "{html}");
+    }
+
+    #[test]
+    #[should_panic = "unknown entity"]
+    fn the_checker_catches_a_raw_ampersand() {
+        parse("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<a>x & y;</a>\n");
+    }
+
+    #[test]
+    #[should_panic = "tags close in the wrong order"]
+    fn the_checker_catches_injected_markup() {
+        parse("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<a><b></a></b>\n");
+    }
+
+    #[test]
+    fn atom_escapes_hostile_entries() {
+        let xml = render(Format::Atom);
+        let elements = parse(&xml);
+
+        assert_eq!(
+            texts(&elements, "title"),
+            [HOSTILE_KEPT, HOSTILE_KEPT, "Video summary"]
+        );
+        assert_eq!(texts(&elements, "subtitle"), [HOSTILE_KEPT]);
+        assert_eq!(texts(&elements, "name")[1], HOSTILE_KEPT);
+        assert_eq!(
+            texts(&elements, "id")[0],
+            "https://tldr.example/feeds/all.xml?a=1&b=\"2\""
+        );
+        let contents = texts(&elements, "content");
+        assert_eq!(contents.len(), 2);
+        for html in contents {
+            check_summary_html(html);
+        }
+        assert!(!xml.contains("javascript:"));
+    }
+
+    #[test]
+    fn atom_dates_are_always_valid() {
+        let xml = render(Format::Atom);
+        let updated = texts(&parse(&xml), "updated")
+            .into_iter()
+            .map(str::to_owned)
+            .collect::<Vec<_>>();
+
+        assert_eq!(updated.len(), 3);
+        assert_eq!(updated[0], "2026-03-01T12:00:00Z");
+        assert_eq!(updated[1], "2026-03-01T12:00:00Z");
+        for date in &updated {
+            assert!(datetime::parse_rfc3339(date).is_some(), "{date:?}");
+        }
+    }
+
+    #[test]
+    fn rss_escapes_hostile_entries() {
+        let xml = render(Format::Rss);
+        let elements = parse(&xml);
+
+        assert_eq!(
+            texts(&elements, "title"),
+            [HOSTILE_KEPT, HOSTILE_KEPT, "Video summary"]
+        );
+        assert_eq!(texts(&elements, "dc:creator"), [HOSTILE_KEPT]);
+        assert_eq!(
+            texts(&elements, "link")[1],
+            "https://youtu.be/x?a=\"><script>&b=1"
+        );
+        // Only the entry with a date has one, the bad date is left out rather than made up
+        assert_eq!(
+            texts(&elements, "pubDate"),
+            ["Sun, 01 Mar 2026 12:00:00 +0000"]
+        );
+        let descriptions = texts(&elements, "description");
+        assert_eq!(descriptions[0], HOSTILE_KEPT);
+        for html in &descriptions[1..] {
+            check_summary_html(html);
+        }
+        assert!(!xml.contains("javascript:"));
+    }
+}
@@ -1,10 +1,16 @@
+use crate::{config, deadline};
 use miniserde::{Deserialize, Serialize, json};
 use std::fmt;
 
 #[derive(Debug)]
 pub enum Error {
     Request(minreq::Error),
-    Api { status: u16, body: String },
+    /// The request ran out of time before the model answered
+    DeadlineExceeded,
+    Api {
+        status: u16,
+        body: String,
+    },
     Json(miniserde::Error),
     NoTextInResponse,
 }
@@ -13,6 +19,7 @@ impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Self::Request(_) => write!(f, "Failed to send request to the Gemini API"),
+            Self::DeadlineExceeded => write!(f, "Ran out of time waiting for the Gemini API"),
             Self::Api { status, body } => {
                 write!(f, "Gemini API returned an error (status {status}): {body}")
             }
@@ -27,7 +34,7 @@ impl std::error::Error for Error {
         match self {
             Self::Request(e) => Some(e),
             Self::Json(e) => Some(e),
-            Self::Api { .. } | Self::NoTextInResponse => None,
+            Self::DeadlineExceeded | Self::Api { .. } | Self::NoTextInResponse => None,
         }
     }
 }
@@ -146,11 +153,19 @@ pub fn summarize(
 
     let body_str = json::to_vec(&request_body);
 
+    let timeout =
+        deadline::timeout_secs(config::get().llm.timeout_secs).ok_or(Error::DeadlineExceeded)?;
     let response = minreq::post(req_url)
-        .with_timeout(120)
+        .with_timeout(timeout)
         .with_body(body_str)
         .send()
-        .map_err(Error::Request)?;
+        .map_err(|e| {
+            if deadline::expired() {
+                Error::DeadlineExceeded
+            } else {
+                Error::Request(e)
+            }
+        })?;
 
     if !(200..=299).contains(&response.status_code) {
         let body = response.as_str().unwrap_or("No response body").to_string();
@@ -0,0 +1,616 @@
+use crate::error::Error;
+use crate::subtitle::extract_video_id;
+use crate::{datetime, digest};
+use miniserde::{Deserialize, Serialize, json};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock, PoisonError};
+
+const EXCERPT_CHARS: usize = 200;
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+pub struct Entry {
+    pub id: String,
+    pub created_at: String,
+    pub url: String,
+    pub video_name: String,
+    pub language: String,
+    /// `None` for transcript-only requests
+    pub model: Option<String>,
+    pub summary: String,
+    pub subtitles: String,
+    /// Set while the entry has a public permalink at `/s/{share_token}`
+    pub share_token: Option<String>,
+    /// The subscribed channel the video came from, for summaries made by the scheduler
+    pub channel: Option<Channel>,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+pub struct Channel {
+    pub id: String,
+    pub name: String,
+}
+
+impl Entry {
+    /// An empty entry with a fresh id, created now. The id is what the history API and the exports
+    /// go by, so it comes from the secure generator like the share tokens.
+    pub fn new(url: &str, language: &str, model: Option<&str>) -> io::Result<Self> {
+        Ok(Self {
+            id: digest::secure_random_hex(8)?,
+            created_at: datetime::format_rfc3339(datetime::now_unix()),
+            url: url.to_string(),
+            video_name: String::new(),
+            language: language.to_string(),
+            model: model.map(str::to_string),
+            summary: String::new(),
+            subtitles: String::new(),
+            share_token: None,
+            channel: None,
+        })
+    }
+}
+
+/// An entry as shown in the history list, without the transcript and with the summary shortened
+#[derive(Serialize)]
+pub struct Listing {
+    id: String,
+    created_at: String,
+    url: String,
+    video_name: String,
+    model: Option<String>,
+    excerpt: String,
+    shared: bool,
+}
+
+/// An entry as the API returns it. The share token is left out: the permalink is handed out by
+/// sharing and goes to whoever that's meant for, not to everyone who can read the history.
+#[derive(Serialize)]
+pub struct Details {
+    id: String,
+    created_at: String,
+    url: String,
+    video_name: String,
+    language: String,
+    model: Option<String>,
+    summary: String,
+    subtitles: String,
+    shared: bool,
+    channel: Option<Channel>,
+}
+
+impl From<Entry> for Details {
+    fn from(entry: Entry) -> Self {
+        Self {
+            id: entry.id,
+            created_at: entry.created_at,
+            url: entry.url,
+            video_name: entry.video_name,
+            language: entry.language,
+            model: entry.model,
+            summary: entry.summary,
+            subtitles: entry.subtitles,
+            shared: entry.share_token.is_some(),
+            channel: entry.channel,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct Page {
+    total: usize,
+    entries: Vec<Listing>,
+}
+
+/// One line of the history file, either a new or changed entry or the id of a deleted one
+#[derive(Serialize, Deserialize)]
+struct Line {
+    entry: Option<Entry>,
+    deleted: Option<String>,
+}
+
+struct Indexed {
+    entry: Entry,
+    /// Lowercased title, summary and transcript, so searches don't redo it every time
+    text: String,
+}
+
+impl Indexed {
+    fn new(entry: Entry) -> Self {
+        let text = format!(
+            "{}\n{}\n{}",
+            entry.video_name, entry.summary, entry.subtitles
+        )
+        .to_lowercase();
+        Self { entry, text }
+    }
+}
+
+struct Store {
+    file: File,
+    /// Oldest first, the order they were recorded in
+    entries: Vec<Indexed>,
+}
+
+static STORE: OnceLock<Mutex<Store>> = OnceLock::new();
+
+/// Turns on the history, kept in an append-only file at `path`. Changes and deletions are appended
+/// too and folded into the file on the next start. Returns the number of entries loaded.
+pub fn open(path: &Path) -> io::Result<usize> {
+    let store = Store::open(path)?;
+    let count = store.entries.len();
+    let _ = STORE.set(Mutex::new(store));
+    Ok(count)
+}
+
+pub fn is_enabled() -> bool {
+    STORE.get().is_some()
+}
+
+impl Store {
+    fn open(path: &Path) -> io::Result<Self> {
+        let (entries, needs_compaction) = match fs::read_to_string(path) {
+            Ok(content) => load(&content),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => (Vec::new(), false),
+            Err(e) => return Err(e),
+        };
+
+        if needs_compaction {
+            compact(path, &entries)?;
+        }
+
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self { file, entries })
+    }
+
+    fn record(&mut self, entry: Entry) -> io::Result<()> {
+        self.file
+            .write_all(line(Some(entry.clone()), None).as_bytes())?;
+        self.entries.push(Indexed::new(entry));
+        Ok(())
+    }
+
+    fn delete(&mut self, id: &str) -> Result<(), Error> {
+        let index = self
+            .entries
+            .iter()
+            .position(|e| e.entry.id == id)
+            .ok_or(Error::NotFound)?;
+
+        self.file
+            .write_all(line(None, Some(id.to_string())).as_bytes())?;
+        self.entries.remove(index);
+        Ok(())
+    }
+
+    fn has_video(&self, video_id: &str) -> bool {
+        self.entries
+            .iter()
+            .any(|e| extract_video_id(&e.entry.url) == Some(video_id))
+    }
+
+    fn search(&self, query: &str, offset: usize, limit: usize) -> Page {
+        let terms: Vec<String> = query.split_whitespace().map(str::to_lowercase).collect();
+        let matches: Vec<&Entry> = self
+            .entries
+            .iter()
+            .rev()
+            .filter(|e| terms.iter().all(|term| e.text.contains(term.as_str())))
+            .map(|e| &e.entry)
+            .collect();
+
+        let entries = matches
+            .iter()
+            .skip(offset)
+            .take(limit)
+            .map(|entry| Listing {
+                id: entry.id.clone(),
+                created_at: entry.created_at.clone(),
+                url: entry.url.clone(),
+                video_name: entry.video_name.clone(),
+                model: entry.model.clone(),
+                excerpt: excerpt(&entry.summary),
+                shared: entry.share_token.is_some(),
+            })
+            .collect();
+
+        Page {
+            total: matches.len(),
+            entries,
+        }
+    }
+}
+
+/// Replays the file. Also reports whether it should be rewritten, because of changes, deletions or
+/// a line cut short by a crash mid-write.
+fn load(content: &str) -> (Vec<Indexed>, bool) {
+    let mut entries: Vec<Indexed> = Vec::new();
+    let mut needs_compaction = !content.is_empty() && !content.ends_with('\n');
+
+    for line in content.lines().filter(|l| !l.trim().is_empty()) {
+        match json::from_str::<Line>(line) {
+            Ok(Line {
+                entry: Some(entry), ..
+            }) => {
+                if let Some(existing) = entries.iter_mut().find(|e| e.entry.id == entry.id) {
+                    *existing = Indexed::new(entry);
+                    needs_compaction = true;
+                } else {
+                    entries.push(Indexed::new(entry));
+                }
+            }
+            Ok(Line {
+                deleted: Some(id), ..
+            }) => {
+                entries.retain(|e| e.entry.id != id);
+                needs_compaction = true;
+            }
+            Ok(_) | Err(_) => needs_compaction = true,
+        }
+    }
+
+    (entries, needs_compaction)
+}
+
+/// Rewrites the file with only the live entries, through a temporary file so a crash can't lose it
+fn compact(path: &Path, entries: &[Indexed]) -> io::Result<()> {
+    let mut content = String::new();
+    for indexed in entries {
+        content.push_str(&line(Some(indexed.entry.clone()), None));
+    }
+
+    let mut tmp = PathBuf::from(path);
+    tmp.as_mut_os_string().push(".tmp");
+    fs::write(&tmp, content)?;
+    fs::rename(&tmp, path)
+}
+
+fn line(entry: Option<Entry>, deleted: Option<String>) -> String {
+    let mut line = json::to_string(&Line { entry, deleted });
+    line.push('\n');
+    line
+}
+
+fn store() -> Result<&'static Mutex<Store>, Error> {
+    STORE.get().ok_or(Error::HistoryDisabled)
+}
+
+pub fn record(entry: Entry) -> Result<(), Error> {
+    store()?
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .record(entry)?;
+    Ok(())
+}
+
+pub fn get(id: &str) -> Result<Entry, Error> {
+    store()?
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .entries
+        .iter()
+        .find(|e| e.entry.id == id)
+        .map(|e| e.entry.clone())
+        .ok_or(Error::NotFound)
+}
+
+pub fn delete(id: &str) -> Result<(), Error> {
+    store()?
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .delete(id)
+}
+
+/// Every entry, oldest first
+pub fn all() -> Result<Vec<Entry>, Error> {
+    Ok(store()?
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .entries
+        .iter()
+        .map(|e| e.entry.clone())
+        .collect())
+}
+
+/// Up to `limit` entries that pass `filter`, newest first
+pub fn recent(limit: usize, filter: impl Fn(&Entry) -> bool) -> Result<Vec<Entry>, Error> {
+    Ok(store()?
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .entries
+        .iter()
+        .rev()
+        .map(|e| &e.entry)
+        .filter(|entry| filter(entry))
+        .take(limit)
+        .cloned()
+        .collect())
+}
+
+/// Whether any entry is a summary of the video with this id
+pub fn has_video(video_id: &str) -> Result<bool, Error> {
+    Ok(store()?
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .has_video(video_id))
+}
+
+/// Gives the entry a permalink, or returns the one it already has
+pub fn share(id: &str) -> Result<String, Error> {
+    // Whoever has the token can read the entry, so it has to be unguessable
+    let token = digest::secure_random_hex(16)?;
+    update(id, |entry| entry.share_token.get_or_insert(token).clone())
+}
+
+/// Takes the permalink down. Sharing again later gives a new one.
+pub fn unshare(id: &str) -> Result<(), Error> {
+    update(id, |entry| entry.share_token = None)
+}
+
+/// The entry behind a permalink
+pub fn shared(token: &str) -> Result<Entry, Error> {
+    store()?
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .entries
+        .iter()
+        .find(|e| {
+            e.entry
+                .share_token
+                .as_deref()
+                .is_some_and(|t| digest::constant_time_eq(t.as_bytes(), token.as_bytes()))
+        })
+        .map(|e| e.entry.clone())
+        .ok_or(Error::NotFound)
+}
+
+/// Applies `change` to an entry and appends the result, if it changed anything
+fn update<T>(id: &str, change: impl FnOnce(&mut Entry) -> T) -> Result<T, Error> {
+    let mut guard = store()?.lock().unwrap_or_else(PoisonError::into_inner);
+    let store = &mut *guard;
+    let indexed = store
+        .entries
+        .iter_mut()
+        .find(|e| e.entry.id == id)
+        .ok_or(Error::NotFound)?;
+
+    let mut entry = indexed.entry.clone();
+    let result = change(&mut entry);
+    if entry != indexed.entry {
+        store
+            .file
+            .write_all(line(Some(entry.clone()), None).as_bytes())?;
+        *indexed = Indexed::new(entry);
+    }
+    drop(guard);
+    Ok(result)
+}
+
+/// Newest first. Every word of `query` has to appear in the title, summary or transcript, ignoring case.
+pub fn search(query: &str, offset: usize, limit: usize) -> Result<Page, Error> {
+    Ok(store()?
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .search(query, offset, limit))
+}
+
+fn excerpt(summary: &str) -> String {
+    match summary.char_indices().nth(EXCERPT_CHARS) {
+        Some((end, _)) => format!("{}…", summary[..end].trim_end()),
+        None => summary.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process;
+
+    fn entry(id: &str, url: &str, video_name: &str, summary: &str) -> Entry {
+        Entry {
+            id: id.into(),
+            created_at: "2026-03-01T12:00:00Z".into(),
+            url: url.into(),
+            video_name: video_name.into(),
+            language: "en".into(),
+            model: Some("gemini-2.5-flash".into()),
+            summary: summary.into(),
+            subtitles: format!("transcript of {video_name}"),
+            share_token: None,
+            channel: None,
+        }
+    }
+
+    /// A history file at a temporary path, removed again when dropped
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn new(name: &str, content: &str) -> Self {
+            let path = temp_path(name);
+            fs::write(&path, content).expect("write the history file");
+            Self(path)
+        }
+
+        fn read(&self) -> String {
+            fs::read_to_string(&self.0).expect("read the history file")
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("tldr-history-{}-{name}.jsonl", process::id()))
+    }
+
+    fn ids(store: &Store) -> Vec<&str> {
+        store.entries.iter().map(|e| e.entry.id.as_str()).collect()
+    }
+
+    #[test]
+    fn round_trips_through_the_file() {
+        let file = TempFile::new("round-trip", "");
+        let first = entry("a1", "https://youtu.be/dQw4w9WgXcQ", "First", "One");
+        let second = Entry {
+            share_token: Some("f00d".into()),
+            channel: Some(Channel {
+                id: "UC123".into(),
+                name: "A \"channel\"\nwith lines".into(),
+            }),
+            ..entry(
+                "b2",
+                "https://www.youtube.com/watch?v=jNQXAC9IVRw",
+                "Second",
+                "Two",
+            )
+        };
+
+        let mut store = Store::open(&file.0).expect("open an empty history");
+        store.record(first.clone()).expect("record");
+        store.record(second.clone()).expect("record");
+        drop(store);
+        let written = file.read();
+        assert_eq!(written.lines().count(), 2);
+
+        let store = Store::open(&file.0).expect("reopen the history");
+        let entries: Vec<&Entry> = store.entries.iter().map(|e| &e.entry).collect();
+        assert_eq!(entries, [&first, &second]);
+        // Nothing to fold in, so it's left as written
+        assert_eq!(file.read(), written);
+    }
+
+    #[test]
+    fn compacts_changes_deletions_and_a_cut_off_line() {
+        let kept = entry("a1", "https://youtu.be/dQw4w9WgXcQ", "Kept", "Before");
+        let changed = Entry {
+            summary: "After".into(),
+            ..kept.clone()
+        };
+        let deleted = entry("b2", "https://youtu.be/jNQXAC9IVRw", "Deleted", "Gone");
+        let cut_off = line(Some(entry("c3", "https://youtu.be/x", "Cut", "Off")), None);
+        let content = [
+            line(Some(kept), None),
+            line(Some(deleted), None),
+            "not json\n".into(),
+            line(Some(changed.clone()), None),
+            line(None, Some("b2".into())),
+            cut_off[..cut_off.len() / 2].to_string(),
+        ]
+        .concat();
+
+        let (entries, needs_compaction) = load(&content);
+        assert!(needs_compaction);
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].entry, changed);
+
+        let file = TempFile::new("compact", &content);
+        let mut store = Store::open(&file.0).expect("open the history");
+        assert_eq!(ids(&store), ["a1"]);
+        assert_eq!(file.read(), line(Some(changed), None));
+
+        // A deletion is appended, then folded in on the next start
+        store.delete("a1").expect("delete");
+        assert!(matches!(store.delete("a1"), Err(Error::NotFound)));
+        drop(store);
+        assert_eq!(file.read().lines().count(), 2);
+        let store = Store::open(&file.0).expect("reopen the history");
+        assert!(ids(&store).is_empty());
+        assert_eq!(file.read(), "");
+    }
+
+    #[test]
+    fn a_clean_file_needs_no_compaction() {
+        let content = [
+            line(Some(entry("a1", "https://youtu.be/x", "A", "a")), None),
+            line(Some(entry("b2", "https://youtu.be/y", "B", "b")), None),
+        ]
+        .concat();
+        let (entries, needs_compaction) = load(&content);
+        assert_eq!(entries.len(), 2);
+        assert!(!needs_compaction);
+
+        let (entries, needs_compaction) = load(content.trim_end());
+        assert_eq!(entries.len(), 2);
+        assert!(needs_compaction, "the last line lost its newline");
+    }
+
+    #[test]
+    fn searches_every_word_newest_first() {
+        let file = TempFile::new("search", "");
+        let mut store = Store::open(&file.0).expect("open an empty history");
+        for entry in [
+            entry(
+                "a1",
+                "https://youtu.be/x",
+                "Rust async",
+                "Futures and executors",
+            ),
+            entry(
+                "b2",
+                "https://youtu.be/y",
+                "Cooking",
+                "Rust-coloured ASYNC pans",
+            ),
+            entry(
+                "c3",
+                "https://youtu.be/z",
+                "Rust macros",
+                "Declarative ones",
+            ),
+            entry("d4", "https://youtu.be/w", "Knitting", &"é".repeat(300)),
+        ] {
+            store.record(entry).expect("record");
+        }
+
+        let page = store.search("  rust   Async ", 0, 10);
+        assert_eq!(page.total, 2);
+        let found: Vec<&str> = page.entries.iter().map(|l| l.id.as_str()).collect();
+        assert_eq!(found, ["b2", "a1"]);
+
+        // Matches the transcript too
+        assert_eq!(store.search("transcript of cooking", 0, 10).total, 1);
+
+        let page = store.search("rust", 1, 1);
+        assert_eq!(page.total, 3);
+        assert_eq!(page.entries.len(), 1);
+        assert_eq!(page.entries[0].id, "b2");
+
+        let page = store.search("", 0, 10);
+        assert_eq!(page.total, 4);
+        assert_eq!(page.entries[0].id, "d4");
+        assert_eq!(page.entries[0].excerpt.chars().count(), EXCERPT_CHARS + 1);
+        assert!(page.entries[0].excerpt.ends_with('…'));
+
+        assert_eq!(store.search("rust knitting", 0, 10).total, 0);
+    }
+
+    #[test]
+    fn knows_which_videos_it_has() {
+        let file = TempFile::new("has-video", "");
+        let mut store = Store::open(&file.0).expect("open an empty history");
+        store
+            .record(entry("a1", "https://youtu.be/dQw4w9WgXcQ", "A", "a"))
+            .expect("record");
+        store
+            .record(entry(
+                "b2",
+                "https://www.youtube.com/watch?v=jNQXAC9IVRw&t=10",
+                "B",
+                "b",
+            ))
+            .expect("record");
+
+        assert!(store.has_video("dQw4w9WgXcQ"));
+        assert!(store.has_video("jNQXAC9IVRw"));
+        assert!(!store.has_video("aaaaaaaaaaa"));
+
+        store.delete("a1").expect("delete");
+        drop(store);
+        let store = Store::open(&file.0).expect("reopen the history");
+        assert!(!store.has_video("dQw4w9WgXcQ"));
+        assert!(store.has_video("jNQXAC9IVRw"));
+    }
+}
@@ -0,0 +1,487 @@
+use crate::error::Error;
+use miniserde::{Deserialize, Serialize, json};
+use std::fmt::Write as _;
+use std::io::{self, BufRead, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+
+const MAX_LINE_LENGTH: u64 = 8 * 1024;
+const MAX_HEADERS: usize = 100;
+pub const MAX_HEAD_SIZE: usize = 64 * 1024;
+
+/// A client connection, plain or TLS
+pub trait Connection: Read + Write {
+    fn tcp(&self) -> &TcpStream;
+
+    /// Whether data is buffered that still has to reach the socket, like TLS records written while it was full
+    fn wants_write(&self) -> bool {
+        false
+    }
+}
+
+impl Connection for TcpStream {
+    fn tcp(&self) -> &TcpStream {
+        self
+    }
+}
+
+pub struct Request {
+    pub peer: SocketAddr,
+    pub method: String,
+    /// The path and query exactly as sent
+    pub target: String,
+    pub path: String,
+    pub query: Vec<(String, String)>,
+    pub http_1_0: bool,
+    /// Header names are lowercased
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn query_param(&self, name: &str) -> Option<&str> {
+        self.query
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// HTTP/1.1 connections persist unless the client opts out, HTTP/1.0 ones only if it opts in
+    pub fn keep_alive(&self) -> bool {
+        let connection = self.header("connection").map(str::to_ascii_lowercase);
+        match connection.as_deref() {
+            Some(c) if c.split(',').any(|t| t.trim() == "close") => false,
+            Some(c) if c.split(',').any(|t| t.trim() == "keep-alive") => true,
+            _ => !self.http_1_0,
+        }
+    }
+
+    pub fn json<T: Deserialize>(&self) -> Result<T, Error> {
+        json::from_slice(&self.body).map_err(|e| Error::InvalidJson(e.to_string()))
+    }
+}
+
+pub enum Body {
+    Static(&'static [u8]),
+    Owned(Vec<u8>),
+}
+
+pub struct Response {
+    pub status: &'static str,
+    pub content_type: &'static str,
+    pub headers: Vec<(&'static str, String)>,
+    pub body: Body,
+}
+
+impl Response {
+    pub const fn new(status: &'static str, content_type: &'static str, body: Vec<u8>) -> Self {
+        Self {
+            status,
+            content_type,
+            headers: Vec::new(),
+            body: Body::Owned(body),
+        }
+    }
+
+    pub fn json<T: Serialize>(status: &'static str, payload: &T) -> Self {
+        Self::new(status, "application/json", json::to_vec(payload))
+    }
+
+    pub fn error(error: &Error) -> Self {
+        let response = Self::new(error.status(), "application/json", error.to_json());
+        match error {
+            Error::MethodNotAllowed(allowed) => response.with_header("Allow", allowed.join(", ")),
+            Error::Overloaded => response.with_header("Retry-After", "5"),
+            _ => response,
+        }
+    }
+
+    #[must_use]
+    pub fn with_header(mut self, name: &'static str, value: impl Into<String>) -> Self {
+        self.headers.push((name, value.into()));
+        self
+    }
+
+    fn body(&self) -> &[u8] {
+        match &self.body {
+            Body::Static(content) => content,
+            Body::Owned(content) => content,
+        }
+    }
+}
+
+pub enum Parsed {
+    /// More bytes are needed. `expects_continue` is set once the head is in and the client is waiting
+    /// for `100 Continue` before sending the body.
+    Incomplete { expects_continue: bool },
+    /// A whole request, taking up the first `len` bytes of the buffer
+    Complete { request: Request, len: usize },
+}
+
+enum Framing {
+    Empty,
+    Length(usize),
+    Chunked,
+}
+
+/// Parses a request from the start of `buf` without blocking, for connections that are read as data arrives.
+/// Anything after the request, like a pipelined next one, is left alone.
+pub fn parse_request(buf: &[u8], peer: SocketAddr, max_body_size: usize) -> Result<Parsed, Error> {
+    let Some(head_len) = head_len(buf) else {
+        if buf.len() > MAX_HEAD_SIZE {
+            return Err(Error::BadRequest("Request head too large".into()));
+        }
+        return Ok(Parsed::Incomplete {
+            expects_continue: false,
+        });
+    };
+
+    let mut request = read_head(&mut &buf[..head_len], peer)?
+        .ok_or_else(|| Error::BadRequest("Invalid request line".into()))?;
+    let body = &buf[head_len..];
+
+    let framing = framing(&request, max_body_size)?;
+    let complete = match framing {
+        Framing::Empty => Some((Vec::new(), 0)),
+        Framing::Length(len) => (body.len() >= len).then(|| (body[..len].to_vec(), len)),
+        Framing::Chunked => parse_chunked_body(body, max_body_size)?,
+    };
+
+    Ok(match complete {
+        Some((body, body_len)) => {
+            request.body = body;
+            Parsed::Complete {
+                request,
+                len: head_len + body_len,
+            }
+        }
+        None => Parsed::Incomplete {
+            expects_continue: expects_continue(&request),
+        },
+    })
+}
+
+/// Where the blank line ending the request line and headers is, if it has arrived
+fn head_len(buf: &[u8]) -> Option<usize> {
+    let window = &buf[..buf.len().min(MAX_HEAD_SIZE)];
+    window.iter().enumerate().find_map(|(i, &b)| {
+        if b != b'\n' {
+            return None;
+        }
+        match &window[i + 1..] {
+            [b'\n', ..] => Some(i + 2),
+            [b'\r', b'\n', ..] => Some(i + 3),
+            _ => None,
+        }
+    })
+}
+
+/// Decodes a chunked body from the start of `buf`. Returns `None` until the last chunk and trailers are in.
+fn parse_chunked_body(buf: &[u8], max_body_size: usize) -> Result<Option<(Vec<u8>, usize)>, Error> {
+    let mut chunks = Vec::new();
+    let mut size_total = 0;
+    let mut pos = 0;
+
+    loop {
+        let Some(line) = take_line(buf, &mut pos)? else {
+            return Ok(None);
+        };
+        let size = line.split(';').next().unwrap_or_default().trim();
+        let size = usize::from_str_radix(size, 16)
+            .map_err(|_| Error::BadRequest("Invalid chunk size".into()))?;
+
+        if size == 0 {
+            break;
+        }
+
+        size_total = size.saturating_add(size_total);
+        if size_total > max_body_size {
+            return Err(Error::PayloadTooLarge);
+        }
+        if buf.len() < pos + size {
+            return Ok(None);
+        }
+        chunks.push(pos..pos + size);
+        pos += size;
+
+        match take_line(buf, &mut pos)? {
+            None => return Ok(None),
+            Some(line) if !line.is_empty() => {
+                return Err(Error::BadRequest("Malformed chunk".into()));
+            }
+            Some(_) => {}
+        }
+    }
+
+    // Trailer fields aren't used for anything, but must be consumed
+    for _ in 0..=MAX_HEADERS {
+        match take_line(buf, &mut pos)? {
+            None => return Ok(None),
+            Some("") => {
+                let body = chunks.into_iter().flat_map(|c| &buf[c]).copied().collect();
+                return Ok(Some((body, pos)));
+            }
+            Some(_) => {}
+        }
+    }
+    Err(Error::BadRequest("Too many headers".into()))
+}
+
+/// Takes the line starting at `pos` without its terminator and moves past it. Returns `None` if it isn't complete yet.
+fn take_line<'a>(buf: &'a [u8], pos: &mut usize) -> Result<Option<&'a str>, Error> {
+    let rest = &buf[*pos..];
+    let Some(end) = rest.iter().position(|&b| b == b'\n') else {
+        if rest.len() as u64 > MAX_LINE_LENGTH {
+            return Err(Error::BadRequest("Line too long or truncated".into()));
+        }
+        return Ok(None);
+    };
+    *pos += end + 1;
+
+    let line = rest[..end].strip_suffix(b"\r").unwrap_or(&rest[..end]);
+    std::str::from_utf8(line)
+        .map(Some)
+        .map_err(|_| Error::BadRequest("Request is not valid UTF-8".into()))
+}
+
+/// Reads the request line and headers
+fn read_head(reader: &mut impl BufRead, peer: SocketAddr) -> Result<Option<Request>, Error> {
+    let Some(request_line) = read_line(reader)? else {
+        return Ok(None);
+    };
+
+    let mut parts = request_line.splitn(3, ' ');
+    let (Some(method), Some(target), Some(version)) = (parts.next(), parts.next(), parts.next()) else {
+        return Err(Error::BadRequest("Invalid request line".into()));
+    };
+
+    if method.is_empty() || !method.bytes().all(|b| b.is_ascii_uppercase()) {
+        return Err(Error::BadRequest("Invalid method".into()));
+    }
+
+    let http_1_0 = match version {
+        "HTTP/1.1" => false,
+        "HTTP/1.0" => true,
+        _ => return Err(Error::BadRequest("Unsupported HTTP version".into())),
+    };
+
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    if !path.starts_with('/') {
+        return Err(Error::BadRequest("Invalid request target".into()));
+    }
+
+    let headers = read_headers(reader)?;
+
+    Ok(Some(Request {
+        peer,
+        method: method.to_string(),
+        target: target.to_string(),
+        path: path.to_string(),
+        query: parse_query(query),
+        http_1_0,
+        headers,
+        body: Vec::new(),
+    }))
+}
+
+fn read_headers(reader: &mut impl BufRead) -> Result<Vec<(String, String)>, Error> {
+    let mut headers = Vec::new();
+
+    loop {
+        let line = read_line(reader)?.ok_or_else(|| Error::BadRequest("Unexpected EOF".into()))?;
+        if line.is_empty() {
+            return Ok(headers);
+        }
+
+        if line.starts_with([' ', '\t']) {
+            return Err(Error::BadRequest("Folded headers are not supported".into()));
+        }
+
+        let (name, value) = line
+            .split_once(':')
+            .filter(|(name, _)| !name.is_empty() && !name.contains([' ', '\t']))
+            .ok_or_else(|| Error::BadRequest("Malformed header".into()))?;
+
+        headers.push((name.to_ascii_lowercase(), value.trim().to_string()));
+
+        if headers.len() > MAX_HEADERS {
+            return Err(Error::BadRequest("Too many headers".into()));
+        }
+    }
+}
+
+/// Works out how the body is delimited from the headers, rejecting ambiguous or oversized ones
+fn framing(request: &Request, max_body_size: usize) -> Result<Framing, Error> {
+    let chunked = match request.header("transfer-encoding") {
+        Some(te) if te.eq_ignore_ascii_case("chunked") => true,
+        Some(_) => return Err(Error::BadRequest("Unsupported Transfer-Encoding".into())),
+        None => false,
+    };
+
+    let mut lengths = request
+        .headers
+        .iter()
+        .filter(|(name, _)| name == "content-length")
+        .flat_map(|(_, value)| value.split(','))
+        .map(str::trim);
+    let content_length = match lengths.next() {
+        // Both headers at once is a request smuggling vector
+        Some(_) if chunked => {
+            return Err(Error::BadRequest("Both Content-Length and Transfer-Encoding given".into()));
+        }
+        Some(value) => {
+            // So is a repeat that disagrees, as whatever is in front may have gone by the other one
+            if lengths.any(|other| other != value) {
+                return Err(Error::BadRequest("Conflicting Content-Length values".into()));
+            }
+            value
+                .parse::<usize>()
+                .ok()
+                .filter(|_| value.bytes().all(|b| b.is_ascii_digit()))
+                .ok_or_else(|| Error::BadRequest("Invalid Content-Length".into()))?
+        }
+        None => 0,
+    };
+
+    if chunked {
+        Ok(Framing::Chunked)
+    } else if content_length == 0 {
+        Ok(Framing::Empty)
+    } else if content_length > max_body_size {
+        Err(Error::PayloadTooLarge)
+    } else {
+        Ok(Framing::Length(content_length))
+    }
+}
+
+fn expects_continue(request: &Request) -> bool {
+    request
+        .header("expect")
+        .is_some_and(|e| e.eq_ignore_ascii_case("100-continue"))
+}
+
+/// Reads a CRLF (or bare LF) terminated line without the terminator. Returns `None` on EOF.
+fn read_line(reader: &mut impl BufRead) -> Result<Option<String>, Error> {
+    let mut line = Vec::new();
+    let read = reader.take(MAX_LINE_LENGTH).read_until(b'\n', &mut line)?;
+
+    if read == 0 {
+        return Ok(None);
+    }
+    if line.pop() != Some(b'\n') {
+        return Err(Error::BadRequest("Line too long or truncated".into()));
+    }
+    if line.last() == Some(&b'\r') {
+        line.pop();
+    }
+
+    String::from_utf8(line)
+        .map(Some)
+        .map_err(|_| Error::BadRequest("Request is not valid UTF-8".into()))
+}
+
+fn parse_query(query: &str) -> Vec<(String, String)> {
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (decode(name, true), decode(value, true))
+        })
+        .collect()
+}
+
+/// Decodes a path segment. A `+` stays one there, it only stands for a space in query strings.
+pub fn percent_decode(input: &str) -> String {
+    decode(input, false)
+}
+
+fn decode(input: &str, plus_is_space: bool) -> String {
+    let bytes = input.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' if plus_is_space => decoded.push(b' '),
+            b'%' => match (
+                bytes.get(i + 1).copied().and_then(hex_digit),
+                bytes.get(i + 2).copied().and_then(hex_digit),
+            ) {
+                (Some(high), Some(low)) => {
+                    decoded.push(high << 4 | low);
+                    i += 2;
+                }
+                _ => decoded.push(b'%'),
+            },
+            byte => decoded.push(byte),
+        }
+        i += 1;
+    }
+
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Encodes everything but unreserved characters, as RFC 3986 and RFC 5987 want
+pub fn percent_encode(input: &str) -> String {
+    let mut encoded = String::with_capacity(input.len());
+    for byte in input.bytes() {
+        if byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'.' | b'_' | b'~') {
+            encoded.push(char::from(byte));
+        } else {
+            let _ = write!(encoded, "%{byte:02X}");
+        }
+    }
+    encoded
+}
+
+fn hex_digit(byte: u8) -> Option<u8> {
+    char::from(byte).to_digit(16).map(|d| d as u8)
+}
+
+pub const CONTINUE: &[u8] = b"HTTP/1.1 100 Continue\r\n\r\n";
+
+/// Writes the response. For HEAD requests the body is left out but `Content-Length` still describes it.
+/// 204 and 304 responses have no body and no `Content-Length` at all.
+pub fn write_response(
+    stream: &mut impl Write,
+    response: &Response,
+    head_only: bool,
+    keep_alive: bool,
+) -> io::Result<()> {
+    let body = response.body();
+    let bodiless = response.status.starts_with("204 ") || response.status.starts_with("304 ");
+
+    let mut head = format!(
+        "HTTP/1.1 {}\r\n\
+         Content-Type: {}\r\n",
+        response.status, response.content_type,
+    );
+    if !bodiless {
+        let _ = write!(head, "Content-Length: {}\r\n", body.len());
+    }
+    let _ = write!(
+        head,
+        "Connection: {}\r\n",
+        if keep_alive { "keep-alive" } else { "close" }
+    );
+    for (name, value) in &response.headers {
+        head.push_str(name);
+        head.push_str(": ");
+        head.push_str(value);
+        head.push_str("\r\n");
+    }
+    head.push_str("\r\n");
+
+    stream.write_all(head.as_bytes())?;
+    if !head_only && !bodiless {
+        stream.write_all(body)?;
+    }
+    stream.flush()
+}
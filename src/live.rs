@@ -0,0 +1,284 @@
+use crate::error::Error;
+use crate::subtitle::LiveCaptions;
+use crate::{config, datetime, digest, gemini, log, metrics, webhook};
+use miniserde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const CAPTION_POLL_INTERVAL: Duration = Duration::from_secs(30);
+const STOP_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+pub const DEFAULT_SUMMARY_INTERVAL_MINUTES: u64 = 5;
+const MAX_SUMMARY_INTERVAL_MINUTES: u64 = 24 * 60;
+const FINISHED_JOB_RETENTION: Duration = Duration::from_hours(1);
+const MAX_CONSECUTIVE_FAILURES: u32 = 5;
+
+const LIVE_PROMPT_NOTE: &str = "The transcript comes from a live stream that is still in progress. \
+    Summarize what has been said so far and don't speculate about how it ends.";
+
+pub struct LlmSettings {
+    pub api_key: String,
+    pub model: String,
+    pub system_prompt: String,
+}
+
+#[derive(Serialize, Clone)]
+pub struct JobStatus {
+    id: String,
+    state: &'static str,
+    video_name: String,
+    summary: String,
+    subtitles: String,
+    summarized_at: Option<String>,
+    error: Option<String>,
+}
+
+impl JobStatus {
+    pub fn drop_subtitles(&mut self) {
+        self.subtitles.clear();
+    }
+}
+
+struct Job {
+    stop: AtomicBool,
+    status: Mutex<JobStatus>,
+    finished_at: Mutex<Option<Instant>>,
+}
+
+impl Job {
+    fn update(&self, f: impl FnOnce(&mut JobStatus)) {
+        f(&mut self.status.lock().unwrap_or_else(PoisonError::into_inner));
+    }
+
+    fn finish(&self, state: &'static str) {
+        self.update(|status| status.state = state);
+        *self
+            .finished_at
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner) = Some(Instant::now());
+    }
+
+    fn is_finished(&self) -> bool {
+        self.finished_at
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .is_some()
+    }
+
+    fn finished_long_ago(&self) -> bool {
+        self.finished_at
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .is_some_and(|t| t.elapsed() > FINISHED_JOB_RETENTION)
+    }
+}
+
+static JOBS: Mutex<BTreeMap<String, Arc<Job>>> = Mutex::new(BTreeMap::new());
+
+/// Starts a rolling summary of an ongoing live stream, re-summarized every `interval_minutes`
+/// until the stream ends or the job is stopped. Returns the job id. `webhook` hears about the job
+/// ending, with its last summary.
+pub fn start(
+    video_url: &str,
+    language: &str,
+    settings: LlmSettings,
+    interval_minutes: u64,
+    webhook: Option<webhook::Target>,
+) -> Result<String, Error> {
+    if !(1..=MAX_SUMMARY_INTERVAL_MINUTES).contains(&interval_minutes) {
+        return Err(Error::BadRequest(format!(
+            "interval_minutes must be between 1 and {MAX_SUMMARY_INTERVAL_MINUTES}"
+        )));
+    }
+    {
+        let mut jobs = JOBS.lock().unwrap_or_else(PoisonError::into_inner);
+        jobs.retain(|_, job| !job.finished_long_ago());
+        if jobs.values().filter(|job| !job.is_finished()).count()
+            >= config::get().limits.max_live_jobs
+        {
+            return Err(Error::TooManyJobs);
+        }
+    }
+
+    let captions = LiveCaptions::open(video_url, language)?;
+    if !captions.is_live() {
+        return Err(Error::NotLive);
+    }
+
+    // Whoever has the id can read the job's summary and stop it, so it has to be unguessable
+    let id = digest::secure_random_hex(8)?;
+    let job = Arc::new(Job {
+        stop: AtomicBool::new(false),
+        status: Mutex::new(JobStatus {
+            id: id.clone(),
+            state: "running",
+            video_name: captions.title().to_string(),
+            summary: String::new(),
+            subtitles: captions.transcript(),
+            summarized_at: None,
+            error: None,
+        }),
+        finished_at: Mutex::new(None),
+    });
+
+    JOBS.lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .insert(id.clone(), Arc::clone(&job));
+
+    let interval = Duration::from_mins(interval_minutes);
+    let job_id = id.clone();
+    let video_url = video_url.to_string();
+    thread::spawn(move || {
+        log::scope("job_id", &job_id, || {
+            run(&job, captions, &settings, interval);
+            notify(webhook, &video_url, &job);
+        });
+    });
+
+    Ok(id)
+}
+
+pub fn status(id: &str) -> Result<JobStatus, Error> {
+    let job = find(id)?;
+    let status = job
+        .status
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .clone();
+    Ok(status)
+}
+
+/// Asks the job to stop. It finishes within a second and keeps its last summary.
+pub fn stop(id: &str) -> Result<JobStatus, Error> {
+    find(id)?.stop.store(true, Ordering::Relaxed);
+    status(id)
+}
+
+/// Asks every running job to stop, for shutdown. Returns how many were running.
+pub fn stop_all() -> usize {
+    let running: Vec<_> = JOBS
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .values()
+        .filter(|job| !job.is_finished())
+        .cloned()
+        .collect();
+
+    for job in &running {
+        job.stop.store(true, Ordering::Relaxed);
+    }
+    running.len()
+}
+
+fn find(id: &str) -> Result<Arc<Job>, Error> {
+    JOBS.lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .get(id)
+        .cloned()
+        .ok_or(Error::NotFound)
+}
+
+fn notify(target: Option<webhook::Target>, video_url: &str, job: &Job) {
+    if target.is_none() {
+        return;
+    }
+    let status = job
+        .status
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .clone();
+    let event = match status.state {
+        "finished" => "live.finished",
+        "stopped" => "live.stopped",
+        _ => "live.failed",
+    };
+    webhook::send(
+        target,
+        webhook::Payload {
+            video_name: Some(status.video_name),
+            summary: Some(status.summary).filter(|s| !s.is_empty()),
+            job_id: Some(status.id),
+            error: status.error,
+            ..webhook::Payload::new(event, video_url)
+        },
+    );
+}
+
+fn run(job: &Job, mut captions: LiveCaptions, settings: &LlmSettings, interval: Duration) {
+    let live_prompt = format!("{}\n\n{LIVE_PROMPT_NOTE}", settings.system_prompt);
+    let mut last_summary: Option<Instant> = None;
+    let mut summarized_len = 0;
+    let mut summary_failures = 0;
+    let mut poll_failures = 0;
+
+    loop {
+        let transcript = captions.transcript();
+        let ended = !captions.is_live();
+        let due = last_summary.is_none_or(|t| t.elapsed() >= interval);
+
+        if (due || ended) && transcript.len() > summarized_len {
+            let prompt = if ended {
+                &settings.system_prompt
+            } else {
+                &live_prompt
+            };
+
+            let result = log::stage("llm_call", &metrics::LLM_CALL, || {
+                gemini::summarize(&settings.api_key, &settings.model, prompt, &transcript)
+            });
+            match result {
+                Ok(summary) => {
+                    summary_failures = 0;
+                    job.update(|status| {
+                        status.summary = summary;
+                        status.summarized_at = Some(datetime::format_rfc3339(datetime::now_unix()));
+                        status.error = None;
+                    });
+                }
+                Err(e) => {
+                    summary_failures += 1;
+                    log::warn!("Live summary failed"; error = e);
+                    job.update(|status| status.error = Some(Error::from(e).to_string()));
+                }
+            }
+
+            last_summary = Some(Instant::now());
+            summarized_len = transcript.len();
+        }
+
+        job.update(|status| {
+            status.video_name = captions.title().to_string();
+            status.subtitles = transcript;
+        });
+
+        if ended {
+            job.finish("finished");
+            return;
+        }
+        if summary_failures >= MAX_CONSECUTIVE_FAILURES || poll_failures >= MAX_CONSECUTIVE_FAILURES
+        {
+            job.finish("failed");
+            return;
+        }
+
+        let waiting_since = Instant::now();
+        while waiting_since.elapsed() < CAPTION_POLL_INTERVAL {
+            if job.stop.load(Ordering::Relaxed) {
+                job.finish("stopped");
+                return;
+            }
+            thread::sleep(STOP_CHECK_INTERVAL);
+        }
+
+        match captions.poll() {
+            Ok(_) => poll_failures = 0,
+            Err(e) => {
+                poll_failures += 1;
+                log::warn!("Live caption poll failed"; error = e);
+                job.update(|status| status.error = Some(Error::from(e).to_string()));
+            }
+        }
+    }
+}
@@ -1,15 +1,49 @@
+mod compare;
+mod config;
+mod cookies;
+mod cors;
+mod datetime;
+mod deadline;
+mod digest;
+mod error;
+mod event_loop;
+mod export;
+mod feed;
 mod gemini;
+mod history;
+mod http;
+mod live;
+mod log;
+mod markdown;
+mod metrics;
+mod parallel;
+mod pdf;
+mod permalink;
+mod router;
+mod shutdown;
+mod subscriptions;
 mod subtitle;
-
+#[cfg(any(feature = "native-tls", feature = "rustls-tls"))]
+mod tls;
+mod webhook;
+mod zip;
+
+use crate::config::Config;
+use crate::error::Error;
+use crate::event_loop::{Completions, EventLoop, Job};
+use crate::http::{Body, Request, Response};
+use crate::router::{Next, Params, Router};
 use crate::subtitle::get_video_data;
 use flume::{Receiver, bounded};
-use miniserde::{Deserialize, Serialize, json};
-use std::env;
-use std::io::{self, BufRead, BufReader, Read, Write};
-use std::net::{SocketAddr, TcpListener, TcpStream};
+use miniserde::{Deserialize, Serialize};
+use std::io;
+use std::net::TcpListener;
+use std::path::Path;
 use std::sync::Arc;
+use std::sync::atomic::Ordering;
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
+use std::{env, process};
 
 #[derive(Deserialize)]
 struct SummarizeRequest {
@@ -20,6 +54,10 @@ struct SummarizeRequest {
     language: Option<String>,
     dry_run: bool,
     transcript_only: bool,
+    /// Also return the summary rendered to sanitized HTML
+    summary_html: Option<bool>,
+    /// Overrides the configured webhook for this request
+    webhook_url: Option<String>,
 }
 
 #[derive(Serialize)]
@@ -27,37 +65,57 @@ struct SummarizeResponse {
     summary: String,
     subtitles: String,
     video_name: String,
+    /// Set when the summary was saved to the server-side history
+    id: Option<String>,
+    summary_html: Option<String>,
+    /// Where a finished live stream has no captions (yet), which the summary can't cover
+    caption_gaps: Vec<subtitle::Gap>,
 }
 
-struct WorkItem {
-    stream: TcpStream,
-    addr: SocketAddr,
+#[derive(Deserialize)]
+struct CompareRequest {
+    urls: Vec<String>,
+    api_key: Option<String>,
+    model: Option<String>,
+    /// Used when the videos have to be summarized one by one before comparing
+    system_prompt: Option<String>,
+    /// What the comparison should concentrate on, like a question the videos answer
+    focus: Option<String>,
+    language: Option<String>,
+    summary_html: Option<bool>,
 }
 
-struct ServerConfig {
-    addr: String,
-    num_workers: usize,
-    read_timeout: Duration,
-    write_timeout: Duration,
-    max_body_size: usize,
+#[derive(Serialize)]
+struct CompareResponse {
+    synthesis: String,
+    synthesis_html: Option<String>,
+    videos: Vec<ComparedVideo>,
+    /// Whether the videos were too long to compare directly and were summarized first
+    summarized_first: bool,
 }
 
-impl ServerConfig {
-    fn from_env() -> Self {
-        let ip = env::var("TLDR_IP").unwrap_or_else(|_| "0.0.0.0".into());
-        let port = env::var("TLDR_PORT").unwrap_or_else(|_| "8000".into());
+#[derive(Serialize)]
+struct ComparedVideo {
+    url: String,
+    video_name: String,
+    /// Set when the videos were summarized first
+    summary: Option<String>,
+}
 
-        Self {
-            addr: format!("{ip}:{port}"),
-            num_workers: env::var("TLDR_WORKERS")
-                .ok()
-                .and_then(|s| s.parse().ok())
-                .unwrap_or(4),
-            read_timeout: Duration::from_secs(15),
-            write_timeout: Duration::from_secs(15),
-            max_body_size: 10 * 1024 * 1024,
-        }
-    }
+#[derive(Deserialize)]
+struct LiveRequest {
+    url: String,
+    api_key: Option<String>,
+    model: Option<String>,
+    system_prompt: Option<String>,
+    language: Option<String>,
+    interval_minutes: Option<u64>,
+    webhook_url: Option<String>,
+}
+
+#[derive(Serialize)]
+struct LiveResponse {
+    job_id: String,
 }
 
 struct StaticResource {
@@ -74,6 +132,10 @@ macro_rules! static_resource {
     };
 }
 
+const DEFAULT_HISTORY_PAGE: usize = 20;
+const MAX_HISTORY_PAGE: usize = 100;
+const FEED_ENTRIES: usize = 50;
+
 static_resource!(HTML_RESOURCE, "index.html", "text/html; charset=utf-8");
 static_resource!(CSS_RESOURCE, "style.css", "text/css; charset=utf-8");
 static_resource!(
@@ -83,269 +145,734 @@ static_resource!(
 );
 
 fn main() -> io::Result<()> {
-    let config = Arc::new(ServerConfig::from_env());
+    let config = load_config();
+    let server = &config.server;
+    log::init(config.log_level(), config.log_format());
+
+    if let Some(path) = &config.youtube.cookies_file {
+        let count = subtitle::load_cookies(Path::new(path)).map_err(io::Error::other)?;
+        log::info!("Loaded YouTube cookies"; count = count, path = path);
+    }
+
+    if let Some(path) = &config.history.file {
+        let count = history::open(Path::new(path))?;
+        log::info!("Loaded summary history"; count = count, path = path);
+    }
+
+    subscriptions::start(
+        config.subscriptions.channels.clone(),
+        config.subscriptions.interval_minutes,
+        Box::new(summarize_upload),
+    );
 
-    let listener = TcpListener::bind(&config.addr)?;
-    listener.set_nonblocking(false)?; // Better performance
+    if !server.cors_origins.is_empty() {
+        log::info!("Allowing cross-origin requests"; origins = server.cors_origins.join(","));
+        cors::configure(cors::CorsConfig {
+            origins: server.cors_origins.clone(),
+            credentials: server.cors_credentials,
+        });
+    }
 
-    println!("✅ Server started at http://{}", config.addr);
-    println!("✅ Spawning {} worker threads", config.num_workers);
+    let scheme = if setup_tls(config)? { "https" } else { "http" };
 
-    let (sender, receiver) = bounded(100);
+    let addr = server.addr();
+    let listener = TcpListener::bind(&addr)?;
+    let local_addr = listener.local_addr()?;
 
-    for id in 0..config.num_workers {
-        let receiver = receiver.clone();
-        let config = Arc::clone(&config);
-        thread::spawn(move || worker(id, &receiver, &config));
+    #[cfg(any(feature = "native-tls", feature = "rustls-tls"))]
+    if let Some(port) = server.redirect_port.filter(|_| tls::is_enabled()) {
+        let redirect_listener = TcpListener::bind((server.ip.as_str(), port))?;
+        let https_port = local_addr.port();
+        log::info!("Redirecting HTTP to HTTPS"; port = port);
+        thread::spawn(move || tls::redirect_to_https(&redirect_listener, https_port));
     }
 
-    println!("▶️ Ready to accept requests");
-
-    for stream in listener.incoming() {
-        match stream {
-            Ok(stream) => {
-                let addr = match stream.peer_addr() {
-                    Ok(addr) => addr,
-                    Err(e) => {
-                        eprintln!("❌ Failed to get peer address: {e}");
-                        continue;
-                    }
-                };
-
-                let _ = stream.set_nodelay(true);
-                let _ = stream.set_read_timeout(Some(config.read_timeout));
-                let _ = stream.set_write_timeout(Some(config.write_timeout));
-
-                let work_item = WorkItem { stream, addr };
-
-                if sender.try_send(work_item).is_err() {
-                    eprintln!("⚠️ Queue full, rejecting connection from {addr}");
-                }
-            }
-            Err(e) => {
-                eprintln!("❌ Accept failed: {e}");
-            }
-        }
+    log::info!("Server started"; url = format!("{scheme}://{addr}"), workers = server.workers);
+
+    let queue_capacity = config.limits.queue_capacity;
+    let (jobs, job_queue) = bounded(queue_capacity);
+    metrics::init(server.workers, queue_capacity);
+
+    let router = Arc::new(routes());
+    let mut event_loop = EventLoop::new(listener, config, Arc::clone(&router), jobs)?;
+
+    for id in 0..server.workers {
+        let job_queue = job_queue.clone();
+        let router = Arc::clone(&router);
+        let completions = event_loop.completions();
+        thread::spawn(move || worker(id, &job_queue, &router, &completions));
     }
+
+    shutdown::install(local_addr);
+
+    log::info!("Ready to accept requests");
+    event_loop.run();
+
+    log::info!("Shutting down, finishing in-flight requests"; timeout_secs = server.shutdown_timeout_secs);
+
+    let live_jobs = live::stop_all();
+    if live_jobs > 0 {
+        log::info!("Stopped live summary jobs"; count = live_jobs);
+    }
+
+    let open = event_loop.drain(Instant::now() + server.shutdown_timeout());
+    if open > 0 {
+        log::warn!(
+            "Shutdown timed out, aborting connections";
+            open = open,
+            queued = job_queue.len(),
+        );
+    } else {
+        log::info!("All requests finished");
+    }
+
     Ok(())
 }
 
-fn worker(id: usize, receiver: &Receiver<WorkItem>, config: &Arc<ServerConfig>) {
-    println!("   Worker {id} started");
+/// Reads the command line, config file and environment. Exits with a message on anything invalid,
+/// so a typo never silently falls back to a default.
+fn load_config() -> &'static Config {
+    let exit = |e: config::Error| -> ! {
+        eprintln!("{e}");
+        process::exit(2)
+    };
+
+    let args = config::Args::parse(env::args().skip(1)).unwrap_or_else(|e| exit(e));
+    if args.help {
+        println!("{}", config::USAGE);
+        process::exit(0);
+    }
+
+    let config = Config::load(args.config_file.as_deref()).unwrap_or_else(|e| exit(e));
+    if args.print_config {
+        println!("{}", config.to_pretty_json());
+        process::exit(0);
+    }
+
+    config::init(config)
+}
+
+/// Turns on TLS if a certificate is configured. Returns whether it's on.
+#[cfg(any(feature = "native-tls", feature = "rustls-tls"))]
+fn setup_tls(config: &Config) -> io::Result<bool> {
+    // Validation already made sure both or neither are set
+    let (Some(cert), Some(key)) = (&config.server.tls_cert, &config.server.tls_key) else {
+        return Ok(false);
+    };
+    tls::configure(cert.into(), key.into())?;
+    log::info!("Loaded TLS certificate"; path = cert);
+    Ok(true)
+}
 
-    let mut buffer = Vec::with_capacity(4096);
+#[cfg(not(any(feature = "native-tls", feature = "rustls-tls")))]
+fn setup_tls(config: &Config) -> io::Result<bool> {
+    if config.server.tls_cert.is_some() || config.server.tls_key.is_some() {
+        return Err(io::Error::other(
+            "This build has no TLS support, enable the native-tls or rustls-tls feature",
+        ));
+    }
+    Ok(false)
+}
 
-    while let Ok(mut work_item) = receiver.recv() {
-        buffer.clear();
+fn worker(id: usize, job_queue: &Receiver<Job>, router: &Router, completions: &Completions) {
+    log::debug!("Worker started"; worker = id);
 
-        if let Err(e) = handle_request(&mut work_item.stream, config, &mut buffer) {
-            eprintln!("❌ Worker {} error handling {}: {}", id, work_item.addr, e);
-            let _ = write_error_response(
-                &mut work_item.stream,
-                "500 Internal Server Error",
-                &e.to_string(),
-            );
-        }
+    while let Ok(job) = job_queue.recv() {
+        metrics::QUEUED.fetch_sub(1, Ordering::Relaxed);
+        let done = shutdown::track(|| job.run(router));
+        completions.send(done);
     }
 
-    println!("   Worker {id} shutting down");
+    log::debug!("Worker shutting down"; worker = id);
 }
 
-fn handle_request(
-    stream: &mut TcpStream,
-    config: &ServerConfig,
-    buffer: &mut Vec<u8>,
-) -> io::Result<()> {
-    let mut reader = BufReader::with_capacity(8192, stream.try_clone()?);
+fn routes() -> Router {
+    Router::new()
+        .middleware(cors::middleware)
+        .middleware(log_errors)
+        .middleware(require_admin)
+        .get("/", |_, _| Ok(static_response(&HTML_RESOURCE)))
+        .inline()
+        .get("/index.html", |_, _| Ok(static_response(&HTML_RESOURCE)))
+        .inline()
+        .get("/style.css", |_, _| Ok(static_response(&CSS_RESOURCE)))
+        .inline()
+        .get("/script.js", |_, _| Ok(static_response(&JS_RESOURCE)))
+        .inline()
+        .post("/api/summarize", summarize)
+        .post("/api/compare", compare_videos)
+        .post("/api/live", start_live)
+        .get("/api/jobs/{id}", job_status)
+        .inline()
+        .delete("/api/jobs/{id}", stop_job)
+        .inline()
+        .post("/api/export", export_summary)
+        .get("/api/history", list_history)
+        // Before `/api/history/{id}`, which would take "export" as an id
+        .get("/api/history/export", export_history)
+        .get("/api/history/{id}", |_, params| {
+            let entry = history::get(params.get("id").unwrap_or_default())?;
+            Ok(Response::json("200 OK", &history::Details::from(entry)))
+        })
+        .delete("/api/history/{id}", |_, params| {
+            history::delete(params.get("id").unwrap_or_default())?;
+            Ok(Response::new("204 No Content", "text/plain", Vec::new()))
+        })
+        .get("/api/history/{id}/export", export_entry)
+        .post("/api/history/{id}/share", share_summary)
+        .delete("/api/history/{id}/share", |_, params| {
+            history::unshare(params.get("id").unwrap_or_default())?;
+            Ok(Response::new("204 No Content", "text/plain", Vec::new()))
+        })
+        .get("/s/{token}", shared_summary)
+        .get("/feeds/all.xml", |request, _| {
+            let entries = history::recent(FEED_ENTRIES, |_| true)?;
+            serve_feed(request, "YouTubeTLDR", "Latest video summaries", &entries)
+        })
+        .get("/feeds/summaries.xml", |request, _| {
+            let entries = history::recent(FEED_ENTRIES, |entry| entry.channel.is_some())?;
+            let description = "Latest summaries of uploads from followed channels";
+            serve_feed(request, "YouTubeTLDR subscriptions", description, &entries)
+        })
+        .get("/feeds/channels/{file}", channel_feed)
+        .get("/healthz", |_, _| Ok(Response::new("200 OK", "text/plain", b"ok".to_vec())))
+        .inline()
+        .get("/readyz", |_, _| Ok(readiness()))
+        .inline()
+        .get("/metrics", |_, _| {
+            let body = metrics::render(&metrics::Load::current());
+            Ok(Response::new("200 OK", "text/plain; version=0.0.4", body.into_bytes()))
+        })
+        .inline()
+}
 
-    let mut request_line = String::new();
-    reader.read_line(&mut request_line)?;
+fn log_errors(request: &Request, next: Next) -> Result<Response, Error> {
+    next(request).inspect_err(|e| {
+        let level = if e.status_code() >= 500 {
+            log::Level::Error
+        } else {
+            log::Level::Warn
+        };
+        log::log!(
+            level,
+            "Request failed";
+            method = request.method,
+            path = request.target,
+            peer = request.peer,
+            code = e.code(),
+            error = e,
+        );
+    })
+}
 
-    if request_line.is_empty() {
-        return Err(io::Error::new(io::ErrorKind::InvalidData, "Empty request"));
+/// Keeps the history API, which can read, delete and share every stored summary, to requests
+/// bearing `history.admin_token`. The feeds publish the same summaries, so they need it too
+/// unless `history.public_feeds` is on.
+fn require_admin(request: &Request, next: Next) -> Result<Response, Error> {
+    let under = |prefix: &str| {
+        request
+            .path
+            .strip_prefix(prefix)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+    };
+    let is_admin_route =
+        under("/api/history") || (under("/feeds") && !config::get().history.public_feeds);
+    if !is_admin_route {
+        return next(request);
     }
 
-    let parts: Vec<&str> = request_line.split_whitespace().collect();
-    if parts.len() < 2 {
-        return Err(io::Error::new(
-            io::ErrorKind::InvalidData,
-            "Invalid request line",
-        ));
+    let token = config::get()
+        .history
+        .admin_token
+        .as_deref()
+        .ok_or(Error::AdminTokenNotSet)?;
+    let given = request
+        .header("authorization")
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .ok_or(Error::Unauthorized)?;
+    if !digest::constant_time_eq(given.trim().as_bytes(), token.as_bytes()) {
+        return Err(Error::Unauthorized);
     }
+    next(request)
+}
 
-    let method = parts[0];
-    let path = parts[1];
-
-    match method {
-        "GET" => handle_get(path, stream),
-        "POST" if path == "/api/summarize" => {
-            // Read headers
-            let mut headers = Vec::new();
-            let mut content_length = None;
-
-            loop {
-                let mut line = String::new();
-                let bytes_read = reader.read_line(&mut line)?;
-
-                if bytes_read == 0 {
-                    return Err(io::Error::new(
-                        io::ErrorKind::UnexpectedEof,
-                        "Unexpected EOF",
-                    ));
-                }
-
-                if line == "\r\n" || line == "\n" {
-                    break;
-                }
-
-                if line.to_lowercase().starts_with("content-length:")
-                    && let Some(value) = line.split(':').nth(1) {
-                        content_length = value.trim().parse().ok();
-                }
-
-                headers.push(line);
-
-                if headers.len() > 100 {
-                    return Err(io::Error::new(
-                        io::ErrorKind::InvalidData,
-                        "Too many headers",
-                    ));
-                }
-            }
-
-            let content_length = content_length.ok_or_else(|| {
-                io::Error::new(io::ErrorKind::InvalidInput, "Missing Content-Length")
-            })?;
-
-            if content_length > config.max_body_size {
-                return Err(io::Error::new(
-                    io::ErrorKind::InvalidData,
-                    "Request body too large",
-                ));
-            }
-
-            // Read body
-            buffer.clear();
-            buffer.resize(content_length, 0);
-            reader.read_exact(buffer)?;
-
-            // Process
-            let req: SummarizeRequest = json::from_slice(buffer).map_err(|e| {
-                io::Error::new(io::ErrorKind::InvalidData, format!("Invalid JSON: {e}"))
-            })?;
-
-            let response_payload = perform_summary_work(&req)
-                .map_err(|e| io::Error::other(format!("Processing error: {e}")))?;
-
-            let response_body = json::to_vec(&response_payload);
-
-            write_response(stream, "200 OK", "application/json", &response_body)
-        }
-        _ => write_error_response(stream, "405 Method Not Allowed", "Method Not Allowed"),
+fn summarize(request: &Request, _: &Params) -> Result<Response, Error> {
+    let req: SummarizeRequest = request.json()?;
+    let webhook = webhook::target(req.webhook_url.as_deref())?;
+
+    let result = perform_summary_work(&req, None);
+    notify(webhook, &req.url, &result);
+    let mut response_payload = result?;
+    if req.summary_html == Some(true) {
+        response_payload.summary_html = Some(markdown::to_html(&response_payload.summary));
     }
+
+    Ok(Response::json("200 OK", &response_payload))
 }
 
-fn handle_get(path: &str, stream: &mut TcpStream) -> io::Result<()> {
-    let resource = match path {
-        "/" | "/index.html" => Some(&HTML_RESOURCE),
-        "/style.css" => Some(&CSS_RESOURCE),
-        "/script.js" => Some(&JS_RESOURCE),
-        _ => None,
+fn compare_videos(request: &Request, _: &Params) -> Result<Response, Error> {
+    let req: CompareRequest = request.json()?;
+    if !(compare::MIN_VIDEOS..=compare::MAX_VIDEOS).contains(&req.urls.len()) {
+        return Err(Error::BadRequest(format!(
+            "urls must list {} to {} videos",
+            compare::MIN_VIDEOS,
+            compare::MAX_VIDEOS
+        )));
+    }
+
+    // The individual summaries fall back to a built-in prompt, the comparison has its own
+    let video_prompt = req
+        .system_prompt
+        .as_deref()
+        .or_else(|| config::get().llm.system_prompt.as_deref())
+        .unwrap_or(compare::VIDEO_PROMPT);
+    let (api_key, model, video_prompt) = llm_settings(
+        req.api_key.as_deref(),
+        req.model.as_deref(),
+        Some(video_prompt),
+    )?;
+
+    let videos = compare::fetch(&req.urls, language(req.language.as_deref()))?;
+    let llm = compare::Llm {
+        api_key,
+        model,
+        video_prompt,
+    };
+    let comparison = compare::compare(&videos, &llm, req.focus.as_deref())?;
+
+    let summarized_first = comparison.summaries.is_some();
+    let mut summaries = comparison.summaries.into_iter().flatten();
+    let response = CompareResponse {
+        synthesis_html: (req.summary_html == Some(true))
+            .then(|| markdown::to_html(&comparison.synthesis)),
+        synthesis: comparison.synthesis,
+        videos: videos
+            .into_iter()
+            .map(|video| ComparedVideo {
+                url: video.url,
+                video_name: video.title,
+                summary: summaries.next(),
+            })
+            .collect(),
+        summarized_first,
     };
+    Ok(Response::json("200 OK", &response))
+}
+
+fn start_live(request: &Request, _: &Params) -> Result<Response, Error> {
+    let req: LiveRequest = request.json()?;
+    let webhook = webhook::target(req.webhook_url.as_deref())?;
+
+    let (api_key, model, system_prompt) = llm_settings(
+        req.api_key.as_deref(),
+        req.model.as_deref(),
+        req.system_prompt.as_deref(),
+    )?;
+    let job_id = live::start(
+        &req.url,
+        language(req.language.as_deref()),
+        live::LlmSettings {
+            api_key: api_key.to_string(),
+            model: model.to_string(),
+            system_prompt: system_prompt.to_string(),
+        },
+        req.interval_minutes
+            .unwrap_or(live::DEFAULT_SUMMARY_INTERVAL_MINUTES),
+        webhook,
+    )?;
+
+    Ok(Response::json("202 Accepted", &LiveResponse { job_id }))
+}
 
-    match resource {
-        Some(res) => write_static_response(stream, res),
-        None => write_error_response(stream, "404 Not Found", "Not Found"),
+fn job_status(request: &Request, params: &Params) -> Result<Response, Error> {
+    let mut status = live::status(params.get("id").unwrap_or_default())?;
+    // Pollers usually only care about the summary, the transcript keeps growing
+    if request.query_param("subtitles") == Some("false") {
+        status.drop_subtitles();
     }
+    Ok(Response::json("200 OK", &status))
 }
 
-fn write_static_response(stream: &mut TcpStream, resource: &StaticResource) -> io::Result<()> {
-    let response = format!(
-        "HTTP/1.1 200 OK\r\n\
-         Content-Type: {}\r\n\
-         Content-Encoding: gzip\r\n\
-         Content-Length: {}\r\n\
-         Cache-Control: public, max-age=3600\r\n\
-         Connection: close\r\n\r\n",
-        resource.content_type,
-        resource.content.len()
-    );
+fn stop_job(_: &Request, params: &Params) -> Result<Response, Error> {
+    let status = live::stop(params.get("id").unwrap_or_default())?;
+    Ok(Response::json("200 OK", &status))
+}
 
-    stream.write_all(response.as_bytes())?;
-    stream.write_all(resource.content)?;
-    stream.flush()
-}
-
-fn write_response(
-    stream: &mut TcpStream,
-    status: &str,
-    content_type: &str,
-    content: &[u8],
-) -> io::Result<()> {
-    let response = format!(
-        "HTTP/1.1 {}\r\n\
-         Content-Type: {}\r\n\
-         Content-Length: {}\r\n\
-         Connection: close\r\n\r\n",
-        status,
-        content_type,
-        content.len()
-    );
+fn list_history(request: &Request, _: &Params) -> Result<Response, Error> {
+    let number = |name: &str, default: usize| {
+        request.query_param(name).map_or(Ok(default), |v| {
+            v.parse()
+                .map_err(|_| Error::BadRequest(format!("{name} must be a whole number")))
+        })
+    };
+    let offset = number("offset", 0)?;
+    let limit = number("limit", DEFAULT_HISTORY_PAGE)?.min(MAX_HISTORY_PAGE);
+
+    let page = history::search(request.query_param("q").unwrap_or_default(), offset, limit)?;
+    Ok(Response::json("200 OK", &page))
+}
 
-    stream.write_all(response.as_bytes())?;
-    stream.write_all(content)?;
-    stream.flush()
+#[derive(Serialize)]
+struct ShareResponse {
+    token: String,
+    path: String,
+}
+
+fn share_summary(_: &Request, params: &Params) -> Result<Response, Error> {
+    if !config::get().history.permalinks {
+        return Err(Error::PermalinksDisabled);
+    }
+    let token = history::share(params.get("id").unwrap_or_default())?;
+    let path = format!("/s/{token}");
+    Ok(Response::json("200 OK", &ShareResponse { token, path }))
 }
 
-fn write_error_response(stream: &mut TcpStream, status: &str, msg: &str) -> io::Result<()> {
-    write_response(stream, status, "text/plain; charset=utf-8", msg.as_bytes())
+fn shared_summary(_: &Request, params: &Params) -> Result<Response, Error> {
+    // Turning permalinks off takes down the ones already shared too
+    if !config::get().history.permalinks {
+        return Err(Error::NotFound);
+    }
+    let entry = history::shared(params.get("token").unwrap_or_default())?;
+
+    let page = permalink::render(&entry);
+    let response = Response::new("200 OK", "text/html; charset=utf-8", page.into_bytes());
+    Ok(permalink::HEADERS
+        .iter()
+        .fold(response, |response, (name, value)| {
+            response.with_header(name, *value)
+        }))
+}
+
+#[derive(Deserialize)]
+struct ExportRequest {
+    url: String,
+    video_name: String,
+    summary: String,
+    subtitles: Option<String>,
+    model: Option<String>,
+    language: Option<String>,
 }
 
-fn perform_summary_work(req: &SummarizeRequest) -> Result<SummarizeResponse, String> {
+/// Exports a summary the client has, like one from its local history
+fn export_summary(request: &Request, _: &Params) -> Result<Response, Error> {
+    let format = export::Format::parse(request.query_param("format"))?;
+    let req: ExportRequest = request.json()?;
+    let entry = history::Entry {
+        video_name: req.video_name,
+        summary: req.summary,
+        subtitles: req.subtitles.unwrap_or_default(),
+        ..history::Entry::new(
+            &req.url,
+            language(req.language.as_deref()),
+            req.model.as_deref(),
+        )?
+    };
+    let name = export::file_name(&entry, format);
+    Ok(download(
+        &name,
+        format.content_type(),
+        export::render(&entry, format)?,
+    ))
+}
+
+fn export_entry(request: &Request, params: &Params) -> Result<Response, Error> {
+    let format = export::Format::parse(request.query_param("format"))?;
+    let entry = history::get(params.get("id").unwrap_or_default())?;
+    let name = export::file_name(&entry, format);
+    Ok(download(
+        &name,
+        format.content_type(),
+        export::render(&entry, format)?,
+    ))
+}
+
+/// The whole history as a zip with one file per summary
+fn export_history(request: &Request, _: &Params) -> Result<Response, Error> {
+    let format = export::Format::parse(request.query_param("format"))?;
+    let archive = export::archive(&history::all()?, format)?;
+    Ok(download("summaries.zip", "application/zip", archive))
+}
+
+fn download(name: &str, content_type: &'static str, body: Vec<u8>) -> Response {
+    Response::new("200 OK", content_type, body)
+        .with_header("Content-Disposition", export::content_disposition(name))
+}
+
+/// Summaries of one channel's videos, at `/feeds/channels/{channel_id}.xml`
+fn channel_feed(request: &Request, params: &Params) -> Result<Response, Error> {
+    let channel_id = params
+        .get("file")
+        .and_then(|file| file.strip_suffix(".xml"))
+        .ok_or(Error::NotFound)?;
+    let entries = history::recent(FEED_ENTRIES, |entry| {
+        entry.channel.as_ref().is_some_and(|c| c.id == channel_id)
+    })?;
+
+    let name = entries
+        .iter()
+        .find_map(|entry| entry.channel.as_ref())
+        .map_or(channel_id, |channel| channel.name.as_str());
+    let title = format!("{name} · YouTubeTLDR");
+    let description = format!("Latest summaries of videos from {name}");
+    serve_feed(request, &title, &description, &entries)
+}
+
+fn serve_feed(
+    request: &Request,
+    title: &str,
+    description: &str,
+    entries: &[history::Entry],
+) -> Result<Response, Error> {
+    let format = feed::Format::parse(request.query_param("format"))?;
+    let base = base_url(request);
+    let url = format!("{base}{}", request.target);
+    let body = feed::Feed {
+        title,
+        description,
+        url: &url,
+        site_url: &format!("{base}/"),
+        entries,
+    }
+    .render(format);
+    Ok(Response::new(
+        "200 OK",
+        format.content_type(),
+        body.into_bytes(),
+    ))
+}
+
+/// Scheme and host the client reached the server at, for links that have to be absolute. A proxy
+/// terminating TLS in front of the server is recognized by `X-Forwarded-Proto`.
+fn base_url(request: &Request) -> String {
+    #[cfg(any(feature = "native-tls", feature = "rustls-tls"))]
+    let tls = tls::is_enabled();
+    #[cfg(not(any(feature = "native-tls", feature = "rustls-tls")))]
+    let tls = false;
+
+    let scheme = if tls || request.header("x-forwarded-proto") == Some("https") {
+        "https"
+    } else {
+        "http"
+    };
+    let host = request
+        .header("host")
+        .filter(|host| {
+            !host.is_empty()
+                && host
+                    .bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || b".-:[]".contains(&b))
+        })
+        .map_or_else(|| config::get().server.addr(), str::to_string);
+    format!("{scheme}://{host}")
+}
+
+#[derive(Serialize)]
+struct Readiness {
+    ready: bool,
+    shutting_down: bool,
+    workers: usize,
+    busy_workers: usize,
+    queued: usize,
+}
+
+/// Not ready while shutting down or when a new request would have to wait behind a long queue
+fn readiness() -> Response {
+    let load = metrics::Load::current();
+    let shutting_down = shutdown::requested();
+    let ready = !shutting_down && load.is_ready();
+
+    let status = if ready { "200 OK" } else { "503 Service Unavailable" };
+    Response::json(
+        status,
+        &Readiness {
+            ready,
+            shutting_down,
+            workers: load.workers,
+            busy_workers: load.busy_workers,
+            queued: load.queued,
+        },
+    )
+}
+
+fn static_response(resource: &StaticResource) -> Response {
+    Response {
+        status: "200 OK",
+        content_type: resource.content_type,
+        headers: vec![
+            ("Content-Encoding", "gzip".into()),
+            ("Cache-Control", "public, max-age=3600".into()),
+        ],
+        body: Body::Static(resource.content),
+    }
+}
+
+fn perform_summary_work(
+    req: &SummarizeRequest,
+    channel: Option<history::Channel>,
+) -> Result<SummarizeResponse, Error> {
     if req.dry_run {
         let test_md = include_str!("./markdown_test.md");
         return Ok(SummarizeResponse {
             summary: test_md.to_string(),
             subtitles: test_md.to_string(),
             video_name: "Dry Run".to_string(),
+            id: None,
+            summary_html: None,
+            caption_gaps: Vec::new(),
         });
     }
 
-    let language = req.language.as_deref().unwrap_or("en");
-    let (transcript, video_name) =
-        get_video_data(&req.url, language).map_err(|e| format!("Transcript error: {e}"))?;
+    let language = language(req.language.as_deref());
+    let video = log::stage("transcript_fetch", &metrics::TRANSCRIPT_FETCH, || {
+        get_video_data(&req.url, language)
+    })?;
 
     if req.transcript_only {
-        return Ok(SummarizeResponse {
-            summary: transcript.clone(),
-            subtitles: transcript,
-            video_name,
+        let mut response = SummarizeResponse {
+            summary: video.transcript.clone(),
+            subtitles: video.transcript,
+            video_name: video.title,
+            id: None,
+            summary_html: None,
+            caption_gaps: video.gaps,
+        };
+        remember(&req.url, language, None, channel, &mut response);
+        return Ok(response);
+    }
+
+    let (api_key, model, system_prompt) = llm_settings(
+        req.api_key.as_deref(),
+        req.model.as_deref(),
+        req.system_prompt.as_deref(),
+    )?;
+
+    let summary = log::stage("llm_call", &metrics::LLM_CALL, || {
+        gemini::summarize(api_key, model, system_prompt, &video.transcript)
+    })?;
+
+    let mut response = SummarizeResponse {
+        summary,
+        subtitles: video.transcript,
+        video_name: video.title,
+        id: None,
+        summary_html: None,
+        caption_gaps: video.gaps,
+    };
+    remember(&req.url, language, Some(model), channel, &mut response);
+    Ok(response)
+}
+
+/// Saves the result to the server-side history if it's enabled. A failure there is only logged,
+/// the summary itself still worked.
+fn remember(
+    url: &str,
+    language: &str,
+    model: Option<&str>,
+    channel: Option<history::Channel>,
+    response: &mut SummarizeResponse,
+) {
+    if !history::is_enabled() {
+        return;
+    }
+
+    let saved = history::Entry::new(url, language, model)
+        .map_err(Error::from)
+        .and_then(|new| {
+            let entry = history::Entry {
+                video_name: response.video_name.clone(),
+                summary: response.summary.clone(),
+                subtitles: response.subtitles.clone(),
+                channel,
+                ..new
+            };
+            let id = entry.id.clone();
+            history::record(entry).map(|()| id)
         });
+    match saved {
+        Ok(id) => response.id = Some(id),
+        Err(e) => log::error!("Failed to save summary to history"; error = e),
     }
+}
 
-    let api_key =
-        req.api_key.as_deref().filter(|k| !k.is_empty()).ok_or(
-            "Missing Gemini API key. Get one here: https://aistudio.google.com/app/apikey",
-        )?;
+/// Summarizes a subscribed channel's upload into the history, using the configured LLM settings
+fn summarize_upload(
+    url: &str,
+    prompt: Option<&str>,
+    language: Option<&str>,
+    channel: history::Channel,
+) -> Result<(), Error> {
+    let req = SummarizeRequest {
+        url: url.to_string(),
+        api_key: config::get().subscriptions.api_key.clone(),
+        model: None,
+        system_prompt: prompt.map(str::to_string),
+        language: language.map(str::to_string),
+        dry_run: false,
+        transcript_only: false,
+        summary_html: None,
+        webhook_url: None,
+    };
+    // Held to the same deadline as an API request, so one stuck video can't stall the schedule
+    let timeout = Duration::from_secs(config::get().server.request_timeout_secs);
+    let result = deadline::until(Some(Instant::now() + timeout), || {
+        perform_summary_work(&req, Some(channel))
+    });
+    notify(webhook::target(None)?, url, &result);
+
+    // Only counts as done once it's in the history, otherwise the next poll would skip it
+    match result?.id {
+        Some(_) => Ok(()),
+        None => Err(Error::Io(io::Error::other(
+            "the summary couldn't be saved to the history",
+        ))),
+    }
+}
 
-    let model = req
-        .model
-        .as_deref()
-        .filter(|m| !m.is_empty())
-        .ok_or("Missing model name")?;
+/// Tells the webhook, if there is one, how the summary went. It fires even when the client
+/// has stopped waiting for the response.
+fn notify(
+    target: Option<webhook::Target>,
+    url: &str,
+    result: &Result<SummarizeResponse, Error>,
+) {
+    if target.is_none() {
+        return;
+    }
+    let event = match result {
+        Ok(response) => webhook::Payload {
+            video_name: Some(response.video_name.clone()),
+            summary: Some(response.summary.clone()),
+            history_id: response.id.clone(),
+            ..webhook::Payload::new("summary.completed", url)
+        },
+        Err(e) => webhook::Payload::failed("summary.failed", url, e),
+    };
+    webhook::send(target, event);
+}
 
-    let system_prompt = req
-        .system_prompt
-        .as_deref()
-        .filter(|p| !p.is_empty())
-        .ok_or("Missing system prompt")?;
+fn language(requested: Option<&str>) -> &str {
+    requested
+        .filter(|l| !l.is_empty())
+        .unwrap_or(&config::get().youtube.default_language)
+}
 
-    let summary = gemini::summarize(api_key, model, system_prompt, &transcript)
-        .map_err(|e| format!("API error: {e}"))?;
+/// Picks the API key, model and system prompt from the request, falling back to the configured ones,
+/// and checks they're all present. The configured API key is only used if `llm.share_api_key` allows it.
+fn llm_settings<'a>(
+    api_key: Option<&'a str>,
+    model: Option<&'a str>,
+    system_prompt: Option<&'a str>,
+) -> Result<(&'a str, &'a str, &'a str), Error> {
+    let defaults = &config::get().llm;
+    let pick = |requested: Option<&'a str>, default: &'a Option<String>| {
+        requested.filter(|v| !v.is_empty()).or(default.as_deref())
+    };
 
-    Ok(SummarizeResponse {
-        summary,
-        subtitles: transcript,
-        video_name,
-    })
+    let shared_key = if defaults.share_api_key { &defaults.api_key } else { &None };
+    let api_key = pick(api_key, shared_key).ok_or(Error::MissingApiKey)?;
+    let model = pick(model, &defaults.model).ok_or(Error::MissingField("model name"))?;
+    let system_prompt =
+        pick(system_prompt, &defaults.system_prompt).ok_or(Error::MissingField("system prompt"))?;
+
+    Ok((api_key, model, system_prompt))
 }
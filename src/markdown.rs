@@ -0,0 +1,899 @@
+//! A small Markdown to HTML renderer for the constructs LLM summaries use: headings, lists (nested,
+//! ordered and task lists), emphasis, code, links, tables, blockquotes and rules. The output is safe
+//! to embed as is: text is escaped, links are limited to the web and email, and the only raw HTML
+//! let through is a handful of formatting tags without attributes.
+
+use std::fmt::Write as _;
+
+/// Converts `markdown` to an HTML fragment
+pub fn to_html(markdown: &str) -> String {
+    let lines: Vec<&str> = markdown.lines().collect();
+    let mut out = String::with_capacity(markdown.len() * 3 / 2);
+    blocks(&lines, &mut out);
+    out
+}
+
+/// Like `to_html`, but well-formed XML, as EPUB needs
+pub fn to_xhtml(markdown: &str) -> String {
+    // Text is escaped, so these only ever come from the renderer itself
+    to_html(markdown)
+        .replace("<br>", "<br/>")
+        .replace("<hr>", "<hr/>")
+        .replace(
+            " checked disabled>",
+            " checked=\"checked\" disabled=\"disabled\"/>",
+        )
+        .replace("checkbox\" disabled>", "checkbox\" disabled=\"disabled\"/>")
+}
+
+/// Escapes text for use in HTML content and quoted attribute values
+pub fn escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    escape_into(&mut out, text);
+    out
+}
+
+fn escape_into(out: &mut String, text: &str) {
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            _ => out.push(c),
+        }
+    }
+}
+
+fn blocks(lines: &[&str], out: &mut String) {
+    let mut i = 0;
+    while i < lines.len() {
+        let line = lines[i];
+        let trimmed = line.trim_start();
+        let rest = &lines[i..];
+
+        i += if trimmed.is_empty() {
+            1
+        } else if indent_of(line) >= 4 && list_marker(trimmed).is_none() {
+            indented_code(rest, out)
+        } else if fence(trimmed).is_some() {
+            fenced_code(rest, out)
+        } else if let Some(level) = heading_level(trimmed) {
+            heading(trimmed, level, out);
+            1
+        } else if is_rule(trimmed) {
+            out.push_str("<hr>\n");
+            1
+        } else if trimmed.starts_with('>') {
+            blockquote(rest, out)
+        } else if list_marker(trimmed).is_some() {
+            list(rest, out)
+        } else if let Some(columns) = table_columns(rest) {
+            table(rest, &columns, out)
+        } else {
+            paragraph(rest, out)
+        };
+    }
+}
+
+/// Whether `line` starts a block that ends a paragraph before it
+fn starts_block(line: &str) -> bool {
+    let trimmed = line.trim_start();
+    fence(trimmed).is_some()
+        || heading_level(trimmed).is_some()
+        || is_rule(trimmed)
+        || trimmed.starts_with('>')
+        || list_marker(trimmed).is_some()
+}
+
+/// Leading whitespace in columns, with tabs counting as 4
+fn indent_of(line: &str) -> usize {
+    line.chars()
+        .map_while(|c| match c {
+            ' ' => Some(1),
+            '\t' => Some(4),
+            _ => None,
+        })
+        .sum()
+}
+
+/// Removes up to `columns` of leading whitespace
+fn strip_indent(line: &str, columns: usize) -> &str {
+    let mut removed = 0;
+    for (i, c) in line.char_indices() {
+        let width = match c {
+            ' ' => 1,
+            '\t' => 4,
+            _ => return &line[i..],
+        };
+        if removed + width > columns {
+            return &line[i..];
+        }
+        removed += width;
+    }
+    ""
+}
+
+fn heading_level(trimmed: &str) -> Option<usize> {
+    let level = trimmed.bytes().take_while(|&b| b == b'#').count();
+    let after = trimmed.as_bytes().get(level);
+    ((1..=6).contains(&level) && matches!(after, None | Some(b' ' | b'\t'))).then_some(level)
+}
+
+fn heading(trimmed: &str, level: usize, out: &mut String) {
+    let text = trimmed[level..].trim();
+    // A closing run of #s is decoration, unless it's glued to the text
+    let without_closing = text.trim_end_matches('#');
+    let text = if without_closing.is_empty() || without_closing.ends_with([' ', '\t']) {
+        without_closing.trim_end()
+    } else {
+        text
+    };
+
+    let _ = write!(out, "<h{level}>");
+    inline(text, out);
+    let _ = writeln!(out, "</h{level}>");
+}
+
+/// Three or more `-`, `*` or `_`, optionally spaced out
+fn is_rule(trimmed: &str) -> bool {
+    let mut chars = trimmed.chars().filter(|c| !c.is_whitespace());
+    let Some(first) = chars.next().filter(|c| matches!(c, '-' | '*' | '_')) else {
+        return false;
+    };
+    let mut count = 1;
+    for c in chars {
+        if c != first {
+            return false;
+        }
+        count += 1;
+    }
+    count >= 3
+}
+
+/// The fence character and length of an opening or closing code fence
+fn fence(trimmed: &str) -> Option<(char, usize)> {
+    let c = trimmed.chars().next().filter(|c| matches!(c, '`' | '~'))?;
+    let len = trimmed.chars().take_while(|&ch| ch == c).count();
+    // Backtick fences can't have backticks in their info string
+    let valid = len >= 3 && (c == '~' || !trimmed[len..].contains('`'));
+    valid.then_some((c, len))
+}
+
+fn fenced_code(lines: &[&str], out: &mut String) -> usize {
+    let indent = indent_of(lines[0]);
+    let opening = lines[0].trim_start();
+    let Some((c, len)) = fence(opening) else {
+        return 0;
+    };
+    let language = opening[len..].split_whitespace().next().unwrap_or_default();
+
+    if language.is_empty() {
+        out.push_str("<pre><code>");
+    } else {
+        out.push_str("<pre><code class=\"language-");
+        escape_into(out, language);
+        out.push_str("\">");
+    }
+
+    let mut consumed = 1;
+    for line in &lines[1..] {
+        consumed += 1;
+        let trimmed = line.trim();
+        if trimmed.len() >= len && trimmed.chars().all(|ch| ch == c) {
+            break;
+        }
+        escape_into(out, strip_indent(line, indent));
+        out.push('\n');
+    }
+
+    out.push_str("</code></pre>\n");
+    consumed
+}
+
+fn indented_code(lines: &[&str], out: &mut String) -> usize {
+    let mut len = lines
+        .iter()
+        .take_while(|line| line.trim().is_empty() || indent_of(line) >= 4)
+        .count();
+    while len > 0 && lines[len - 1].trim().is_empty() {
+        len -= 1;
+    }
+
+    out.push_str("<pre><code>");
+    for line in &lines[..len] {
+        escape_into(out, strip_indent(line, 4));
+        out.push('\n');
+    }
+    out.push_str("</code></pre>\n");
+    len
+}
+
+fn blockquote(lines: &[&str], out: &mut String) -> usize {
+    let mut inner = Vec::new();
+    for line in lines {
+        let trimmed = line.trim_start();
+        if let Some(quoted) = trimmed.strip_prefix('>') {
+            inner.push(quoted.strip_prefix(' ').unwrap_or(quoted));
+        } else if !trimmed.is_empty()
+            && !starts_block(line)
+            && inner.last().is_some_and(|l: &&str| !l.trim().is_empty())
+        {
+            // A lazy continuation of the quoted paragraph
+            inner.push(trimmed);
+        } else {
+            break;
+        }
+    }
+
+    out.push_str("<blockquote>\n");
+    blocks(&inner, out);
+    out.push_str("</blockquote>\n");
+    inner.len()
+}
+
+fn paragraph(lines: &[&str], out: &mut String) -> usize {
+    let len = 1
+        + (1..lines.len())
+            .take_while(|&i| {
+                !lines[i].trim().is_empty()
+                    && !starts_block(lines[i])
+                    && table_columns(&lines[i..]).is_none()
+            })
+            .count();
+
+    let text: Vec<&str> = lines[..len].iter().map(|l| l.trim_start()).collect();
+    out.push_str("<p>");
+    inline(text.join("\n").trim_end(), out);
+    out.push_str("</p>\n");
+    len
+}
+
+#[derive(Clone, Copy)]
+enum Align {
+    None,
+    Left,
+    Center,
+    Right,
+}
+
+/// The column alignments if `lines` start with a table: a header row followed by a delimiter row
+/// like `|:---|:---:|` with the same number of cells
+fn table_columns(lines: &[&str]) -> Option<Vec<Align>> {
+    let [header, delimiter, ..] = lines else {
+        return None;
+    };
+    if !header.contains('|') || !delimiter.contains('-') {
+        return None;
+    }
+
+    let columns = table_cells(delimiter)
+        .iter()
+        .map(|cell| {
+            let dashes = cell.trim_start_matches(':').trim_end_matches(':');
+            if dashes.is_empty() || !dashes.bytes().all(|b| b == b'-') {
+                return None;
+            }
+            Some(match (cell.starts_with(':'), cell.ends_with(':')) {
+                (true, true) => Align::Center,
+                (true, false) => Align::Left,
+                (false, true) => Align::Right,
+                (false, false) => Align::None,
+            })
+        })
+        .collect::<Option<Vec<_>>>()?;
+
+    (table_cells(header).len() == columns.len()).then_some(columns)
+}
+
+/// Splits a table row on `|`, except escaped ones and those inside code spans
+fn table_cells(row: &str) -> Vec<&str> {
+    let row = row.trim();
+    let row = row.strip_prefix('|').unwrap_or(row);
+    let row = match row.strip_suffix('|') {
+        Some(stripped) if !stripped.ends_with('\\') => stripped,
+        _ => row,
+    };
+
+    let mut cells = Vec::new();
+    let mut start = 0;
+    let mut in_code = false;
+    let mut escaped = false;
+    for (i, b) in row.bytes().enumerate() {
+        match b {
+            _ if escaped => escaped = false,
+            b'\\' => escaped = true,
+            b'`' => in_code = !in_code,
+            b'|' if !in_code => {
+                cells.push(row[start..i].trim());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    cells.push(row[start..].trim());
+    cells
+}
+
+fn table(lines: &[&str], columns: &[Align], out: &mut String) -> usize {
+    let body_len = lines[2..]
+        .iter()
+        .take_while(|line| !line.trim().is_empty() && !starts_block(line))
+        .count();
+
+    out.push_str("<table>\n<thead>\n");
+    table_row(lines[0], columns, "th", out);
+    out.push_str("</thead>\n");
+    if body_len > 0 {
+        out.push_str("<tbody>\n");
+        for line in &lines[2..2 + body_len] {
+            table_row(line, columns, "td", out);
+        }
+        out.push_str("</tbody>\n");
+    }
+    out.push_str("</table>\n");
+
+    2 + body_len
+}
+
+/// Rows with missing cells get empty ones, extra cells are dropped
+fn table_row(line: &str, columns: &[Align], tag: &str, out: &mut String) {
+    let cells = table_cells(line);
+    out.push_str("<tr>");
+    for (i, align) in columns.iter().enumerate() {
+        let style = match align {
+            Align::None => "",
+            Align::Left => " style=\"text-align: left\"",
+            Align::Center => " style=\"text-align: center\"",
+            Align::Right => " style=\"text-align: right\"",
+        };
+        let _ = write!(out, "<{tag}{style}>");
+        inline(cells.get(i).copied().unwrap_or_default(), out);
+        let _ = write!(out, "</{tag}>");
+    }
+    out.push_str("</tr>\n");
+}
+
+struct Marker {
+    /// The bullet character, or `.`/`)` for ordered lists
+    delimiter: char,
+    /// Where an ordered list starts
+    number: Option<u64>,
+    /// The marker plus the space after it
+    width: usize,
+}
+
+impl Marker {
+    const fn continues(&self, other: &Self) -> bool {
+        self.delimiter == other.delimiter && self.number.is_some() == other.number.is_some()
+    }
+}
+
+fn list_marker(trimmed: &str) -> Option<Marker> {
+    let bytes = trimmed.as_bytes();
+    let followed_by_space = |at: usize| matches!(bytes.get(at), None | Some(b' ' | b'\t'));
+
+    match bytes.first()? {
+        b @ (b'-' | b'*' | b'+') if followed_by_space(1) && !is_rule(trimmed) => Some(Marker {
+            delimiter: char::from(*b),
+            number: None,
+            width: 2.min(bytes.len()),
+        }),
+        b'0'..=b'9' => {
+            let digits = bytes.iter().take_while(|b| b.is_ascii_digit()).count();
+            let delimiter = *bytes.get(digits).filter(|b| matches!(b, b'.' | b')'))?;
+            (digits <= 9 && followed_by_space(digits + 1)).then(|| Marker {
+                delimiter: char::from(delimiter),
+                number: trimmed[..digits].parse().ok(),
+                width: (digits + 2).min(bytes.len()),
+            })
+        }
+        _ => None,
+    }
+}
+
+fn list(lines: &[&str], out: &mut String) -> usize {
+    let indent = indent_of(lines[0]);
+    let Some(first) = list_marker(lines[0].trim_start()) else {
+        return 0;
+    };
+
+    let mut items: Vec<Vec<&str>> = Vec::new();
+    let mut content_offset = 0;
+    let mut consumed = 0;
+
+    for (i, line) in lines.iter().enumerate() {
+        let line_indent = indent_of(line);
+        let trimmed = line.trim_start();
+        let marker = list_marker(trimmed).filter(|m| m.continues(&first));
+
+        if let Some(marker) = marker.filter(|_| line_indent <= indent + 1) {
+            items.push(vec![&trimmed[marker.width..]]);
+            content_offset = line_indent + marker.width;
+        } else if trimmed.is_empty() {
+            // A blank line only stays in the list if more of it follows
+            let next = lines[i + 1..].iter().find(|l| !l.trim().is_empty());
+            let continues = next.is_some_and(|next| {
+                indent_of(next) > indent
+                    || list_marker(next.trim_start()).is_some_and(|m| m.continues(&first))
+            });
+            if !continues {
+                break;
+            }
+            if let Some(item) = items.last_mut() {
+                item.push("");
+            }
+        } else if line_indent > indent {
+            if let Some(item) = items.last_mut() {
+                item.push(strip_indent(line, content_offset.min(line_indent)));
+            }
+        } else if !starts_block(line)
+            && items
+                .last()
+                .and_then(|item| item.last())
+                .is_some_and(|last| !last.trim().is_empty())
+        {
+            // A lazy continuation of the item's paragraph
+            if let Some(item) = items.last_mut() {
+                item.push(trimmed);
+            }
+        } else {
+            break;
+        }
+        consumed = i + 1;
+    }
+
+    match first.number {
+        Some(1) => out.push_str("<ol>\n"),
+        Some(start) => {
+            let _ = writeln!(out, "<ol start=\"{start}\">");
+        }
+        None => out.push_str("<ul>\n"),
+    }
+    for item in &items {
+        list_item(item, out);
+    }
+    out.push_str(if first.number.is_some() {
+        "</ol>\n"
+    } else {
+        "</ul>\n"
+    });
+
+    consumed
+}
+
+/// Items hold their first paragraph directly, anything after it as blocks
+fn list_item(lines: &[&str], out: &mut String) {
+    let text_len = lines
+        .iter()
+        .enumerate()
+        .take_while(|(i, line)| !line.trim().is_empty() && (*i == 0 || !starts_block(line)))
+        .count();
+    let text: Vec<&str> = lines[..text_len].iter().map(|l| l.trim()).collect();
+    let mut text = text.join("\n");
+
+    out.push_str("<li>");
+    if let Some(checked) = task_box(&text) {
+        out.push_str(if checked {
+            "<input type=\"checkbox\" checked disabled> "
+        } else {
+            "<input type=\"checkbox\" disabled> "
+        });
+        text.drain(..4);
+    }
+    inline(&text, out);
+
+    if text_len < lines.len() {
+        out.push('\n');
+        blocks(&lines[text_len..], out);
+    }
+    out.push_str("</li>\n");
+}
+
+/// `[ ] ` or `[x] ` at the start of a list item
+fn task_box(text: &str) -> Option<bool> {
+    match text.as_bytes().get(..4)? {
+        b"[ ] " => Some(false),
+        b"[x] " | b"[X] " => Some(true),
+        _ => None,
+    }
+}
+
+fn inline(text: &str, out: &mut String) {
+    let bytes = text.as_bytes();
+    let mut plain_start = 0;
+    let mut i = 0;
+    let mut open_tags = Vec::new();
+
+    while i < bytes.len() {
+        let mut html = String::new();
+        let consumed = match bytes[i] {
+            b'\\' => backslash(&text[i..], &mut html),
+            b'`' => Some(code_span(&text[i..], &mut html)),
+            b'[' => link(&text[i..], &mut html),
+            b'<' => autolink(&text[i..], &mut html)
+                .or_else(|| html_tag(&text[i..], &mut open_tags, &mut html)),
+            b'*' | b'_' | b'~' => Some(emphasis(text, i, &mut html)),
+            b'h' if i == 0 || !bytes[i - 1].is_ascii_alphanumeric() => {
+                bare_url(&text[i..], &mut html)
+            }
+            b'\n' if text[..i].ends_with("  ") => {
+                escape_into(out, text[plain_start..i].trim_end_matches(' '));
+                out.push_str("<br>\n");
+                i += 1;
+                plain_start = i;
+                continue;
+            }
+            _ => None,
+        };
+
+        if let Some(len) = consumed {
+            escape_into(out, &text[plain_start..i]);
+            out.push_str(&html);
+            i += len;
+            plain_start = i;
+        } else {
+            i += text[i..].chars().next().map_or(1, char::len_utf8);
+        }
+    }
+
+    escape_into(out, &text[plain_start..]);
+    for tag in open_tags.iter().rev() {
+        let _ = write!(out, "</{tag}>");
+    }
+}
+
+fn backslash(rest: &str, out: &mut String) -> Option<usize> {
+    match rest.as_bytes().get(1)? {
+        b'\n' => {
+            out.push_str("<br>\n");
+            Some(2)
+        }
+        b if b.is_ascii_punctuation() => {
+            escape_into(out, &rest[1..2]);
+            Some(2)
+        }
+        _ => None,
+    }
+}
+
+fn code_span(rest: &str, out: &mut String) -> usize {
+    let ticks = rest.bytes().take_while(|&b| b == b'`').count();
+    let body = &rest[ticks..];
+
+    // The closing run has to be exactly as long as the opening one
+    let mut search = 0;
+    while let Some(found) = body[search..].find(&rest[..ticks]) {
+        let start = search + found;
+        let run = body[start..].bytes().take_while(|&b| b == b'`').count();
+        if run == ticks {
+            let code = body[..start].replace('\n', " ");
+            let code = match code.strip_prefix(' ').and_then(|c| c.strip_suffix(' ')) {
+                Some(inner) if !inner.trim().is_empty() => inner,
+                _ => &code,
+            };
+            out.push_str("<code>");
+            escape_into(out, code);
+            out.push_str("</code>");
+            return ticks + start + ticks;
+        }
+        search = start + run;
+    }
+
+    // No match, so the whole run is literal
+    escape_into(out, &rest[..ticks]);
+    ticks
+}
+
+/// `[text](url)` or `[text](url "title")`. Links to anything but the web or email keep only their text.
+fn link(rest: &str, out: &mut String) -> Option<usize> {
+    let mut depth = 0;
+    let text_end = rest.char_indices().find_map(|(i, c)| {
+        match c {
+            '[' => depth += 1,
+            ']' => depth -= 1,
+            _ => {}
+        }
+        (depth == 0).then_some(i)
+    })?;
+    let text = &rest[1..text_end];
+
+    let destination = rest[text_end + 1..].strip_prefix('(')?;
+    // URLs can have balanced parentheses in them, like Wikipedia's
+    let mut depth = 0;
+    let close = destination.char_indices().find_map(|(i, c)| {
+        match c {
+            '(' => depth += 1,
+            ')' if depth == 0 => return Some(i),
+            ')' => depth -= 1,
+            _ => {}
+        }
+        None
+    })?;
+    let inside = destination[..close].trim();
+    let url = inside
+        .split_once(char::is_whitespace)
+        .map_or(inside, |(url, _)| url);
+    let url = url
+        .strip_prefix('<')
+        .and_then(|u| u.strip_suffix('>'))
+        .unwrap_or(url);
+
+    if is_safe_url(url) {
+        push_link(out, url, |out| inline(text, out));
+    } else {
+        inline(text, out);
+    }
+    Some(text_end + 2 + close + 1)
+}
+
+/// `<https://example.com>`
+fn autolink(rest: &str, out: &mut String) -> Option<usize> {
+    let end = rest.find('>')?;
+    let url = &rest[1..end];
+    let is_url = (url.starts_with("http://") || url.starts_with("https://"))
+        && !url.contains(char::is_whitespace);
+    if !is_url {
+        return None;
+    }
+
+    push_link(out, url, |out| escape_into(out, url));
+    Some(end + 1)
+}
+
+/// URLs written out in the text, as models often do
+fn bare_url(rest: &str, out: &mut String) -> Option<usize> {
+    if !rest.starts_with("http://") && !rest.starts_with("https://") {
+        return None;
+    }
+    let end = rest
+        .find(|c: char| c.is_whitespace() || c == '<')
+        .unwrap_or(rest.len());
+    let url = rest[..end].trim_end_matches(['.', ',', ':', ';', '!', '?', ')', '\'', '"']);
+    if url.ends_with("://") {
+        return None;
+    }
+
+    push_link(out, url, |out| escape_into(out, url));
+    Some(url.len())
+}
+
+/// Formatting tags models sometimes write as HTML, mostly inside table cells
+const ALLOWED_TAGS: &[&str] = &[
+    "b", "i", "u", "s", "em", "strong", "del", "sub", "sup", "kbd", "mark", "small",
+];
+
+/// `<br>` and the allowed formatting tags, only without attributes. Anything else stays escaped text.
+/// Tags left open are closed at the end of `inline`, closing tags that don't match are dropped.
+fn html_tag(rest: &str, open_tags: &mut Vec<&'static str>, out: &mut String) -> Option<usize> {
+    let end = rest.find('>')?;
+    let inner = &rest[1..end];
+    let (closing, name) = inner
+        .strip_prefix('/')
+        .map_or((false, inner), |name| (true, name));
+    let name = name.trim_end().trim_end_matches('/').to_ascii_lowercase();
+
+    if name == "br" && !closing {
+        out.push_str("<br>");
+        return Some(end + 1);
+    }
+    let tag = *ALLOWED_TAGS.iter().find(|tag| **tag == name)?;
+
+    if !closing {
+        open_tags.push(tag);
+        let _ = write!(out, "<{tag}>");
+    } else if let Some(at) = open_tags.iter().rposition(|open| *open == tag) {
+        // Tags opened inside this one close with it
+        for open in open_tags.drain(at..).rev() {
+            let _ = write!(out, "</{open}>");
+        }
+    }
+    Some(end + 1)
+}
+
+fn push_link(out: &mut String, url: &str, text: impl FnOnce(&mut String)) {
+    out.push_str("<a href=\"");
+    escape_into(out, url);
+    out.push_str("\" rel=\"nofollow noopener noreferrer\">");
+    text(out);
+    out.push_str("</a>");
+}
+
+/// Relative URLs, the web and email. Rules out `javascript:` and `data:` links.
+pub fn is_safe_url(url: &str) -> bool {
+    let scheme_end = url.find(':');
+    let path_start = url.find(['/', '?', '#']);
+    match scheme_end {
+        Some(end) if path_start.is_none_or(|start| end < start) => {
+            let scheme = url[..end].to_ascii_lowercase();
+            matches!(scheme.as_str(), "http" | "https" | "mailto")
+        }
+        _ => true,
+    }
+}
+
+/// `*em*`, `**strong**`, `***both***`, the same with `_`, and `~~strikethrough~~`
+fn emphasis(text: &str, at: usize, out: &mut String) -> usize {
+    let bytes = text.as_bytes();
+    let delimiter = bytes[at];
+    let run = bytes[at..].iter().take_while(|&&b| b == delimiter).count();
+    let before = at.checked_sub(1).map(|i| bytes[i]);
+
+    let valid_run = if delimiter == b'~' {
+        run == 2
+    } else {
+        run <= 3
+    };
+    // Opening runs have to touch the text they wrap, and `_` doesn't work inside words
+    let opens = bytes
+        .get(at + run)
+        .is_some_and(|b| !b.is_ascii_whitespace())
+        && !(delimiter == b'_' && before.is_some_and(|b| b.is_ascii_alphanumeric()));
+    if !valid_run || !opens {
+        escape_into(out, &text[at..at + run]);
+        return run;
+    }
+
+    let body_start = at + run;
+    // The body can't be empty, so the search starts after its first character
+    let mut search = body_start + text[body_start..].chars().next().map_or(1, char::len_utf8);
+    while search < bytes.len() {
+        let Some(found) = text[search..].find(char::from(delimiter)) else {
+            break;
+        };
+        let start = search + found;
+        let close_run = bytes[start..]
+            .iter()
+            .take_while(|&&b| b == delimiter)
+            .count();
+        let after = bytes.get(start + close_run);
+        let closes = close_run == run
+            && !bytes[start - 1].is_ascii_whitespace()
+            && !(delimiter == b'_' && after.is_some_and(u8::is_ascii_alphanumeric));
+
+        if closes {
+            let (open, close) = match (delimiter, run) {
+                (b'~', _) => ("<del>", "</del>"),
+                (_, 1) => ("<em>", "</em>"),
+                (_, 2) => ("<strong>", "</strong>"),
+                _ => ("<strong><em>", "</em></strong>"),
+            };
+            out.push_str(open);
+            inline(&text[body_start..start], out);
+            out.push_str(close);
+            return start + close_run - at;
+        }
+        search = start + close_run;
+    }
+
+    escape_into(out, &text[at..at + run]);
+    run
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn inline_html(text: &str) -> String {
+        let mut out = String::new();
+        inline(text, &mut out);
+        out
+    }
+
+    #[test]
+    fn lets_through_allowed_tags_only_without_attributes() {
+        assert_eq!(
+            inline_html("<b>bold</b>, <EM>em</EM> and<br/>a <kbd>key</kbd>"),
+            "<b>bold</b>, <em>em</em> and<br>a <kbd>key</kbd>"
+        );
+        assert_eq!(
+            inline_html("<b onclick=\"alert(1)\">x</b>"),
+            "&lt;b onclick=&quot;alert(1)&quot;&gt;x"
+        );
+        assert_eq!(
+            inline_html("<b class=x>x</b> <br style=\"\">"),
+            "&lt;b class=x&gt;x &lt;br style=&quot;&quot;&gt;"
+        );
+        assert_eq!(
+            inline_html("<span>x</span><img src=x onerror=alert(1)>"),
+            "&lt;span&gt;x&lt;/span&gt;&lt;img src=x onerror=alert(1)&gt;"
+        );
+    }
+
+    #[test]
+    fn balances_mismatched_and_stray_tags() {
+        // Closing an outer tag closes the ones opened inside it
+        assert_eq!(inline_html("<b><i>x</b>y</i>"), "<b><i>x</i></b>y");
+        // Closing tags that were never opened are dropped, open ones are closed at the end
+        assert_eq!(inline_html("x</u></b>y"), "xy");
+        assert_eq!(
+            inline_html("<sup>open <sub>ended"),
+            "<sup>open <sub>ended</sub></sup>"
+        );
+        assert_eq!(inline_html("</br>"), "&lt;/br&gt;");
+        assert_eq!(inline_html("<b"), "&lt;b");
+    }
+
+    #[test]
+    fn keeps_raw_html_escaped() {
+        assert_eq!(
+            to_html("<script>alert(1)</script>\n\n<div onmouseover=\"x\">hi</div>"),
+            "<p>&lt;script&gt;alert(1)&lt;/script&gt;</p>\n\
+             <p>&lt;div onmouseover=&quot;x&quot;&gt;hi&lt;/div&gt;</p>\n"
+        );
+        assert_eq!(
+            inline_html("<!-- note --> <![CDATA[x]]> &lt;b&gt; &amp;"),
+            "&lt;!-- note --&gt; &lt;![CDATA[x]]&gt; &amp;lt;b&amp;gt; &amp;amp;"
+        );
+        assert_eq!(
+            inline_html("`<b>code</b>`"),
+            "<code>&lt;b&gt;code&lt;/b&gt;</code>"
+        );
+    }
+
+    #[test]
+    fn only_links_to_the_web_and_email() {
+        for url in [
+            "https://example.com/a?b=c#d",
+            "HTTP://example.com",
+            "mailto:someone@example.com",
+            "/relative/path:with-colon",
+            "#fragment",
+            "?q=a:b",
+            "page.html",
+        ] {
+            assert!(is_safe_url(url), "{url}");
+        }
+        for url in [
+            "javascript:alert(1)",
+            "JaVaScRiPt:alert(1)",
+            " javascript:alert(1)",
+            "\tjavascript:alert(1)",
+            "java\nscript:alert(1)",
+            "data:text/html;base64,PHNjcmlwdD4=",
+            "DATA:text/html,x",
+            "vbscript:msgbox(1)",
+            "file:///etc/passwd",
+        ] {
+            assert!(!is_safe_url(url), "{url:?}");
+        }
+    }
+
+    #[test]
+    fn unsafe_links_keep_only_their_text() {
+        assert_eq!(
+            inline_html(
+                "[click](JaVaScRiPt:alert(1)) and [img](data:image/svg+xml,<svg onload=alert(1)>)"
+            ),
+            "click and img"
+        );
+        assert_eq!(inline_html("[x]( \t javascript:alert(1) \"title\")"), "x");
+        assert_eq!(inline_html("[x](<javascript:alert(1)>)"), "x");
+        assert_eq!(
+            inline_html("[a \"quote\"](https://example.com/?q=\"><script>)"),
+            "<a href=\"https://example.com/?q=&quot;&gt;&lt;script&gt;\" rel=\"nofollow noopener noreferrer\">a &quot;quote&quot;</a>"
+        );
+    }
+
+    #[test]
+    fn autolinks_only_web_urls() {
+        assert_eq!(
+            inline_html("<https://example.com/a&b>"),
+            "<a href=\"https://example.com/a&amp;b\" rel=\"nofollow noopener noreferrer\">https://example.com/a&amp;b</a>"
+        );
+        assert_eq!(
+            inline_html("<javascript:alert(1)>"),
+            "&lt;javascript:alert(1)&gt;"
+        );
+        assert_eq!(
+            inline_html("<data:text/html,x>"),
+            "&lt;data:text/html,x&gt;"
+        );
+        // Not an autolink, so only the bare URL up to the space is linked
+        assert_eq!(
+            inline_html("<https://exa mple.com>"),
+            "&lt;<a href=\"https://exa\" rel=\"nofollow noopener noreferrer\">https://exa</a> mple.com&gt;"
+        );
+    }
+}
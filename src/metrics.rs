@@ -0,0 +1,223 @@
+use crate::shutdown;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Mutex, PoisonError};
+use std::time::Duration;
+
+const BUCKETS: [f64; 10] = [0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0];
+
+pub struct Histogram {
+    name: &'static str,
+    help: &'static str,
+    /// Per bucket, not cumulative. The last slot counts observations above every bound.
+    buckets: [AtomicU64; BUCKETS.len() + 1],
+    sum_micros: AtomicU64,
+}
+
+impl Histogram {
+    const fn new(name: &'static str, help: &'static str) -> Self {
+        Self {
+            name,
+            help,
+            buckets: [const { AtomicU64::new(0) }; BUCKETS.len() + 1],
+            sum_micros: AtomicU64::new(0),
+        }
+    }
+
+    pub fn observe(&self, elapsed: Duration) {
+        let secs = elapsed.as_secs_f64();
+        let bucket = BUCKETS
+            .iter()
+            .position(|&le| secs <= le)
+            .unwrap_or(BUCKETS.len());
+        self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        self.sum_micros
+            .fetch_add(elapsed.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    fn render(&self, out: &mut String) {
+        let _ = writeln!(out, "# HELP {} {}", self.name, self.help);
+        let _ = writeln!(out, "# TYPE {} histogram", self.name);
+
+        let mut cumulative = 0;
+        for (le, count) in BUCKETS.iter().zip(&self.buckets) {
+            cumulative += count.load(Ordering::Relaxed);
+            let _ = writeln!(out, "{}_bucket{{le=\"{le}\"}} {cumulative}", self.name);
+        }
+        cumulative += self.buckets[BUCKETS.len()].load(Ordering::Relaxed);
+        let _ = writeln!(out, "{}_bucket{{le=\"+Inf\"}} {cumulative}", self.name);
+
+        let sum = self.sum_micros.load(Ordering::Relaxed);
+        let _ = writeln!(
+            out,
+            "{}_sum {}.{:06}",
+            self.name,
+            sum / 1_000_000,
+            sum % 1_000_000
+        );
+        let _ = writeln!(out, "{}_count {cumulative}", self.name);
+    }
+}
+
+pub struct Cache {
+    name: &'static str,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl Cache {
+    const fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    pub fn hit(&self) {
+        self.hits.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn miss(&self) {
+        self.misses.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+pub static TRANSCRIPT_FETCH: Histogram = Histogram::new(
+    "tldr_transcript_fetch_seconds",
+    "Time spent fetching a video's transcript",
+);
+pub static LLM_CALL: Histogram =
+    Histogram::new("tldr_llm_call_seconds", "Time spent waiting for the LLM");
+pub static PLAYER_CONFIG_CACHE: Cache = Cache::new("player_config");
+
+/// Requests waiting for a worker
+pub static QUEUED: AtomicUsize = AtomicUsize::new(0);
+/// Requests and connections turned away with a 503
+pub static REJECTED: AtomicU64 = AtomicU64::new(0);
+pub static CONNECTIONS: AtomicUsize = AtomicUsize::new(0);
+static WORKERS: AtomicUsize = AtomicUsize::new(0);
+static QUEUE_CAPACITY: AtomicUsize = AtomicUsize::new(0);
+
+static REQUESTS: Mutex<BTreeMap<(&'static str, &'static str), u64>> = Mutex::new(BTreeMap::new());
+
+/// Records the worker pool size and queue capacity, which readiness is judged against
+pub fn init(workers: usize, queue_capacity: usize) {
+    WORKERS.store(workers, Ordering::Relaxed);
+    QUEUE_CAPACITY.store(queue_capacity, Ordering::Relaxed);
+}
+
+/// Counts a finished request. `route` is the matched pattern, not the path, to keep the label set small.
+pub fn record_request(route: &'static str, status: &'static str) {
+    let code = status.split(' ').next().unwrap_or(status);
+    *REQUESTS
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .entry((route, code))
+        .or_default() += 1;
+}
+
+pub struct Load {
+    pub connections: usize,
+    pub workers: usize,
+    pub busy_workers: usize,
+    pub queued: usize,
+    pub queue_capacity: usize,
+}
+
+impl Load {
+    pub fn current() -> Self {
+        Self {
+            connections: CONNECTIONS.load(Ordering::Relaxed),
+            workers: WORKERS.load(Ordering::Relaxed),
+            busy_workers: shutdown::in_flight(),
+            queued: QUEUED.load(Ordering::Relaxed),
+            queue_capacity: QUEUE_CAPACITY.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Ready while the queue is less than half full. Busy workers alone don't count against it:
+    /// requests wait in the queue for them, and it's the queue filling up that shows the server
+    /// falling behind, before it has to turn requests away with 503s.
+    pub const fn is_ready(&self) -> bool {
+        // Doubling instead of halving keeps a queue of one ready while it's empty
+        self.queued.saturating_mul(2) < self.queue_capacity
+    }
+}
+
+/// All metrics in the Prometheus text exposition format
+pub fn render(load: &Load) -> String {
+    let mut out = String::new();
+
+    out.push_str("# HELP tldr_http_requests_total HTTP requests by route and status\n");
+    out.push_str("# TYPE tldr_http_requests_total counter\n");
+    let requests = REQUESTS
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .clone();
+    for ((route, status), count) in requests {
+        let _ = writeln!(
+            out,
+            "tldr_http_requests_total{{route=\"{route}\",status=\"{status}\"}} {count}"
+        );
+    }
+
+    TRANSCRIPT_FETCH.render(&mut out);
+    LLM_CALL.render(&mut out);
+
+    out.push_str("# HELP tldr_cache_requests_total Cache lookups by result\n");
+    out.push_str("# TYPE tldr_cache_requests_total counter\n");
+    for cache in [&PLAYER_CONFIG_CACHE] {
+        for (result, count) in [("hit", &cache.hits), ("miss", &cache.misses)] {
+            let _ = writeln!(
+                out,
+                "tldr_cache_requests_total{{cache=\"{}\",result=\"{result}\"}} {}",
+                cache.name,
+                count.load(Ordering::Relaxed)
+            );
+        }
+    }
+
+    let gauges = [
+        (
+            "tldr_open_connections",
+            "Open client connections",
+            load.connections,
+        ),
+        ("tldr_workers", "Worker threads", load.workers),
+        (
+            "tldr_busy_workers",
+            "Workers handling a request",
+            load.busy_workers,
+        ),
+        (
+            "tldr_queue_depth",
+            "Requests waiting for a worker",
+            load.queued,
+        ),
+        (
+            "tldr_queue_capacity",
+            "Requests that fit in the queue",
+            load.queue_capacity,
+        ),
+    ];
+    for (name, help, value) in gauges {
+        let _ = writeln!(
+            out,
+            "# HELP {name} {help}\n# TYPE {name} gauge\n{name} {value}"
+        );
+    }
+
+    out.push_str(
+        "# HELP tldr_rejected_connections_total Requests and connections answered with 503 because the server was overloaded\n",
+    );
+    out.push_str("# TYPE tldr_rejected_connections_total counter\n");
+    let _ = writeln!(
+        out,
+        "tldr_rejected_connections_total {}",
+        REJECTED.load(Ordering::Relaxed)
+    );
+
+    out
+}
@@ -0,0 +1,53 @@
+use crate::{config, deadline, log};
+use std::panic;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+/// Runs `f` on every item, at most `limits.max_parallel_fetches` at a time, and returns the
+/// results in order. Meant for independent network requests, which spend their time waiting.
+/// The threads log with the caller's context and keep to its deadline, and a panic in one is
+/// raised again on the caller.
+pub fn map<T: Sync, R: Send>(items: &[T], f: impl Fn(&T) -> R + Sync) -> Vec<R> {
+    let threads = config::get().limits.max_parallel_fetches.min(items.len());
+    if threads <= 1 {
+        return items.iter().map(f).collect();
+    }
+
+    let context = log::context();
+    let deadline = deadline::current();
+    let next = AtomicUsize::new(0);
+    let work = || {
+        deadline::until(deadline, || {
+            context.enter(|| {
+                let mut done = Vec::new();
+                loop {
+                    let index = next.fetch_add(1, Ordering::Relaxed);
+                    let Some(item) = items.get(index) else {
+                        return done;
+                    };
+                    done.push((index, f(item)));
+                }
+            })
+        })
+    };
+
+    let finished = thread::scope(|scope| {
+        let mut handles = Vec::with_capacity(threads);
+        for _ in 0..threads {
+            handles.push(scope.spawn(work));
+        }
+        handles.into_iter().map(join).collect::<Vec<_>>()
+    });
+
+    let mut results: Vec<Option<R>> = items.iter().map(|_| None).collect();
+    for (index, result) in finished.into_iter().flatten() {
+        results[index] = Some(result);
+    }
+    results.into_iter().flatten().collect()
+}
+
+fn join<T>(handle: thread::ScopedJoinHandle<'_, T>) -> T {
+    handle
+        .join()
+        .unwrap_or_else(|payload| panic::resume_unwind(payload))
+}
@@ -0,0 +1,293 @@
+//! A minimal PDF writer for flowing text on A4 pages. It only uses the standard Helvetica and
+//! Courier fonts, so no fonts are embedded, and text outside their Latin character set shows as `?`.
+
+use std::fmt::Write as _;
+
+const PAGE_WIDTH: f32 = 595.0;
+const PAGE_HEIGHT: f32 = 842.0;
+const MARGIN: f32 = 56.0;
+const TEXT_WIDTH: f32 = PAGE_WIDTH - 2.0 * MARGIN;
+const LINE_SPACING: f32 = 1.35;
+
+/// Helvetica's advance widths for ASCII 32 to 126, in thousandths of the font size
+const HELVETICA_WIDTHS: [u16; 95] = [
+    278, 278, 355, 556, 556, 889, 667, 191, 333, 333, 389, 584, 278, 333, 278, 278, 556, 556, 556,
+    556, 556, 556, 556, 556, 556, 556, 278, 278, 584, 584, 584, 556, 1015, 667, 667, 722, 722, 667,
+    611, 778, 722, 278, 500, 667, 556, 833, 722, 778, 667, 778, 722, 667, 611, 722, 667, 944, 667,
+    667, 611, 278, 278, 278, 469, 556, 333, 556, 556, 500, 556, 556, 278, 556, 556, 222, 222, 500,
+    222, 833, 556, 556, 556, 556, 333, 500, 278, 556, 500, 722, 500, 500, 500, 334, 260, 334, 584,
+];
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Font {
+    Regular,
+    Bold,
+    Mono,
+}
+
+impl Font {
+    const fn resource(self) -> &'static str {
+        match self {
+            Self::Regular => "F1",
+            Self::Bold => "F2",
+            Self::Mono => "F3",
+        }
+    }
+
+    fn char_width(self, c: char, size: f32) -> f32 {
+        let width = match (self, u32::from(c)) {
+            (Self::Mono, _) => 600.0,
+            (_, code @ 32..=126) => f32::from(HELVETICA_WIDTHS[(code - 32) as usize]),
+            _ => 556.0,
+        };
+        // Bold glyphs are a little wider, this keeps wrapped lines inside the margin
+        let width = if self == Self::Bold {
+            width * 1.08
+        } else {
+            width
+        };
+        width * size / 1000.0
+    }
+
+    fn text_width(self, text: &str, size: f32) -> f32 {
+        text.chars().map(|c| self.char_width(c, size)).sum()
+    }
+}
+
+pub struct Document {
+    pages: Vec<Vec<u8>>,
+    page: Vec<u8>,
+    /// Baseline of the next line, from the bottom of the page
+    y: f32,
+}
+
+impl Document {
+    pub const fn new() -> Self {
+        Self {
+            pages: Vec::new(),
+            page: Vec::new(),
+            y: PAGE_HEIGHT - MARGIN,
+        }
+    }
+
+    /// Writes `text` wrapped to the page width, starting `indent` points in. A `prefix` like a
+    /// list bullet goes in front of the first line, later lines line up with the text after it.
+    pub fn paragraph(&mut self, prefix: &str, text: &str, font: Font, size: f32, indent: f32) {
+        let text_indent = indent + Font::Regular.text_width(prefix, size);
+        let width = TEXT_WIDTH - text_indent;
+        let lines = wrap(text, font, size, width);
+
+        for (i, line) in lines.iter().enumerate() {
+            self.next_line(size * LINE_SPACING);
+            if i == 0 && !prefix.is_empty() {
+                self.show(prefix, Font::Regular, size, MARGIN + indent);
+            }
+            self.show(line, font, size, MARGIN + text_indent);
+        }
+    }
+
+    /// Writes a line of code as is, keeping its spacing and breaking it only if it's too long
+    pub fn preformatted(&mut self, text: &str, size: f32, indent: f32) {
+        let per_line = ((TEXT_WIDTH - indent) / Font::Mono.char_width(' ', size)) as usize;
+        let chars: Vec<char> = text.chars().collect();
+        for chunk in chars.chunks(per_line.max(1)) {
+            let line: String = chunk.iter().collect();
+            self.next_line(size * LINE_SPACING);
+            self.show(&line, Font::Mono, size, MARGIN + indent);
+        }
+        if chars.is_empty() {
+            self.next_line(size * LINE_SPACING);
+        }
+    }
+
+    /// Vertical space between blocks
+    pub fn gap(&mut self, points: f32) {
+        if self.y < PAGE_HEIGHT - MARGIN {
+            self.y -= points;
+        }
+    }
+
+    /// A thin horizontal line across the text width
+    pub fn rule(&mut self) {
+        self.next_line(12.0);
+        let y = self.y + 4.0;
+        let _ = writeln!(
+            Ascii(&mut self.page),
+            "0.75 G 0.5 w {MARGIN} {y:.1} m {:.1} {y:.1} l S 0 G",
+            PAGE_WIDTH - MARGIN
+        );
+    }
+
+    fn next_line(&mut self, height: f32) {
+        if self.y - height < MARGIN {
+            self.pages.push(std::mem::take(&mut self.page));
+            self.y = PAGE_HEIGHT - MARGIN;
+        }
+        self.y -= height;
+    }
+
+    fn show(&mut self, text: &str, font: Font, size: f32, x: f32) {
+        let _ = write!(
+            Ascii(&mut self.page),
+            "BT /{} {size} Tf {x:.1} {:.1} Td (",
+            font.resource(),
+            self.y
+        );
+        push_string(&mut self.page, text);
+        self.page.extend_from_slice(b") Tj ET\n");
+    }
+
+    pub fn finish(mut self, title: &str) -> Vec<u8> {
+        if !self.page.is_empty() || self.pages.is_empty() {
+            self.pages.push(self.page);
+        }
+
+        // Objects 1-6 are fixed, then each page is followed by its content stream
+        let page_count = self.pages.len();
+        let kids: Vec<String> = (0..page_count)
+            .map(|i| format!("{} 0 R", 7 + 2 * i))
+            .collect();
+
+        let mut objects: Vec<Vec<u8>> = vec![
+            b"<< /Type /Catalog /Pages 2 0 R >>".to_vec(),
+            format!(
+                "<< /Type /Pages /Kids [{}] /Count {page_count} >>",
+                kids.join(" ")
+            )
+            .into_bytes(),
+            font_object("Helvetica"),
+            font_object("Helvetica-Bold"),
+            font_object("Courier"),
+            format!(
+                "<< /Title {} /Producer (YouTubeTLDR) >>",
+                utf16_string(title)
+            )
+            .into_bytes(),
+        ];
+
+        for (i, content) in self.pages.iter().enumerate() {
+            objects.push(
+                format!(
+                    "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 {PAGE_WIDTH} {PAGE_HEIGHT}] \
+                     /Resources << /Font << /F1 3 0 R /F2 4 0 R /F3 5 0 R >> >> /Contents {} 0 R >>",
+                    8 + 2 * i
+                )
+                .into_bytes(),
+            );
+            let mut stream = format!("<< /Length {} >>\nstream\n", content.len()).into_bytes();
+            stream.extend_from_slice(content);
+            stream.extend_from_slice(b"\nendstream");
+            objects.push(stream);
+        }
+
+        // The comment with high bytes tells tools the file is binary
+        let mut out = b"%PDF-1.4\n%\xE2\xE3\xCF\xD3\n".to_vec();
+        let mut offsets = Vec::with_capacity(objects.len());
+        for (i, object) in objects.iter().enumerate() {
+            offsets.push(out.len());
+            out.extend_from_slice(format!("{} 0 obj\n", i + 1).as_bytes());
+            out.extend_from_slice(object);
+            out.extend_from_slice(b"\nendobj\n");
+        }
+
+        let xref = out.len();
+        let mut table = format!("xref\n0 {}\n0000000000 65535 f \n", objects.len() + 1);
+        for offset in offsets {
+            let _ = writeln!(table, "{offset:010} 00000 n ");
+        }
+        let _ = write!(
+            table,
+            "trailer\n<< /Size {} /Root 1 0 R /Info 6 0 R >>\nstartxref\n{xref}\n%%EOF\n",
+            objects.len() + 1
+        );
+        out.extend_from_slice(table.as_bytes());
+        out
+    }
+}
+
+fn font_object(name: &str) -> Vec<u8> {
+    format!("<< /Type /Font /Subtype /Type1 /BaseFont /{name} /Encoding /WinAnsiEncoding >>")
+        .into_bytes()
+}
+
+/// Splits `text` into lines no wider than `width`, breaking words only when they don't fit on a line
+/// of their own
+fn wrap(text: &str, font: Font, size: f32, width: f32) -> Vec<String> {
+    let space = font.char_width(' ', size);
+    let mut lines = Vec::new();
+    let mut line = String::new();
+    let mut line_width = 0.0;
+
+    for word in text.split_whitespace() {
+        let word_width = font.text_width(word, size);
+        if !line.is_empty() && line_width + space + word_width > width {
+            lines.push(std::mem::take(&mut line));
+            line_width = 0.0;
+        }
+
+        if word_width > width {
+            for c in word.chars() {
+                let char_width = font.char_width(c, size);
+                if line_width + char_width > width && !line.is_empty() {
+                    lines.push(std::mem::take(&mut line));
+                    line_width = 0.0;
+                }
+                line.push(c);
+                line_width += char_width;
+            }
+        } else {
+            if !line.is_empty() {
+                line.push(' ');
+                line_width += space;
+            }
+            line.push_str(word);
+            line_width += word_width;
+        }
+    }
+
+    if !line.is_empty() || lines.is_empty() {
+        lines.push(line);
+    }
+    lines
+}
+
+/// Appends `text` as the inside of a PDF string literal in `WinAnsiEncoding`
+fn push_string(out: &mut Vec<u8>, text: &str) {
+    for c in text.chars() {
+        match c {
+            '(' | ')' | '\\' => out.extend_from_slice(&[b'\\', c as u8]),
+            ' '..='~' | '\u{A0}'..='\u{FF}' => out.push(c as u8),
+            '€' => out.push(0x80),
+            '…' => out.push(0x85),
+            '‘' => out.push(0x91),
+            '’' => out.push(0x92),
+            '“' => out.push(0x93),
+            '”' => out.push(0x94),
+            '•' => out.push(0x95),
+            '–' => out.push(0x96),
+            '—' => out.push(0x97),
+            '\t' => out.push(b' '),
+            _ => out.push(b'?'),
+        }
+    }
+}
+
+/// A text string outside of page content, which can hold any character when written as UTF-16
+fn utf16_string(text: &str) -> String {
+    let mut hex = String::from("<FEFF");
+    for unit in text.encode_utf16() {
+        let _ = write!(hex, "{unit:04X}");
+    }
+    hex.push('>');
+    hex
+}
+
+/// Lets `write!` format into a byte buffer. Page content operators are plain ASCII.
+struct Ascii<'a>(&'a mut Vec<u8>);
+
+impl std::fmt::Write for Ascii<'_> {
+    fn write_str(&mut self, s: &str) -> std::fmt::Result {
+        self.0.extend_from_slice(s.as_bytes());
+        Ok(())
+    }
+}
@@ -0,0 +1,144 @@
+use crate::history::Entry;
+use crate::markdown::{self, escape};
+use crate::subtitle::extract_video_id;
+use std::fmt::Write as _;
+
+const DESCRIPTION_CHARS: usize = 200;
+
+const STYLE: &str = "
+body { margin: 0; background: #f6f7f9; color: #1f2328; font: 16px/1.6 system-ui, -apple-system, sans-serif; }
+main { max-width: 760px; margin: 0 auto; padding: 2rem 1.25rem 3rem; }
+h1 { line-height: 1.25; margin-bottom: .25rem; }
+.meta { color: #59636e; font-size: .9rem; margin-top: 0; }
+.thumbnail { width: 100%; border-radius: 8px; margin: 1rem 0; }
+article { background: #fff; border: 1px solid #d1d9e0; border-radius: 8px; padding: .5rem 1.5rem; }
+pre { background: #f6f8fa; padding: .75rem; overflow-x: auto; border-radius: 6px; }
+code { font-family: ui-monospace, monospace; font-size: .9em; }
+blockquote { margin-left: 0; padding-left: 1rem; border-left: 4px solid #d1d9e0; color: #59636e; }
+details { margin-top: 1.5rem; }
+summary { cursor: pointer; font-weight: 600; }
+.transcript { white-space: pre-wrap; color: #3d444d; }
+footer { margin-top: 2rem; color: #59636e; font-size: .85rem; }
+a { color: #0969da; }
+@media (prefers-color-scheme: dark) {
+  body { background: #0d1117; color: #e6edf3; }
+  article { background: #151b23; border-color: #3d444d; }
+  pre { background: #212830; }
+  .meta, footer, blockquote { color: #9198a1; }
+  .transcript { color: #c9d1d9; }
+  a { color: #4493f8; }
+}
+";
+
+/// Headers for the page. It's only reachable through an unguessable link, so it's kept out of search
+/// engines and caches (to make revoking it effective) and doesn't leak the link through `Referer`.
+pub const HEADERS: &[(&str, &str)] = &[
+    ("Cache-Control", "private, no-cache"),
+    ("X-Robots-Tag", "noindex"),
+    ("Referrer-Policy", "no-referrer"),
+    (
+        "Content-Security-Policy",
+        "default-src 'none'; style-src 'unsafe-inline'; img-src https://i.ytimg.com",
+    ),
+];
+
+/// A standalone page for a shared summary, with Open Graph tags for link previews
+pub fn render(entry: &Entry) -> String {
+    let title = if entry.video_name.is_empty() {
+        "Video summary"
+    } else {
+        &entry.video_name
+    };
+    let thumbnail = extract_video_id(&entry.url)
+        .filter(|id| {
+            id.bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+        })
+        .map(|id| format!("https://i.ytimg.com/vi/{id}/hqdefault.jpg"));
+
+    let mut page = String::with_capacity(entry.summary.len() * 2 + entry.subtitles.len());
+    let _ = write!(
+        page,
+        "<!DOCTYPE html>\n<html lang=\"{lang}\">\n<head>\n<meta charset=\"utf-8\">\n\
+         <meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\n\
+         <meta name=\"robots\" content=\"noindex\">\n<title>{title} · YouTubeTLDR</title>\n\
+         <meta property=\"og:type\" content=\"article\">\n\
+         <meta property=\"og:site_name\" content=\"YouTubeTLDR\">\n\
+         <meta property=\"og:title\" content=\"{title}\">\n\
+         <meta property=\"og:description\" content=\"{description}\">\n\
+         <meta name=\"description\" content=\"{description}\">\n",
+        lang = escape(&entry.language),
+        title = escape(title),
+        description = escape(&description(&entry.summary)),
+    );
+    if let Some(image) = &thumbnail {
+        let _ = write!(
+            page,
+            "<meta property=\"og:image\" content=\"{image}\">\n\
+             <meta name=\"twitter:card\" content=\"summary_large_image\">\n"
+        );
+    }
+    let _ = write!(
+        page,
+        "<style>{STYLE}</style>\n</head>\n<body>\n<main>\n<header>\n<h1>{}</h1>\n<p class=\"meta\">",
+        escape(title)
+    );
+
+    if markdown::is_safe_url(&entry.url) && entry.url.starts_with("http") {
+        let _ = write!(
+            page,
+            "<a href=\"{}\" rel=\"noopener noreferrer\">Watch the video</a> · ",
+            escape(&entry.url)
+        );
+    }
+    let _ = write!(
+        page,
+        "Summarized {}",
+        escape(entry.created_at.get(..10).unwrap_or(&entry.created_at))
+    );
+    if let Some(model) = &entry.model {
+        let _ = write!(page, " with {}", escape(model));
+    }
+    page.push_str("</p>\n</header>\n");
+
+    if let Some(image) = &thumbnail {
+        let _ = writeln!(page, "<img class=\"thumbnail\" src=\"{image}\" alt=\"\">");
+    }
+
+    page.push_str("<article>\n");
+    page.push_str(&markdown::to_html(&entry.summary));
+    page.push_str("</article>\n");
+
+    if !entry.subtitles.is_empty() {
+        let _ = write!(
+            page,
+            "<details>\n<summary>Transcript</summary>\n<p class=\"transcript\">{}</p>\n</details>\n",
+            escape(&entry.subtitles)
+        );
+    }
+
+    page.push_str(
+        "<footer>Made with <a href=\"https://github.com/Milkshiift/YouTubeTLDR\">YouTubeTLDR</a></footer>\n</main>\n</body>\n</html>\n",
+    );
+    page
+}
+
+/// The start of the summary as plain text, for link previews
+fn description(summary: &str) -> String {
+    let plain: String = summary
+        .lines()
+        .map(|line| {
+            line.trim()
+                .trim_start_matches(['#', '>', '-', '*', '+'])
+                .trim()
+        })
+        .filter(|line| !line.is_empty() && !line.starts_with("```"))
+        .collect::<Vec<_>>()
+        .join(" ")
+        .replace(['*', '_', '`', '~'], "");
+
+    match plain.char_indices().nth(DESCRIPTION_CHARS) {
+        Some((end, _)) => format!("{}…", plain[..end].trim_end()),
+        None => plain,
+    }
+}
@@ -0,0 +1,189 @@
+use crate::error::Error;
+use crate::http::{Request, Response, percent_decode};
+
+pub type Handler = fn(&Request, &Params) -> Result<Response, Error>;
+
+/// The rest of the chain, ending in the matched route (or the 404/405 for no match)
+pub type Next<'a> = &'a dyn Fn(&Request) -> Result<Response, Error>;
+
+/// Runs around every request, including ones no route matches.
+/// Can answer on its own (e.g. to reject unauthenticated requests) or call `next` and adjust the response.
+pub type Middleware = fn(&Request, Next) -> Result<Response, Error>;
+
+/// Path parameters captured by `{name}` segments, percent-decoded
+pub struct Params(Vec<(&'static str, String)>);
+
+impl Params {
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|(n, _)| *n == name)
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+enum Segment {
+    Literal(&'static str),
+    Param(&'static str),
+}
+
+struct Route {
+    method: &'static str,
+    pattern: &'static str,
+    segments: Vec<Segment>,
+    handler: Handler,
+    inline: bool,
+}
+
+impl Route {
+    fn matches(&self, path: &str) -> Option<Params> {
+        let mut parts = path.trim_start_matches('/').split('/');
+        let mut params = Vec::new();
+
+        for segment in &self.segments {
+            let part = parts.next()?;
+            match segment {
+                Segment::Literal(literal) if *literal == part => {}
+                Segment::Param(name) if !part.is_empty() => {
+                    params.push((*name, percent_decode(part)));
+                }
+                _ => return None,
+            }
+        }
+
+        parts.next().is_none().then_some(Params(params))
+    }
+}
+
+#[derive(Default)]
+pub struct Router {
+    routes: Vec<Route>,
+    middleware: Vec<Middleware>,
+}
+
+impl Router {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers `handler` for `method` on `pattern`, e.g. `/api/jobs/{id}`.
+    /// GET routes also answer HEAD.
+    #[must_use]
+    pub fn route(mut self, method: &'static str, pattern: &'static str, handler: Handler) -> Self {
+        let segments = pattern
+            .trim_start_matches('/')
+            .split('/')
+            .map(|s| {
+                s.strip_prefix('{')
+                    .and_then(|s| s.strip_suffix('}'))
+                    .map_or(Segment::Literal(s), Segment::Param)
+            })
+            .collect();
+
+        self.routes.push(Route {
+            method,
+            pattern,
+            segments,
+            handler,
+            inline: false,
+        });
+        self
+    }
+
+    /// Lets the route just added run on the event loop instead of waiting for a worker thread.
+    /// Only for handlers that answer right away, never ones that wait on the network.
+    #[must_use]
+    pub fn inline(mut self) -> Self {
+        if let Some(route) = self.routes.last_mut() {
+            route.inline = true;
+        }
+        self
+    }
+
+    #[must_use]
+    pub fn get(self, pattern: &'static str, handler: Handler) -> Self {
+        self.route("GET", pattern, handler)
+    }
+
+    #[must_use]
+    pub fn post(self, pattern: &'static str, handler: Handler) -> Self {
+        self.route("POST", pattern, handler)
+    }
+
+    #[must_use]
+    pub fn delete(self, pattern: &'static str, handler: Handler) -> Self {
+        self.route("DELETE", pattern, handler)
+    }
+
+    /// Adds a middleware. The first one added is the outermost.
+    #[must_use]
+    pub fn middleware(mut self, middleware: Middleware) -> Self {
+        self.middleware.push(middleware);
+        self
+    }
+
+    /// Runs the request through the middleware and the matching route.
+    /// Errors are turned into responses, so this always has something to send.
+    pub fn handle(&self, request: &Request) -> Response {
+        self.run(0, request).unwrap_or_else(|e| Response::error(&e))
+    }
+
+    /// The pattern of the route the path belongs to, for labelling metrics without a label per distinct path
+    pub fn pattern(&self, request: &Request) -> &'static str {
+        self.routes
+            .iter()
+            .find(|route| route.matches(&request.path).is_some())
+            .map_or("unmatched", |route| route.pattern)
+    }
+
+    /// Whether the request can be answered on the event loop. Requests no route matches only get an error, so they can.
+    pub fn is_inline(&self, request: &Request) -> bool {
+        let method = Self::route_method(request);
+        self.routes
+            .iter()
+            .find(|route| route.method == method && route.matches(&request.path).is_some())
+            .is_none_or(|route| route.inline)
+    }
+
+    /// HEAD requests are served by GET routes
+    fn route_method(request: &Request) -> &str {
+        if request.method == "HEAD" {
+            "GET"
+        } else {
+            &request.method
+        }
+    }
+
+    fn run(&self, index: usize, request: &Request) -> Result<Response, Error> {
+        let Some(middleware) = self.middleware.get(index) else {
+            return self.dispatch(request);
+        };
+        middleware(request, &|request| self.run(index + 1, request))
+    }
+
+    fn dispatch(&self, request: &Request) -> Result<Response, Error> {
+        let method = Self::route_method(request);
+        let mut allowed = Vec::new();
+
+        for route in &self.routes {
+            let Some(params) = route.matches(&request.path) else {
+                continue;
+            };
+            if route.method == method {
+                return (route.handler)(request, &params);
+            }
+            allowed.push(route.method);
+            if route.method == "GET" {
+                allowed.push("HEAD");
+            }
+        }
+
+        if allowed.is_empty() {
+            Err(Error::NotFound)
+        } else {
+            allowed.sort_unstable();
+            allowed.dedup();
+            Err(Error::MethodNotAllowed(allowed))
+        }
+    }
+}
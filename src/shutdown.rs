@@ -0,0 +1,87 @@
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread;
+use std::time::Duration;
+
+const WATCH_INTERVAL: Duration = Duration::from_millis(100);
+
+static REQUESTED: AtomicBool = AtomicBool::new(false);
+static IN_FLIGHT: AtomicUsize = AtomicUsize::new(0);
+
+pub fn requested() -> bool {
+    REQUESTED.load(Ordering::Relaxed)
+}
+
+/// Requests currently being handled by a worker
+pub fn in_flight() -> usize {
+    IN_FLIGHT.load(Ordering::Relaxed)
+}
+
+/// Counts `f` as an in-flight request while it runs
+pub fn track<T>(f: impl FnOnce() -> T) -> T {
+    struct Guard;
+    impl Drop for Guard {
+        fn drop(&mut self) {
+            IN_FLIGHT.fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+
+    IN_FLIGHT.fetch_add(1, Ordering::Relaxed);
+    let _guard = Guard;
+    f()
+}
+
+/// Installs SIGINT/SIGTERM handlers. The first signal requests a shutdown, a second one exits immediately.
+/// `listen_addr` is connected to once a shutdown is requested, so the event loop wakes up and notices.
+pub fn install(listen_addr: SocketAddr) {
+    #[cfg(unix)]
+    unix::install();
+
+    let wake_addr = match listen_addr {
+        SocketAddr::V4(addr) if addr.ip().is_unspecified() => {
+            (Ipv4Addr::LOCALHOST, addr.port()).into()
+        }
+        SocketAddr::V6(addr) if addr.ip().is_unspecified() => {
+            (Ipv6Addr::LOCALHOST, addr.port()).into()
+        }
+        addr => addr,
+    };
+
+    // The signal handler can only set a flag safely, so the wake-up happens from a normal thread
+    thread::spawn(move || {
+        while !requested() {
+            thread::sleep(WATCH_INTERVAL);
+        }
+        let _ = TcpStream::connect_timeout(&wake_addr, Duration::from_secs(1));
+    });
+}
+
+#[cfg(unix)]
+mod unix {
+    use super::REQUESTED;
+    use std::ffi::c_int;
+    use std::sync::atomic::Ordering;
+
+    const SIGINT: c_int = 2;
+    const SIGTERM: c_int = 15;
+
+    unsafe extern "C" {
+        fn signal(signum: c_int, handler: extern "C" fn(c_int)) -> usize;
+        fn _exit(status: c_int) -> !;
+    }
+
+    extern "C" fn handle(signum: c_int) {
+        if REQUESTED.swap(true, Ordering::Relaxed) {
+            // Only async-signal-safe calls are allowed here, which rules out `process::exit`
+            unsafe { _exit(128 + signum) }
+        }
+    }
+
+    pub fn install() {
+        // SAFETY: the handler only touches an atomic and calls `_exit`, both async-signal-safe
+        unsafe {
+            signal(SIGINT, handle);
+            signal(SIGTERM, handle);
+        }
+    }
+}
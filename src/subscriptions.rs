@@ -0,0 +1,253 @@
+use crate::config::Subscription;
+use crate::error::Error;
+use crate::history::{self, Channel};
+use crate::{log, shutdown};
+use std::collections::HashMap;
+use std::fmt;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const FEED_URL: &str = "https://www.youtube.com/feeds/videos.xml?channel_id=";
+const FEED_TIMEOUT_SECS: u64 = 30;
+const STOP_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+/// New uploads often don't have captions yet, so a failed video is tried again on later polls
+const MAX_ATTEMPTS: u32 = 3;
+/// A week. Config validation holds intervals to this, which also keeps the next poll time
+/// from overflowing.
+pub const MAX_INTERVAL_MINUTES: u64 = 7 * 24 * 60;
+
+#[derive(Debug)]
+pub enum FeedError {
+    Request(minreq::Error),
+    Status(i32),
+    Invalid,
+}
+
+impl fmt::Display for FeedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Request(e) => write!(f, "Channel feed request failed: {e}"),
+            Self::Status(status) => write!(f, "Channel feed returned status {status}"),
+            Self::Invalid => write!(f, "Channel feed is not a YouTube uploads feed"),
+        }
+    }
+}
+
+impl std::error::Error for FeedError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Request(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// Summarizes a video for the scheduler: its URL, the channel's prompt and language, and the
+/// channel to record it under
+pub type Summarize =
+    dyn Fn(&str, Option<&str>, Option<&str>, Channel) -> Result<(), Error> + Send + Sync;
+
+struct Upload {
+    video_id: String,
+    title: String,
+}
+
+struct Feed {
+    channel_name: String,
+    /// Newest first
+    uploads: Vec<Upload>,
+}
+
+/// Starts the background thread that checks `channels` for new uploads every
+/// `default_interval_minutes`, or their own interval, and summarizes them with `summarize`
+pub fn start(channels: Vec<Subscription>, default_interval_minutes: u64, summarize: Box<Summarize>) {
+    if channels.is_empty() {
+        return;
+    }
+    log::info!("Following channels"; count = channels.len());
+    thread::spawn(move || {
+        log::scope("scheduler", "subscriptions", || {
+            run(&channels, default_interval_minutes, &*summarize);
+        });
+    });
+}
+
+fn run(channels: &[Subscription], default_interval_minutes: u64, summarize: &Summarize) {
+    let mut next_poll = vec![Instant::now(); channels.len()];
+    let mut attempts: HashMap<String, u32> = HashMap::new();
+
+    while !shutdown::requested() {
+        for (channel, due) in channels.iter().zip(&mut next_poll) {
+            if *due > Instant::now() {
+                continue;
+            }
+            log::scope("channel_id", &channel.channel_id, || {
+                poll(channel, summarize, &mut attempts);
+            });
+            let minutes = channel
+                .interval_minutes
+                .unwrap_or(default_interval_minutes)
+                .min(MAX_INTERVAL_MINUTES);
+            let now = Instant::now();
+            *due = now.checked_add(Duration::from_mins(minutes)).unwrap_or(now);
+        }
+        thread::sleep(STOP_CHECK_INTERVAL);
+    }
+}
+
+fn poll(channel: &Subscription, summarize: &Summarize, attempts: &mut HashMap<String, u32>) {
+    let feed = match fetch_feed(&channel.channel_id) {
+        Ok(feed) => feed,
+        Err(e) => {
+            log::warn!("Checking channel for uploads failed"; error = e);
+            return;
+        }
+    };
+
+    let new = match new_uploads(&channel.channel_id, &feed) {
+        Ok(new) => new,
+        Err(e) => {
+            log::error!("Reading the history failed"; error = e);
+            return;
+        }
+    };
+
+    // Oldest first, so the history has them in upload order
+    for upload in new.into_iter().rev() {
+        if shutdown::requested() {
+            return;
+        }
+        let tries = attempts.entry(upload.video_id.clone()).or_default();
+        if *tries >= MAX_ATTEMPTS {
+            continue;
+        }
+        *tries += 1;
+
+        let url = format!("https://www.youtube.com/watch?v={}", upload.video_id);
+        let result = summarize(
+            &url,
+            channel.prompt.as_deref(),
+            channel.language.as_deref(),
+            Channel {
+                id: channel.channel_id.clone(),
+                name: feed.channel_name.clone(),
+            },
+        );
+        match result {
+            Ok(()) => {
+                attempts.remove(&upload.video_id);
+                log::info!("Summarized new upload"; video = upload.title, channel = feed.channel_name);
+            }
+            Err(e) => {
+                log::warn!("Summarizing new upload failed"; video = upload.title, attempt = *tries, error = e);
+            }
+        }
+    }
+}
+
+/// Uploads newer than the newest one already summarized. A channel that hasn't been summarized
+/// before only gets its latest upload, rather than its whole back catalogue.
+fn new_uploads<'a>(channel_id: &str, feed: &'a Feed) -> Result<Vec<&'a Upload>, Error> {
+    let known = !history::recent(1, |entry| {
+        entry.channel.as_ref().is_some_and(|c| c.id == channel_id)
+    })?
+    .is_empty();
+
+    let mut new = Vec::new();
+    for upload in &feed.uploads {
+        if history::has_video(&upload.video_id)? {
+            break;
+        }
+        new.push(upload);
+        if !known {
+            break;
+        }
+    }
+    Ok(new)
+}
+
+fn fetch_feed(channel_id: &str) -> Result<Feed, FeedError> {
+    let response = minreq::get(format!("{FEED_URL}{channel_id}"))
+        .with_timeout(FEED_TIMEOUT_SECS)
+        .send()
+        .map_err(FeedError::Request)?;
+    if !(200..=299).contains(&response.status_code) {
+        return Err(FeedError::Status(response.status_code));
+    }
+    parse_feed(response.as_str().map_err(FeedError::Request)?)
+}
+
+/// Picks what's needed out of the feed. It's a fixed format, so this doesn't need a real XML parser.
+fn parse_feed(xml: &str) -> Result<Feed, FeedError> {
+    let mut parts = xml.split("<entry>");
+    let header = parts.next().unwrap_or_default();
+    if !header.contains("<feed") {
+        return Err(FeedError::Invalid);
+    }
+
+    let uploads = parts
+        .filter_map(|entry| {
+            Some(Upload {
+                video_id: element(entry, "yt:videoId")?.trim().to_string(),
+                title: unescape(element(entry, "title").unwrap_or_default()),
+            })
+        })
+        .filter(|upload| {
+            upload.video_id.len() == 11
+                && upload
+                    .video_id
+                    .bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+        })
+        .collect();
+
+    Ok(Feed {
+        channel_name: unescape(element(header, "title").unwrap_or_default()),
+        uploads,
+    })
+}
+
+/// The text inside the first `<name>` element
+fn element<'a>(xml: &'a str, name: &str) -> Option<&'a str> {
+    let open = format!("<{name}>");
+    let start = xml.find(&open)? + open.len();
+    let len = xml[start..].find(&format!("</{name}>"))?;
+    Some(&xml[start..start + len])
+}
+
+fn unescape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        out.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let Some(end) = rest.find(';').filter(|&end| end <= 10) else {
+            out.push('&');
+            rest = &rest[1..];
+            continue;
+        };
+
+        let decoded = match &rest[1..end] {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            entity => entity
+                .strip_prefix("#x")
+                .map(|hex| u32::from_str_radix(hex, 16))
+                .or_else(|| entity.strip_prefix('#').map(str::parse))
+                .and_then(Result::ok)
+                .and_then(char::from_u32),
+        };
+        if let Some(c) = decoded {
+            out.push(c);
+            rest = &rest[end + 1..];
+        } else {
+            out.push('&');
+            rest = &rest[1..];
+        }
+    }
+    out.push_str(rest);
+    out
+}
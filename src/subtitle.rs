@@ -1,16 +1,162 @@
-use miniserde::{json, Deserialize};
-use std::error::Error;
+use crate::cookies::CookieJar;
+use crate::{config, datetime, deadline, digest, log, metrics};
+use miniserde::{json, Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt::{self, Write as _};
+use std::io;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, OnceLock, PoisonError};
+use std::time::{Duration, Instant};
+
+#[derive(Debug)]
+pub enum Error {
+    InvalidUrl(String),
+    Request(minreq::Error),
+    /// The request ran out of time before it was answered
+    DeadlineExceeded,
+    Status(i32),
+    Json(miniserde::Error),
+    PlayerConfig(&'static str),
+    Cookies(io::Error),
+    ConsentRequired,
+    LoginRequired(String),
+    AgeRestricted,
+    Private,
+    RegionBlocked(String),
+    Unplayable(String),
+    Unavailable(String),
+    Upcoming { reason: String, scheduled_start: Option<u64> },
+    LiveInProgress,
+    VideoDetailsMissing,
+    NoCaptions(String),
+    LanguageUnavailable { language: String, available: Vec<String> },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidUrl(url) => write!(f, "Invalid YouTube URL: {url}"),
+            Self::Request(_) => write!(f, "Failed to send request to YouTube"),
+            Self::DeadlineExceeded => write!(f, "Ran out of time waiting for YouTube"),
+            Self::Status(status) => write!(f, "YouTube returned an error (status {status})"),
+            Self::Json(_) => write!(f, "Failed to parse a response from YouTube"),
+            Self::PlayerConfig(what) => write!(f, "Could not find {what} in the video page"),
+            Self::Cookies(e) => write!(f, "Failed to load cookies: {e}"),
+            Self::ConsentRequired => write!(
+                f,
+                "YouTube redirected to its cookie consent page. Supply cookies from a browser session that accepted it"
+            ),
+            Self::LoginRequired(reason) => write!(f, "This video requires login: {reason}"),
+            Self::AgeRestricted => write!(
+                f,
+                "This video is age-restricted. Supply cookies from an account that can watch it"
+            ),
+            Self::Private => write!(f, "This video is private"),
+            Self::RegionBlocked(reason) => write!(f, "This video is not available in the server's region: {reason}"),
+            Self::Unplayable(reason) => write!(f, "This video is unplayable: {reason}"),
+            Self::Unavailable(reason) => write!(f, "This video is unavailable: {reason}"),
+            Self::Upcoming { reason, scheduled_start: Some(start) } => {
+                let wait = start.saturating_sub(datetime::now_unix());
+                write!(
+                    f,
+                    "{reason}. It starts at {} (in {}), captions will be available after it has aired",
+                    datetime::format_rfc3339(*start),
+                    datetime::format_duration(wait)
+                )
+            }
+            Self::Upcoming { reason, scheduled_start: None } => {
+                write!(f, "{reason}. Captions will be available after it has aired")
+            }
+            Self::LiveInProgress => write!(
+                f,
+                "This live stream is still in progress. Captions will be available after it ends"
+            ),
+            Self::VideoDetailsMissing => write!(f, "Video details not found"),
+            Self::NoCaptions(video_id) => write!(f, "No captions found for video: {video_id}"),
+            Self::LanguageUnavailable { language, available } => {
+                write!(f, "No captions for '{language}'. Available: {available:?}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Request(e) => Some(e),
+            Self::Json(e) => Some(e),
+            Self::Cookies(e) => Some(e),
+            _ => None,
+        }
+    }
+}
 
 #[derive(Deserialize)]
 struct PlayerDataResponse {
+    #[serde(rename = "playabilityStatus")]
+    playability_status: Option<PlayabilityStatus>,
     captions: Option<Captions>,
     #[serde(rename = "videoDetails")]
     video_details: Option<VideoDetails>,
 }
 
+#[derive(Deserialize)]
+struct PlayabilityStatus {
+    status: String,
+    reason: Option<String>,
+    #[serde(rename = "liveStreamability")]
+    live_streamability: Option<LiveStreamability>,
+}
+
+#[derive(Deserialize)]
+struct LiveStreamability {
+    #[serde(rename = "liveStreamabilityRenderer")]
+    renderer: LiveStreamabilityRenderer,
+}
+
+#[derive(Deserialize)]
+struct LiveStreamabilityRenderer {
+    #[serde(rename = "offlineSlate")]
+    offline_slate: Option<OfflineSlate>,
+}
+
+#[derive(Deserialize)]
+struct OfflineSlate {
+    #[serde(rename = "liveStreamOfflineSlateRenderer")]
+    renderer: LiveStreamOfflineSlateRenderer,
+}
+
+#[derive(Deserialize)]
+struct LiveStreamOfflineSlateRenderer {
+    #[serde(rename = "scheduledStartTime")]
+    scheduled_start_time: Option<String>,
+}
+
+impl PlayabilityStatus {
+    fn scheduled_start(&self) -> Option<u64> {
+        self.live_streamability
+            .as_ref()?
+            .renderer
+            .offline_slate
+            .as_ref()?
+            .renderer
+            .scheduled_start_time
+            .as_ref()?
+            .parse()
+            .ok()
+    }
+}
+
 #[derive(Deserialize)]
 struct VideoDetails {
     title: String,
+    #[serde(rename = "lengthSeconds")]
+    length_seconds: Option<String>,
+    #[serde(rename = "isLive")]
+    is_live: Option<bool>,
+    #[serde(rename = "isLiveContent")]
+    is_live_content: Option<bool>,
 }
 
 #[derive(Deserialize)]
@@ -40,6 +186,10 @@ struct JsonCaptionResponse {
 
 #[derive(Deserialize)]
 struct JsonCaptionEvent {
+    #[serde(rename = "tStartMs")]
+    t_start_ms: Option<u64>,
+    #[serde(rename = "dDurationMs")]
+    d_duration_ms: Option<u64>,
     segs: Option<Vec<CaptionSegment>>,
 }
 
@@ -56,19 +206,482 @@ struct PlayerConfig {
     api_key: String,
 }
 
-pub fn get_video_data(video_url: &str, language: &str) -> Result<(String, String), Box<dyn Error>> {
+struct CachedPlayerConfig {
+    config: Arc<PlayerConfig>,
+    fetched_at: Instant,
+}
+
+struct PlayerConfigCache {
+    cached: Option<CachedPlayerConfig>,
+    /// Set while one request fetches a new config, the others wait for it instead of downloading base.js too
+    fetching: bool,
+}
+
+static PLAYER_CONFIG_CACHE: Mutex<PlayerConfigCache> = Mutex::new(PlayerConfigCache {
+    cached: None,
+    fetching: false,
+});
+static PLAYER_CONFIG_FETCHED: Condvar = Condvar::new();
+
+// Skips the EU consent interstitial. Sent unless the operator's cookies already carry a consent decision
+const CONSENT_COOKIE: &str = "SOCS=CAI";
+const ORIGIN: &str = "https://www.youtube.com";
+
+struct Cookies {
+    header: String,
+    sapisid: Option<String>,
+}
+
+static COOKIES: OnceLock<Cookies> = OnceLock::new();
+
+/// Loads a Netscape `cookies.txt` file. Its `youtube.com` cookies are attached to every outgoing request.
+/// Returns the number of cookies loaded.
+pub fn load_cookies(path: &Path) -> Result<usize, Error> {
+    let jar = CookieJar::load(path, "youtube.com").map_err(Error::Cookies)?;
+
+    let mut header = jar.header();
+    if jar.get("SOCS").is_none() && jar.get("CONSENT").is_none() {
+        if !header.is_empty() {
+            header.push_str("; ");
+        }
+        header.push_str(CONSENT_COOKIE);
+    }
+
+    let cookies = Cookies {
+        header,
+        sapisid: jar
+            .get("SAPISID")
+            .or_else(|| jar.get("__Secure-3PAPISID"))
+            .map(str::to_string),
+    };
+
+    COOKIES
+        .set(cookies)
+        .map_err(|_| Error::Cookies(io::Error::other("Cookies were already loaded")))?;
+
+    Ok(jar.len())
+}
+
+fn with_cookies(request: minreq::Request) -> minreq::Request {
+    match COOKIES.get() {
+        Some(cookies) => request.with_header("Cookie", cookies.header.as_str()),
+        None => request.with_header("Cookie", CONSENT_COOKIE),
+    }
+}
+
+/// Sends a request, giving up after the configured timeout or at the request's deadline
+fn send(request: minreq::Request) -> Result<minreq::Response, Error> {
+    let timeout = deadline::timeout_secs(config::get().youtube.timeout_secs)
+        .ok_or(Error::DeadlineExceeded)?;
+    request.with_timeout(timeout).send().map_err(|e| {
+        if deadline::expired() {
+            Error::DeadlineExceeded
+        } else {
+            Error::Request(e)
+        }
+    })
+}
+
+/// Logged-in innertube requests must prove ownership of the session with a hash of the SAPISID cookie
+fn sapisid_authorization() -> Option<String> {
+    let sapisid = COOKIES.get()?.sapisid.as_deref()?;
+    let timestamp = datetime::now_unix();
+    let hash = digest::sha1(format!("{timestamp} {sapisid} {ORIGIN}").as_bytes());
+    Some(format!("SAPISIDHASH {timestamp}_{}", digest::to_hex(&hash)))
+}
+
+/// An innertube client identity used for the player request.
+/// Restrictions (PO tokens, bot checks) are applied per client, so when one breaks another usually still works.
+struct InnertubeClient {
+    name: &'static str,
+    /// Numeric id sent in `X-YouTube-Client-Name`
+    id: u32,
+    /// `None` means the version scraped from the watch page is used
+    version: Option<&'static str>,
+    user_agent: &'static str,
+    /// Extra fields for the `context.client` object, each prefixed with a comma
+    context: &'static str,
+    embedded: bool,
+}
+
+const CLIENTS: &[InnertubeClient] = &[
+    InnertubeClient {
+        name: "WEB",
+        id: 1,
+        version: None,
+        user_agent: USER_AGENT,
+        context: "",
+        embedded: false,
+    },
+    InnertubeClient {
+        name: "ANDROID",
+        id: 3,
+        version: Some("20.10.38"),
+        user_agent: "com.google.android.youtube/20.10.38 (Linux; U; Android 11) gzip",
+        context: r#", "androidSdkVersion": 30, "osName": "Android", "osVersion": "11""#,
+        embedded: false,
+    },
+    InnertubeClient {
+        name: "IOS",
+        id: 5,
+        version: Some("20.10.4"),
+        user_agent: "com.google.ios.youtube/20.10.4 (iPhone16,2; U; CPU iOS 18_3_2 like Mac OS X;)",
+        context: r#", "deviceMake": "Apple", "deviceModel": "iPhone16,2", "osName": "iPhone", "osVersion": "18.3.2.22D82""#,
+        embedded: false,
+    },
+    InnertubeClient {
+        name: "TVHTML5_SIMPLY_EMBEDDED_PLAYER",
+        id: 85,
+        version: Some("2.0"),
+        user_agent: USER_AGENT,
+        context: r#", "clientScreen": "EMBED""#,
+        embedded: true,
+    },
+    InnertubeClient {
+        name: "MWEB",
+        id: 2,
+        version: Some("2.20250311.03.00"),
+        user_agent: "Mozilla/5.0 (iPad; CPU OS 16_7_10 like Mac OS X) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/16.6 Mobile/15E148 Safari/604.1,gzip(gfe)",
+        context: "",
+        embedded: false,
+    },
+];
+
+// Index into CLIENTS of the last client that returned captions, tried first on the next request
+static PREFERRED_CLIENT: AtomicUsize = AtomicUsize::new(0);
+
+/// Stretches of a stream this long without captions are reported, shorter ones are just quiet
+const MIN_GAP_MS: u64 = 5 * 60 * 1000;
+
+pub struct VideoData {
+    pub title: String,
+    pub transcript: String,
+    /// Where a finished stream has no captions, see `stitch`
+    pub gaps: Vec<Gap>,
+}
+
+/// A stretch of a stream without captions, in seconds from its start
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Gap {
+    pub start: u64,
+    pub end: u64,
+}
+
+pub fn get_video_data(video_url: &str, language: &str) -> Result<VideoData, Error> {
     let video_id = extract_video_id(video_url)
-        .ok_or_else(|| format!("Invalid YouTube URL: {video_url}"))?;
+        .ok_or_else(|| Error::InvalidUrl(video_url.to_string()))?;
+
+    let (video_details, tracks) = fetch_video(video_id)?;
+
+    let tracks = tracks.ok_or_else(|| {
+        if video_details.is_live == Some(true) {
+            Error::LiveInProgress
+        } else {
+            Error::NoCaptions(video_id.to_string())
+        }
+    })?;
+
+    let track = select_best_track(&tracks, language)?;
+    let events = fetch_caption_events(track)?;
+
+    let length_ms = video_details
+        .length_seconds
+        .as_deref()
+        .and_then(|l| l.parse::<u64>().ok())
+        .filter(|_| video_details.is_live_content == Some(true))
+        .map(|l| l.saturating_mul(1000));
+    let (transcript, gaps) = match length_ms {
+        Some(length_ms) => stitch(events, length_ms),
+        None => (process_json_captions(events), Vec::new()),
+    };
+    if !gaps.is_empty() {
+        log::warn!("Stream captions have gaps"; video_id = video_id, gaps = gaps.len());
+    }
+
+    Ok(VideoData {
+        title: video_details.title,
+        transcript,
+        gaps,
+    })
+}
+
+/// Captions of a live stream, accumulated across polls.
+/// Each poll returns the captions currently served for the stream, which may only be a recent window,
+/// so segments are merged by start time instead of replacing the transcript.
+pub struct LiveCaptions {
+    video_id: String,
+    language: String,
+    title: String,
+    is_live: bool,
+    segments: BTreeMap<u64, String>,
+}
+
+impl LiveCaptions {
+    pub fn open(video_url: &str, language: &str) -> Result<Self, Error> {
+        let video_id = extract_video_id(video_url)
+            .ok_or_else(|| Error::InvalidUrl(video_url.to_string()))?;
+
+        let mut captions = Self {
+            video_id: video_id.to_string(),
+            language: language.to_string(),
+            title: String::new(),
+            is_live: true,
+            segments: BTreeMap::new(),
+        };
+        captions.poll()?;
+
+        Ok(captions)
+    }
+
+    /// Fetches the latest captions and merges them in. Returns the number of new segments.
+    /// Captions may not exist yet in the first minutes of a stream, which isn't an error while it's live.
+    pub fn poll(&mut self) -> Result<usize, Error> {
+        let (video_details, tracks) = fetch_video(&self.video_id)?;
+        self.is_live = video_details.is_live == Some(true);
+        self.title = video_details.title;
+
+        let Some(tracks) = tracks else {
+            return if self.is_live {
+                Ok(0)
+            } else {
+                Err(Error::NoCaptions(self.video_id.clone()))
+            };
+        };
+
+        let track = select_best_track(&tracks, &self.language)?;
+        let before = self.segments.len();
 
-    let config = fetch_player_config(video_id)?;
+        for event in fetch_caption_events(track)? {
+            let (Some(start), Some(segs)) = (event.t_start_ms, event.segs) else {
+                continue;
+            };
+            let text = join_segments(&segs);
+            if !text.is_empty() {
+                self.segments.insert(start, text);
+            }
+        }
+
+        Ok(self.segments.len().saturating_sub(before))
+    }
+
+    pub const fn is_live(&self) -> bool {
+        self.is_live
+    }
+
+    pub fn title(&self) -> &str {
+        &self.title
+    }
+
+    pub fn transcript(&self) -> String {
+        self.segments.values().map(String::as_str).collect::<Vec<_>>().join(" ")
+    }
+}
+
+/// Runs the player request and checks the video can be read.
+/// Caption tracks are `None` when the video has none (yet).
+fn fetch_video(video_id: &str) -> Result<(VideoDetails, Option<Vec<CaptionTrack>>), Error> {
+    let (config, from_cache) = get_player_config(video_id)?;
+
+    let player_data = match fetch_player_data_with_fallback(video_id, &config) {
+        Ok(data) => data,
+        Err(e) if from_cache && !matches!(e, Error::DeadlineExceeded) => {
+            // A stale config (rotated key or signature timestamp) is the most likely cause, so refetch once
+            log::warn!("Player request failed with cached config, refreshing"; error = e);
+            invalidate_player_config();
+            let (config, _) = get_player_config(video_id)?;
+            fetch_player_data_with_fallback(video_id, &config)?
+        }
+        Err(e) => return Err(e),
+    };
+
+    if let Some(status) = &player_data.playability_status {
+        check_playability(status)?;
+    }
+
+    let video_details = player_data
+        .video_details
+        .ok_or(Error::VideoDetailsMissing)?;
+
+    let tracks = player_data
+        .captions
+        .and_then(|c| c.player_captions_tracklist_renderer)
+        .map(|r| r.caption_tracks)
+        .filter(|tracks| !tracks.is_empty());
+
+    Ok((video_details, tracks))
+}
+
+fn fetch_caption_events(track: &CaptionTrack) -> Result<Vec<JsonCaptionEvent>, Error> {
+    let url = format!("{}&fmt=json3", track.base_url.replace("\\u0026", "&"));
+    let caption_response = send(with_cookies(minreq::get(url).with_header("User-Agent", USER_AGENT)))?;
+
+    if !(200..=299).contains(&caption_response.status_code) {
+        return Err(Error::Status(caption_response.status_code));
+    }
+
+    let caption_response: JsonCaptionResponse =
+        json::from_slice(caption_response.as_bytes()).map_err(Error::Json)?;
+
+    Ok(caption_response.events)
+}
+
+/// Automatic captions of a recently finished stream are published in pieces while the archive is processed,
+/// which can arrive out of order and overlapping. Puts the pieces in order, keeps one of each, and marks
+/// where captions are missing, in the transcript and in the returned gaps, so a summary of what's there
+/// doesn't pass for one of the whole stream.
+fn stitch(events: Vec<JsonCaptionEvent>, length_ms: u64) -> (String, Vec<Gap>) {
+    let mut pieces = BTreeMap::new();
+    for event in events {
+        let (Some(start), Some(segs)) = (event.t_start_ms, event.segs) else {
+            continue;
+        };
+        let text = join_segments(&segs);
+        if !text.is_empty() {
+            let end = start.saturating_add(event.d_duration_ms.unwrap_or(0));
+            pieces.insert(start, (end, text));
+        }
+    }
+
+    let mut transcript = String::new();
+    let mut gaps = Vec::new();
+    let mut mark_gap = |transcript: &mut String, from_ms: u64, to_ms: u64| {
+        let gap = Gap {
+            start: from_ms / 1000,
+            end: to_ms / 1000,
+        };
+        if !transcript.is_empty() {
+            transcript.push(' ');
+        }
+        let _ = write!(
+            transcript,
+            "[No captions from {} to {}]",
+            datetime::format_duration(gap.start),
+            datetime::format_duration(gap.end)
+        );
+        gaps.push(gap);
+    };
+
+    let mut covered_ms = 0;
+    for (start, (end, text)) in pieces {
+        if start.saturating_sub(covered_ms) >= MIN_GAP_MS {
+            mark_gap(&mut transcript, covered_ms, start);
+        }
+        if !transcript.is_empty() {
+            transcript.push(' ');
+        }
+        transcript.push_str(&text);
+        covered_ms = covered_ms.max(end);
+    }
+    if length_ms.saturating_sub(covered_ms) >= MIN_GAP_MS {
+        mark_gap(&mut transcript, covered_ms, length_ms);
+    }
+
+    (transcript, gaps)
+}
+
+fn join_segments(segs: &[CaptionSegment]) -> String {
+    segs.iter()
+        .map(|seg| seg.utf8.trim())
+        .filter(|t| !t.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Runs the player request with the client that worked last, or the next ones in `CLIENTS` if it doesn't return captions
+fn fetch_player_data_with_fallback(video_id: &str, config: &PlayerConfig) -> Result<PlayerDataResponse, Error> {
+    let preferred = PREFERRED_CLIENT.load(Ordering::Relaxed);
+    let (index, data) = try_clients(preferred, |client| fetch_player_data(video_id, config, client))?;
+
+    if let Some(index) = index.filter(|&index| index != preferred) {
+        log::info!("Switched innertube client"; client = CLIENTS[index].name);
+        PREFERRED_CLIENT.store(index, Ordering::Relaxed);
+    }
+    Ok(data)
+}
+
+/// Tries every client in `CLIENTS`, starting at `preferred`, until one returns caption tracks, and returns its index.
+/// If none do, the first successfully parsed response is returned without an index so the caller can report why.
+/// Only fails when every client failed at the request level.
+fn try_clients(
+    preferred: usize,
+    mut fetch: impl FnMut(&InnertubeClient) -> Result<PlayerDataResponse, Error>,
+) -> Result<(Option<usize>, PlayerDataResponse), Error> {
+    let mut fallback = None;
+    let mut last_error = None;
+
+    for index in (0..CLIENTS.len()).map(|i| (preferred + i) % CLIENTS.len()) {
+        let client = &CLIENTS[index];
+
+        match fetch(client) {
+            Ok(data) if has_caption_tracks(&data) => return Ok((Some(index), data)),
+            // A playable response beats a refusal (like WEB's bot check) when explaining why there are no captions
+            Ok(data) if fallback.as_ref().is_none_or(|kept| !is_playable(kept) && is_playable(&data)) => {
+                fallback = Some(data);
+            }
+            Ok(_) => {}
+            // Every other client would fail the same way
+            Err(Error::DeadlineExceeded) => return Err(Error::DeadlineExceeded),
+            Err(e) => {
+                log::warn!("Player request failed"; client = client.name, error = e);
+                last_error = Some(e);
+            }
+        }
+    }
+
+    fallback
+        .map(|data| (None, data))
+        .ok_or_else(|| last_error.unwrap_or_else(|| Error::Unplayable("No innertube client succeeded".into())))
+}
+
+/// Maps a non-OK playability status to the error explaining why the video can't be read
+fn check_playability(status: &PlayabilityStatus) -> Result<(), Error> {
+    let reason = status.reason.clone().unwrap_or_else(|| status.status.clone());
+    let lowercase_reason = reason.to_lowercase();
+
+    match status.status.as_str() {
+        "OK" => Ok(()),
+        "LOGIN_REQUIRED" if lowercase_reason.contains("private") => Err(Error::Private),
+        "LOGIN_REQUIRED" if lowercase_reason.contains("confirm your age") => Err(Error::AgeRestricted),
+        "LOGIN_REQUIRED" => Err(Error::LoginRequired(reason)),
+        "LIVE_STREAM_OFFLINE" => Err(Error::Upcoming {
+            scheduled_start: status.scheduled_start(),
+            reason,
+        }),
+        "ERROR" => Err(Error::Unavailable(reason)),
+        _ if lowercase_reason.contains("members") => Err(Error::LoginRequired(reason)),
+        _ if lowercase_reason.contains("country") => Err(Error::RegionBlocked(reason)),
+        // UNPLAYABLE, AGE_CHECK_REQUIRED, CONTENT_CHECK_REQUIRED and anything new
+        _ => Err(Error::Unplayable(reason)),
+    }
+}
+
+fn is_playable(data: &PlayerDataResponse) -> bool {
+    data.playability_status.as_ref().is_none_or(|status| status.status == "OK")
+}
+
+fn has_caption_tracks(data: &PlayerDataResponse) -> bool {
+    data.captions
+        .as_ref()
+        .and_then(|c| c.player_captions_tracklist_renderer.as_ref())
+        .is_some_and(|r| !r.caption_tracks.is_empty())
+}
+
+fn fetch_player_data(video_id: &str, config: &PlayerConfig, client: &InnertubeClient) -> Result<PlayerDataResponse, Error> {
+    let client_version = client.version.unwrap_or(&config.client_version);
+    let third_party = if client.embedded {
+        r#", "thirdParty": { "embedUrl": "https://www.youtube.com/" }"#
+    } else {
+        ""
+    };
 
     let request_body = format!(
         r#"{{
             "context": {{
                 "client": {{
-                    "clientName": "WEB",
-                    "clientVersion": "{client_version}"
-                }}
+                    "clientName": "{client_name}",
+                    "clientVersion": "{client_version}",
+                    "hl": "en"{client_context}
+                }}{third_party}
             }},
             "videoId": "{video_id}",
             "playbackContext": {{
@@ -77,7 +690,8 @@ pub fn get_video_data(video_url: &str, language: &str) -> Result<(String, String
                 }}
             }}
         }}"#,
-        client_version = config.client_version,
+        client_name = client.name,
+        client_context = client.context,
         sts = config.signature_timestamp,
     );
 
@@ -86,51 +700,112 @@ pub fn get_video_data(video_url: &str, language: &str) -> Result<(String, String
         config.api_key
     );
 
-    let player_response = minreq::post(api_url)
-        .with_header("User-Agent", USER_AGENT)
+    let mut request = with_cookies(minreq::post(api_url))
+        .with_header("User-Agent", client.user_agent)
         .with_header("Referer", "https://www.youtube.com/")
-        .with_body(request_body)
-        .send()?;
+        .with_header("Origin", ORIGIN)
+        .with_header("X-YouTube-Client-Name", client.id.to_string())
+        .with_header("X-YouTube-Client-Version", client_version);
 
-    let player_data: PlayerDataResponse = json::from_slice(player_response.as_bytes())?;
+    if let Some(authorization) = sapisid_authorization() {
+        request = request
+            .with_header("Authorization", authorization)
+            .with_header("X-Origin", ORIGIN)
+            .with_header("X-Goog-AuthUser", "0");
+    }
 
-    let video_title = player_data
-        .video_details
-        .ok_or("Video details not found")?
-        .title;
+    let player_response = send(request.with_body(request_body))?;
 
-    let tracks = player_data
-        .captions
-        .and_then(|c| c.player_captions_tracklist_renderer)
-        .map(|r| r.caption_tracks)
-        .ok_or_else(|| format!("No captions found for video: {video_id}"))?;
+    if !(200..=299).contains(&player_response.status_code) {
+        return Err(Error::Status(player_response.status_code));
+    }
 
-    let track = select_best_track(&tracks, language)?;
+    json::from_slice(player_response.as_bytes()).map_err(Error::Json)
+}
 
-    let url = format!("{}&fmt=json3", track.base_url.replace("\\u0026", "&"));
-    let caption_response: JsonCaptionResponse = json::from_slice(minreq::get(url).send()?.as_bytes())?;
+/// Returns the shared player config, fetching a new one if the cached copy is missing or expired.
+/// The flag tells whether the config came from the cache.
+fn get_player_config(video_id: &str) -> Result<(Arc<PlayerConfig>, bool), Error> {
+    // The player config only changes when YouTube ships a new base.js, so one fetch can serve many requests
+    let ttl = Duration::from_secs(config::get().cache.player_config_ttl_secs);
+    let mut cache = lock_player_config_cache();
+
+    loop {
+        if let Some(cached) = cache.cached.as_ref()
+            && cached.fetched_at.elapsed() < ttl {
+                metrics::PLAYER_CONFIG_CACHE.hit();
+                return Ok((Arc::clone(&cached.config), true));
+        }
+        if !cache.fetching {
+            break;
+        }
+        // The lock isn't held during the fetch, so waiting here keeps to this request's deadline
+        cache = match deadline::current() {
+            Some(deadline) => {
+                let left = deadline
+                    .checked_duration_since(Instant::now())
+                    .filter(|left| !left.is_zero())
+                    .ok_or(Error::DeadlineExceeded)?;
+                PLAYER_CONFIG_FETCHED.wait_timeout(cache, left).unwrap_or_else(PoisonError::into_inner).0
+            }
+            None => PLAYER_CONFIG_FETCHED.wait(cache).unwrap_or_else(PoisonError::into_inner),
+        };
+    }
+    metrics::PLAYER_CONFIG_CACHE.miss();
+    cache.fetching = true;
+    drop(cache);
+
+    let _fetching = FetchingPlayerConfig;
+
+    let config = Arc::new(fetch_player_config(video_id)?);
+    lock_player_config_cache().cached = Some(CachedPlayerConfig {
+        config: Arc::clone(&config),
+        fetched_at: Instant::now(),
+    });
 
-    let transcript = process_json_captions(caption_response.events);
+    Ok((config, false))
+}
+
+/// Wakes the waiting requests however the fetch ends, so one of them can try again if it failed
+struct FetchingPlayerConfig;
 
-    Ok((transcript, video_title))
+impl Drop for FetchingPlayerConfig {
+    fn drop(&mut self) {
+        lock_player_config_cache().fetching = false;
+        PLAYER_CONFIG_FETCHED.notify_all();
+    }
 }
 
-fn fetch_player_config(video_id: &str) -> Result<PlayerConfig, Box<dyn Error>> {
+fn lock_player_config_cache() -> MutexGuard<'static, PlayerConfigCache> {
+    PLAYER_CONFIG_CACHE.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+fn invalidate_player_config() {
+    lock_player_config_cache().cached = None;
+}
+
+fn fetch_player_config(video_id: &str) -> Result<PlayerConfig, Error> {
     let page_url = format!("https://www.youtube.com/watch?v={video_id}");
-    let page_response = minreq::get(&page_url)
-        .with_header("User-Agent", USER_AGENT)
-        .send()?;
-    let page_html = page_response.as_str()?;
+    let page_response = send(with_cookies(minreq::get(&page_url)).with_header("User-Agent", USER_AGENT))?;
+    let page_html = page_response
+        .as_str()
+        .map_err(Error::Request)?;
+
+    if page_response.url.contains("consent.youtube.com")
+        || page_html.contains("action=\"https://consent.youtube.com")
+    {
+        return Err(Error::ConsentRequired);
+    }
 
     let js_path = extract_json_string_value(page_html, "jsUrl")
-        .ok_or("Could not find jsUrl in video page")?;
+        .ok_or(Error::PlayerConfig("jsUrl"))?;
 
     let client_version = extract_json_string_value(page_html, "clientVersion")
-        .ok_or("Could not find clientVersion")?
+        .ok_or(Error::PlayerConfig("clientVersion"))?
         .to_string();
 
     let api_key = extract_json_string_value(page_html, "INNERTUBE_API_KEY")
-        .ok_or("Could not find INNERTUBE_API_KEY")?
+        .ok_or(Error::PlayerConfig("INNERTUBE_API_KEY"))?
         .to_string();
 
     let js_url = if js_path.starts_with("http") {
@@ -139,12 +814,10 @@ fn fetch_player_config(video_id: &str) -> Result<PlayerConfig, Box<dyn Error>> {
         format!("https://www.youtube.com{js_path}")
     };
 
-    let js_response = minreq::get(&js_url)
-        .with_header("User-Agent", USER_AGENT)
-        .send()?;
+    let js_response = send(with_cookies(minreq::get(&js_url)).with_header("User-Agent", USER_AGENT))?;
 
-    let signature_timestamp = extract_signature_timestamp(js_response.as_str()?)
-        .ok_or("Could not find signatureTimestamp in JS player")?;
+    let signature_timestamp = extract_signature_timestamp(js_response.as_str().map_err(Error::Request)?)
+        .ok_or(Error::PlayerConfig("signatureTimestamp"))?;
 
     Ok(PlayerConfig {
         client_version,
@@ -154,7 +827,7 @@ fn fetch_player_config(video_id: &str) -> Result<PlayerConfig, Box<dyn Error>> {
 }
 
 fn extract_json_string_value<'a>(text: &'a str, key: &str) -> Option<&'a str> {
-    let search = format!("\"{}\":\"", key);
+    let search = format!("\"{key}\":\"");
     let mut start = 0;
     while let Some(pos) = text[start..].find(&search) {
         let value_start = start + pos + search.len();
@@ -174,13 +847,12 @@ fn extract_signature_timestamp(js_code: &str) -> Option<u64> {
 
             let digits: String = js_code[abs_pos..]
                 .chars()
-                .take_while(|c| c.is_ascii_digit())
+                .take_while(char::is_ascii_digit)
                 .collect();
 
-            if !digits.is_empty() {
-                if let Ok(val) = digits.parse::<u64>() {
+            if !digits.is_empty()
+                && let Ok(val) = digits.parse::<u64>() {
                     return Some(val);
-                }
             }
             search_from = abs_pos;
         }
@@ -188,7 +860,7 @@ fn extract_signature_timestamp(js_code: &str) -> Option<u64> {
     None
 }
 
-fn extract_video_id(url: &str) -> Option<&str> {
+pub fn extract_video_id(url: &str) -> Option<&str> {
     const PATTERNS: &[&str] = &["v=", "/embed/", "/live/", "/v/", "/shorts/", "youtu.be/"];
 
     for pattern in PATTERNS {
@@ -200,7 +872,7 @@ fn extract_video_id(url: &str) -> Option<&str> {
     None
 }
 
-fn select_best_track<'a>(tracks: &'a [CaptionTrack], language: &str) -> Result<&'a CaptionTrack, Box<dyn Error>> {
+fn select_best_track<'a>(tracks: &'a [CaptionTrack], language: &str) -> Result<&'a CaptionTrack, Error> {
     tracks
         .iter()
         .filter(|t| t.language_code == language)
@@ -209,9 +881,9 @@ fn select_best_track<'a>(tracks: &'a [CaptionTrack], language: &str) -> Result<&
             else if t.base_url.contains("variant=punctuated") { 1 }
             else { 2 }
         })
-        .ok_or_else(|| {
-            let available: Vec<_> = tracks.iter().map(|t| &t.language_code).collect();
-            format!("No captions for '{language}'. Available: {available:?}").into()
+        .ok_or_else(|| Error::LanguageUnavailable {
+            language: language.to_string(),
+            available: tracks.iter().map(|t| t.language_code.clone()).collect(),
         })
 }
 
@@ -233,4 +905,184 @@ fn process_json_captions(events: Vec<JsonCaptionEvent>) -> String {
     }
 
     result
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A saved player response, trimmed to the parts that matter
+    macro_rules! fixture {
+        ($name:literal) => {
+            json::from_str::<PlayerDataResponse>(include_str!(concat!("fixtures/player/", $name, ".json")))
+                .expect(concat!("fixture ", $name, " should parse"))
+        };
+    }
+
+    fn playability(response: &PlayerDataResponse) -> Result<(), Error> {
+        check_playability(response.playability_status.as_ref().expect("fixture has a playability status"))
+    }
+
+    type Outcome = Result<(Option<usize>, PlayerDataResponse), Error>;
+
+    /// Runs the fallback with `answer` standing in for the player request, returning the clients in the order they were asked
+    fn run_clients(preferred: usize, answer: impl Fn(&str) -> Result<PlayerDataResponse, Error>) -> (Outcome, Vec<&'static str>) {
+        let mut asked = Vec::new();
+        let outcome = try_clients(preferred, |client| {
+            asked.push(client.name);
+            answer(client.name)
+        });
+        (outcome, asked)
+    }
+
+    #[test]
+    fn playable_videos_pass() {
+        assert!(playability(&fixture!("ok_with_captions")).is_ok());
+        assert!(playability(&fixture!("ok_without_captions")).is_ok());
+    }
+
+    #[test]
+    fn classifies_login_required() {
+        assert!(matches!(playability(&fixture!("private")), Err(Error::Private)));
+        assert!(matches!(playability(&fixture!("age_restricted")), Err(Error::AgeRestricted)));
+        assert!(matches!(
+            playability(&fixture!("login_required_bot_check")),
+            Err(Error::LoginRequired(reason)) if reason.contains("not a bot")
+        ));
+    }
+
+    #[test]
+    fn classifies_unplayable() {
+        assert!(matches!(playability(&fixture!("members_only")), Err(Error::LoginRequired(_))));
+        assert!(matches!(playability(&fixture!("region_blocked")), Err(Error::RegionBlocked(_))));
+        assert!(matches!(playability(&fixture!("embedding_disabled")), Err(Error::Unplayable(_))));
+        assert!(matches!(playability(&fixture!("unavailable")), Err(Error::Unavailable(_))));
+    }
+
+    #[test]
+    fn classifies_upcoming_with_start_time() {
+        assert!(matches!(
+            playability(&fixture!("live_offline")),
+            Err(Error::Upcoming { scheduled_start: Some(1_767_225_600), .. })
+        ));
+    }
+
+    #[test]
+    fn stops_at_first_client_with_captions() {
+        let (outcome, asked) = run_clients(0, |client| match client {
+            "WEB" => Ok(fixture!("login_required_bot_check")),
+            "ANDROID" => Ok(fixture!("ok_without_captions")),
+            _ => Ok(fixture!("ok_with_captions")),
+        });
+
+        let (index, data) = outcome.expect("a client returned captions");
+        assert_eq!(index.map(|i| CLIENTS[i].name), Some("IOS"));
+        assert!(has_caption_tracks(&data));
+        assert_eq!(asked, ["WEB", "ANDROID", "IOS"]);
+    }
+
+    #[test]
+    fn starts_with_preferred_client_and_wraps_around() {
+        let (outcome, asked) = run_clients(3, |client| match client {
+            "ANDROID" => Ok(fixture!("ok_with_captions")),
+            _ => Err(Error::Status(403)),
+        });
+
+        let (index, _) = outcome.expect("a client returned captions");
+        assert_eq!(index, Some(1));
+        assert_eq!(asked, ["TVHTML5_SIMPLY_EMBEDDED_PLAYER", "MWEB", "WEB", "ANDROID"]);
+    }
+
+    #[test]
+    fn without_captions_reports_first_refusal_when_none_are_playable() {
+        let (outcome, asked) = run_clients(0, |client| match client {
+            "WEB" => Err(Error::Status(500)),
+            "ANDROID" => Ok(fixture!("private")),
+            _ => Ok(fixture!("login_required_bot_check")),
+        });
+
+        let (index, data) = outcome.expect("some clients got a response");
+        assert_eq!(index, None);
+        assert!(matches!(playability(&data), Err(Error::Private)));
+        assert_eq!(asked.len(), CLIENTS.len());
+    }
+
+    #[test]
+    fn without_captions_prefers_a_playable_response() {
+        let (outcome, _) = run_clients(0, |client| match client {
+            "WEB" => Ok(fixture!("login_required_bot_check")),
+            "IOS" => Ok(fixture!("ok_without_captions")),
+            _ => Err(Error::Status(500)),
+        });
+
+        let (index, data) = outcome.expect("some clients got a response");
+        assert_eq!(index, None);
+        assert!(playability(&data).is_ok());
+    }
+
+    #[test]
+    fn fails_with_last_error_when_every_request_fails() {
+        let (outcome, asked) = run_clients(0, |client| match client {
+            "MWEB" => Err(Error::Status(429)),
+            _ => Err(Error::Status(500)),
+        });
+
+        assert!(matches!(outcome, Err(Error::Status(429))));
+        assert_eq!(asked.len(), CLIENTS.len());
+    }
+
+    #[test]
+    fn deadline_stops_the_fallback() {
+        let (outcome, asked) = run_clients(0, |_| Err(Error::DeadlineExceeded));
+
+        assert!(matches!(outcome, Err(Error::DeadlineExceeded)));
+        assert_eq!(asked, ["WEB"]);
+    }
+
+    fn events(json: &str) -> Vec<JsonCaptionEvent> {
+        json::from_str::<JsonCaptionResponse>(json)
+            .expect("valid json3 captions")
+            .events
+    }
+
+    #[test]
+    fn stitches_stream_pieces_in_order() {
+        // Published out of order, with the second piece twice
+        let events = events(
+            r#"{"events": [
+                {"tStartMs": 60000, "dDurationMs": 2000, "segs": [{"utf8": "second"}]},
+                {"tStartMs": 0, "dDurationMs": 2000, "segs": [{"utf8": "first"}, {"utf8": " part "}]},
+                {"tStartMs": 60000, "dDurationMs": 2000, "segs": [{"utf8": "second"}]},
+                {"tStartMs": 90000, "segs": [{"utf8": "\n"}]},
+                {"tStartMs": 120000, "dDurationMs": 170000, "segs": [{"utf8": "third"}]}
+            ]}"#,
+        );
+
+        let (transcript, gaps) = stitch(events, 300_000);
+        assert_eq!(transcript, "first part second third");
+        assert!(gaps.is_empty());
+    }
+
+    #[test]
+    fn marks_where_stream_captions_are_missing() {
+        let events = events(
+            r#"{"events": [
+                {"tStartMs": 0, "dDurationMs": 60000, "segs": [{"utf8": "start"}]},
+                {"tStartMs": 1800000, "dDurationMs": 60000, "segs": [{"utf8": "middle"}]}
+            ]}"#,
+        );
+
+        let (transcript, gaps) = stitch(events, 3_600_000);
+        assert_eq!(
+            transcript,
+            "start [No captions from 1m to 30m] middle [No captions from 31m to 1h 0m]"
+        );
+        assert_eq!(
+            gaps,
+            [
+                Gap { start: 60, end: 1800 },
+                Gap { start: 1860, end: 3600 }
+            ]
+        );
+    }
+}
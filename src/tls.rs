@@ -0,0 +1,371 @@
+use crate::error::Error;
+use crate::http::{self, Parsed, Request, Response};
+use crate::log;
+use std::fs;
+use std::io::{self, Read};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, OnceLock, PoisonError};
+use std::time::{Duration, Instant, SystemTime};
+
+const RELOAD_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+const REDIRECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[cfg(feature = "rustls-tls")]
+mod backend {
+    use crate::http::Connection;
+    use rustls::pki_types::pem::PemObject;
+    use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+    use rustls::{
+        ClientConfig, ClientConnection, RootCertStore, ServerConfig, ServerConnection, StreamOwned,
+    };
+    use std::io;
+    use std::net::TcpStream;
+    use std::sync::{Arc, LazyLock};
+
+    pub type Acceptor = Arc<ServerConfig>;
+    pub type TlsStream = StreamOwned<ServerConnection, TcpStream>;
+    pub type ClientStream = StreamOwned<ClientConnection, TcpStream>;
+
+    fn provider() -> Arc<rustls::crypto::CryptoProvider> {
+        // Named outright: with another provider compiled in by a dependency, `builder()` can't
+        // pick one and panics. This is the one minreq uses for outgoing requests.
+        Arc::new(rustls::crypto::aws_lc_rs::default_provider())
+    }
+
+    pub fn acceptor(cert: &[u8], key: &[u8]) -> io::Result<Acceptor> {
+        let certs = CertificateDer::pem_slice_iter(cert)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| io::Error::other(format!("Invalid certificate: {e}")))?;
+        let key = PrivateKeyDer::from_pem_slice(key)
+            .map_err(|e| io::Error::other(format!("Invalid private key: {e}")))?;
+
+        let mut config = ServerConfig::builder_with_provider(provider())
+            .with_safe_default_protocol_versions()
+            .map_err(io::Error::other)?
+            .with_no_client_auth()
+            .with_single_cert(certs, key)
+            .map_err(io::Error::other)?;
+        config.alpn_protocols = vec![b"http/1.1".to_vec()];
+
+        Ok(Arc::new(config))
+    }
+
+    /// A client connection to `host` over `stream`, checked against the same roots minreq trusts.
+    /// The handshake happens on the first write.
+    pub fn connect(host: &str, stream: TcpStream) -> io::Result<ClientStream> {
+        static CONFIG: LazyLock<Result<Arc<ClientConfig>, rustls::Error>> = LazyLock::new(|| {
+            let roots = RootCertStore {
+                roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+            };
+            let config = ClientConfig::builder_with_provider(provider())
+                .with_safe_default_protocol_versions()?
+                .with_root_certificates(roots)
+                .with_no_client_auth();
+            Ok(Arc::new(config))
+        });
+
+        let config = CONFIG.as_ref().map_err(|e| io::Error::other(e.clone()))?;
+        let name = ServerName::try_from(host.to_string()).map_err(io::Error::other)?;
+        let connection =
+            ClientConnection::new(Arc::clone(config), name).map_err(io::Error::other)?;
+        Ok(StreamOwned::new(connection, stream))
+    }
+
+    /// rustls runs the handshake as part of the first reads and writes, so there's nothing to wait for here
+    pub fn start(acceptor: &Acceptor, stream: TcpStream) -> io::Result<TlsStream> {
+        let connection = ServerConnection::new(Arc::clone(acceptor)).map_err(io::Error::other)?;
+        Ok(StreamOwned::new(connection, stream))
+    }
+
+    impl Connection for TlsStream {
+        fn tcp(&self) -> &TcpStream {
+            self.get_ref()
+        }
+
+        fn wants_write(&self) -> bool {
+            self.conn.wants_write()
+        }
+    }
+}
+
+#[cfg(not(feature = "rustls-tls"))]
+mod backend {
+    use crate::http::Connection;
+    use native_tls::{HandshakeError, Identity, MidHandshakeTlsStream, TlsAcceptor, TlsConnector};
+    use std::io::{self, Read, Write};
+    use std::mem;
+    use std::net::TcpStream;
+    use std::sync::Arc;
+
+    pub type Acceptor = TlsAcceptor;
+    pub type ClientStream = native_tls::TlsStream<TcpStream>;
+
+    /// Shares the socket with `TlsStream`, which needs it for polling even mid-handshake
+    pub struct Socket(Arc<TcpStream>);
+
+    impl Read for Socket {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            (&*self.0).read(buf)
+        }
+    }
+
+    impl Write for Socket {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            (&*self.0).write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            (&*self.0).flush()
+        }
+    }
+
+    enum State {
+        Handshaking(MidHandshakeTlsStream<Socket>),
+        Established(native_tls::TlsStream<Socket>),
+        Failed,
+    }
+
+    /// A TLS connection on a non-blocking socket. The handshake is resumed by reads and writes until it's done.
+    pub struct TlsStream {
+        socket: Arc<TcpStream>,
+        state: State,
+    }
+
+    pub fn acceptor(cert: &[u8], key: &[u8]) -> io::Result<Acceptor> {
+        let identity = Identity::from_pkcs8(cert, key)
+            .map_err(|e| io::Error::other(format!("Invalid certificate or key: {e}")))?;
+        TlsAcceptor::new(identity).map_err(io::Error::other)
+    }
+
+    /// A client connection to `host` over a blocking `stream`, checked against the system's roots
+    pub fn connect(host: &str, stream: TcpStream) -> io::Result<ClientStream> {
+        let connector = TlsConnector::new().map_err(io::Error::other)?;
+        connector.connect(host, stream).map_err(|e| match e {
+            HandshakeError::Failure(e) => handshake_failed(&e),
+            HandshakeError::WouldBlock(_) => io::ErrorKind::WouldBlock.into(),
+        })
+    }
+
+    pub fn start(acceptor: &Acceptor, stream: TcpStream) -> io::Result<TlsStream> {
+        let socket = Arc::new(stream);
+        let state = match acceptor.accept(Socket(Arc::clone(&socket))) {
+            Ok(stream) => State::Established(stream),
+            Err(HandshakeError::WouldBlock(mid)) => State::Handshaking(mid),
+            Err(HandshakeError::Failure(e)) => return Err(handshake_failed(&e)),
+        };
+        Ok(TlsStream { socket, state })
+    }
+
+    fn handshake_failed(e: &native_tls::Error) -> io::Error {
+        io::Error::other(format!("TLS handshake failed: {e}"))
+    }
+
+    impl TlsStream {
+        fn established(&mut self) -> io::Result<&mut native_tls::TlsStream<Socket>> {
+            self.state = match mem::replace(&mut self.state, State::Failed) {
+                State::Handshaking(mid) => match mid.handshake() {
+                    Ok(stream) => State::Established(stream),
+                    Err(HandshakeError::WouldBlock(mid)) => {
+                        self.state = State::Handshaking(mid);
+                        return Err(io::ErrorKind::WouldBlock.into());
+                    }
+                    Err(HandshakeError::Failure(e)) => return Err(handshake_failed(&e)),
+                },
+                state => state,
+            };
+
+            match &mut self.state {
+                State::Established(stream) => Ok(stream),
+                _ => Err(io::Error::other("TLS handshake failed")),
+            }
+        }
+    }
+
+    impl Read for TlsStream {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.established()?.read(buf)
+        }
+    }
+
+    impl Write for TlsStream {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.established()?.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.established()?.flush()
+        }
+    }
+
+    impl Connection for TlsStream {
+        fn tcp(&self) -> &TcpStream {
+            &self.socket
+        }
+    }
+}
+
+pub use backend::{TlsStream, connect};
+
+struct Certificate {
+    cert_path: PathBuf,
+    key_path: PathBuf,
+    state: Mutex<LoadedCertificate>,
+}
+
+struct LoadedCertificate {
+    acceptor: Arc<backend::Acceptor>,
+    modified: (Option<SystemTime>, Option<SystemTime>),
+    checked_at: Instant,
+}
+
+static CERTIFICATE: OnceLock<Certificate> = OnceLock::new();
+
+/// Loads the PEM certificate chain and PKCS#8 private key, after which every connection is served over TLS.
+/// The files are watched and reloaded when they change, so renewed certificates are picked up without a restart.
+pub fn configure(cert_path: PathBuf, key_path: PathBuf) -> io::Result<()> {
+    let state = load(&cert_path, &key_path)?;
+    let _ = CERTIFICATE.set(Certificate {
+        cert_path,
+        key_path,
+        state: Mutex::new(state),
+    });
+    Ok(())
+}
+
+pub fn is_enabled() -> bool {
+    CERTIFICATE.get().is_some()
+}
+
+/// Starts serving TLS on a freshly accepted, non-blocking connection. The handshake finishes as data arrives.
+pub fn accept(stream: TcpStream) -> io::Result<TlsStream> {
+    let certificate = CERTIFICATE
+        .get()
+        .ok_or_else(|| io::Error::other("TLS is not configured"))?;
+
+    let acceptor = {
+        let mut state = certificate
+            .state
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        if state.checked_at.elapsed() >= RELOAD_CHECK_INTERVAL {
+            reload_if_changed(certificate, &mut state);
+        }
+        Arc::clone(&state.acceptor)
+    };
+
+    backend::start(&acceptor, stream)
+}
+
+fn load(cert_path: &PathBuf, key_path: &PathBuf) -> io::Result<LoadedCertificate> {
+    let modified = (modified(cert_path), modified(key_path));
+    let acceptor = backend::acceptor(&fs::read(cert_path)?, &fs::read(key_path)?)?;
+
+    Ok(LoadedCertificate {
+        acceptor: Arc::new(acceptor),
+        modified,
+        checked_at: Instant::now(),
+    })
+}
+
+fn reload_if_changed(certificate: &Certificate, state: &mut LoadedCertificate) {
+    state.checked_at = Instant::now();
+
+    let modified = (
+        modified(&certificate.cert_path),
+        modified(&certificate.key_path),
+    );
+    if modified == state.modified {
+        return;
+    }
+
+    match load(&certificate.cert_path, &certificate.key_path) {
+        Ok(loaded) => {
+            *state = loaded;
+            log::info!("Reloaded TLS certificate");
+        }
+        Err(e) => {
+            // Retried on the next check, the cert and key may have been mid-update
+            log::warn!("Failed to reload TLS certificate, keeping the old one"; error = e);
+        }
+    }
+}
+
+fn modified(path: &PathBuf) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// Answers every plain HTTP request with a permanent redirect to the same URL on the HTTPS port
+pub fn redirect_to_https(listener: &TcpListener, https_port: u16) {
+    for stream in listener.incoming().flatten() {
+        // Connections are served one at a time, so each gets a fixed budget for the whole
+        // request rather than per read, or a client sending a byte at a time could hold the port
+        let deadline = Instant::now() + REDIRECT_TIMEOUT;
+        if stream.set_write_timeout(Some(REDIRECT_TIMEOUT)).is_err() {
+            continue;
+        }
+        let Ok(peer) = stream.peer_addr() else {
+            continue;
+        };
+
+        let response = match read_head(&stream, peer, deadline) {
+            Ok(Some(request)) => match request.header("host") {
+                Some(host) => {
+                    let location =
+                        format!("https://{}{}", https_host(host, https_port), request.target);
+                    Response::new("301 Moved Permanently", "text/plain", Vec::new())
+                        .with_header("Location", location)
+                }
+                None => Response::new(
+                    "400 Bad Request",
+                    "text/plain",
+                    b"Missing Host header".to_vec(),
+                ),
+            },
+            Ok(None) => continue,
+            Err(e) => Response::error(&e),
+        };
+
+        let _ = http::write_response(&mut &stream, &response, false, false);
+    }
+}
+
+/// Reads a request without a body. Returns `None` if the client hung up or didn't finish by
+/// `deadline`.
+fn read_head(
+    mut stream: &TcpStream,
+    peer: SocketAddr,
+    deadline: Instant,
+) -> Result<Option<Request>, Error> {
+    let mut input = Vec::new();
+    let mut chunk = [0; 4096];
+
+    loop {
+        if let Parsed::Complete { request, .. } = http::parse_request(&input, peer, 0)? {
+            return Ok(Some(request));
+        }
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() || stream.set_read_timeout(Some(left)).is_err() {
+            return Ok(None);
+        }
+        match stream.read(&mut chunk) {
+            Ok(0) | Err(_) => return Ok(None),
+            Ok(read) => input.extend_from_slice(&chunk[..read]),
+        }
+    }
+}
+
+/// Swaps the port in a Host header for the HTTPS one, leaving it out when it's the default
+fn https_host(host: &str, https_port: u16) -> String {
+    let hostname = match host.rfind(':') {
+        // An IPv6 literal without a port, like `[::1]`, also contains colons
+        Some(i) if !host[i..].contains(']') => &host[..i],
+        _ => host,
+    };
+
+    if https_port == 443 {
+        hostname.to_string()
+    } else {
+        format!("{hostname}:{https_port}")
+    }
+}
@@ -0,0 +1,704 @@
+use crate::error::Error;
+use crate::{config, datetime, digest, log, shutdown};
+use miniserde::{Serialize, json};
+use std::fmt::{self, Write as _};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream, ToSocketAddrs};
+use std::thread;
+use std::time::Duration;
+
+#[cfg(not(test))]
+const FIRST_RETRY_DELAY: Duration = Duration::from_secs(2);
+#[cfg(test)]
+const FIRST_RETRY_DELAY: Duration = Duration::from_millis(10);
+const MAX_RETRY_DELAY: Duration = Duration::from_mins(1);
+const USER_AGENT: &str = concat!("YouTubeTLDR/", env!("CARGO_PKG_VERSION"));
+/// Only the status code of the answer is read, so there's no need for more than its first line
+const MAX_STATUS_LINE: u64 = 1024;
+
+/// The JSON body of a delivery
+#[derive(Serialize)]
+pub struct Payload {
+    /// `summary.completed` or `summary.failed`, or `live.finished`, `live.stopped` or `live.failed`
+    /// when a live job ends
+    pub event: &'static str,
+    /// Unique per event and the same across retries, so receivers can drop duplicates
+    pub delivery_id: String,
+    pub created_at: String,
+    pub url: String,
+    pub video_name: Option<String>,
+    pub summary: Option<String>,
+    /// Set when the summary was saved to the server-side history
+    pub history_id: Option<String>,
+    pub job_id: Option<String>,
+    pub error_code: Option<&'static str>,
+    pub error: Option<String>,
+}
+
+impl Payload {
+    pub fn new(event: &'static str, url: &str) -> Self {
+        Self {
+            event,
+            delivery_id: digest::random_hex(8),
+            created_at: datetime::format_rfc3339(datetime::now_unix()),
+            url: url.to_string(),
+            video_name: None,
+            summary: None,
+            history_id: None,
+            job_id: None,
+            error_code: None,
+            error: None,
+        }
+    }
+
+    pub fn failed(event: &'static str, url: &str, error: &Error) -> Self {
+        Self {
+            error_code: Some(error.code()),
+            // Receivers are outside the server, so they get what the logs get
+            error: Some(log::redact(&error.to_string()).into_owned()),
+            ..Self::new(event, url)
+        }
+    }
+}
+
+enum Failure {
+    /// Worth trying again, like a timeout or a 5xx
+    Temporary(String),
+    /// The receiver rejected it, retrying won't help
+    Permanent(String),
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Temporary(reason) | Self::Permanent(reason) => f.write_str(reason),
+        }
+    }
+}
+
+/// Where a request's events go
+#[derive(Clone)]
+pub struct Target {
+    url: String,
+    /// Only the configured URL is. A URL a request names could be anyone's, and signing for it
+    /// would hand them deliveries that pass as coming from this server.
+    signed: bool,
+    /// Held to public addresses. A request's own URL is, while the configured one may well be
+    /// on the local network.
+    public_only: bool,
+}
+
+pub fn is_valid_url(url: &str) -> bool {
+    url.strip_prefix("https://")
+        .or_else(|| url.strip_prefix("http://"))
+        .is_some_and(|rest| !rest.is_empty() && !rest.contains(char::is_whitespace))
+}
+
+/// Where to deliver events for a request: its own URL if it has one, otherwise the configured one.
+/// A request's own URL has to be on one of the `webhook.allowed_hosts`.
+pub fn target(requested: Option<&str>) -> Result<Option<Target>, Error> {
+    let settings = &config::get().webhook;
+    let Some(url) = requested.filter(|url| !url.is_empty()) else {
+        return Ok(settings.url.clone().map(|url| Target {
+            url,
+            signed: true,
+            public_only: false,
+        }));
+    };
+
+    let endpoint = Endpoint::parse(url)
+        .filter(|_| is_valid_url(url))
+        .ok_or_else(|| Error::BadRequest("webhook_url must be an http or https URL".into()))?;
+    let host = endpoint.host;
+    if !settings
+        .allowed_hosts
+        .iter()
+        .any(|allowed| allowed.eq_ignore_ascii_case(host))
+    {
+        return Err(Error::BadRequest(format!(
+            "webhook_url can't point at {host}, it's not one of the allowed webhook hosts"
+        )));
+    }
+    Ok(Some(Target {
+        url: url.to_string(),
+        signed: false,
+        public_only: true,
+    }))
+}
+
+/// Delivers `payload` in the background, retrying with backoff. Does nothing without a target.
+pub fn send(target: Option<Target>, payload: Payload) {
+    let Some(target) = target else {
+        return;
+    };
+    let timestamp = datetime::now_unix();
+    thread::spawn(move || {
+        log::scope("delivery_id", &payload.delivery_id, || {
+            deliver(&target, &payload, timestamp, &config::get().webhook);
+        });
+    });
+}
+
+fn deliver(target: &Target, payload: &Payload, timestamp: u64, settings: &config::Webhook) {
+    let body = json::to_string(payload);
+    let timestamp = timestamp.to_string();
+    let mut head = format!(
+        "Content-Type: application/json\r\nUser-Agent: {USER_AGENT}\r\nX-TLDR-Event: {}\r\n\
+         X-TLDR-Delivery: {}\r\nX-TLDR-Timestamp: {timestamp}\r\n",
+        payload.event, payload.delivery_id
+    );
+    if let Some(secret) = settings.secret.as_ref().filter(|_| target.signed) {
+        let _ = write!(
+            head,
+            "X-TLDR-Signature: {}\r\n",
+            sign(secret, &timestamp, &body)
+        );
+    }
+    let timeout = Duration::from_secs(settings.timeout_secs);
+
+    let mut delay = FIRST_RETRY_DELAY;
+    for attempt in 1..=settings.retries.saturating_add(1) {
+        let failure = match post(target, &head, &body, timeout) {
+            Ok(status) if (200..=299).contains(&status) => {
+                log::info!("Webhook delivered"; event = payload.event, attempt = attempt);
+                return;
+            }
+            Ok(status) => {
+                // Redirects aren't followed, so a 30x ends up here too
+                let reason = format!("receiver answered {status}");
+                if matches!(status, 408 | 429 | 500..) {
+                    Failure::Temporary(reason)
+                } else {
+                    Failure::Permanent(reason)
+                }
+            }
+            Err(failure) => failure,
+        };
+
+        log::warn!("Webhook delivery failed"; event = payload.event, attempt = attempt, error = failure);
+        if matches!(failure, Failure::Permanent(_)) || shutdown::requested() {
+            break;
+        }
+        if attempt <= settings.retries {
+            thread::sleep(delay);
+            delay = (delay * 2).min(MAX_RETRY_DELAY);
+        }
+    }
+
+    log::error!("Giving up on webhook delivery"; event = payload.event);
+}
+
+/// The `X-TLDR-Signature` header. Covering the timestamp lets receivers reject replays of old
+/// deliveries.
+fn sign(secret: &str, timestamp: &str, body: &str) -> String {
+    let message = format!("{timestamp}.{body}");
+    let mac = digest::hmac_sha256(secret.as_bytes(), message.as_bytes());
+    format!("sha256={}", digest::to_hex(&mac))
+}
+
+/// Sends one delivery and returns the status the receiver answered with. It goes to an address
+/// that passed the checks rather than whatever the name resolves to by then, and a redirect
+/// is returned as is instead of followed, so neither can lead it somewhere it isn't allowed.
+fn post(target: &Target, head: &str, body: &str, timeout: Duration) -> Result<u16, Failure> {
+    let endpoint = Endpoint::parse(&target.url)
+        .ok_or_else(|| Failure::Permanent("not an http or https URL".into()))?;
+    // Resolved on every attempt, as what the name resolves to can change in between
+    let addrs = resolve(&endpoint, target.public_only)?;
+    let stream = connect(&addrs, timeout).map_err(|e| Failure::Temporary(e.to_string()))?;
+
+    let request = format!(
+        "POST {} HTTP/1.1\r\nHost: {}\r\n{head}Content-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        endpoint.path,
+        endpoint.authority,
+        body.len()
+    );
+    let status = if endpoint.https {
+        #[cfg(any(feature = "native-tls", feature = "rustls-tls"))]
+        {
+            crate::tls::connect(endpoint.host, stream).and_then(|tls| exchange(tls, &request))
+        }
+        #[cfg(not(any(feature = "native-tls", feature = "rustls-tls")))]
+        {
+            drop(stream);
+            return Err(Failure::Permanent(
+                "https webhooks need a build with TLS support".into(),
+            ));
+        }
+    } else {
+        exchange(stream, &request)
+    };
+    status.map_err(|e| Failure::Temporary(e.to_string()))
+}
+
+fn connect(addrs: &[SocketAddr], timeout: Duration) -> io::Result<TcpStream> {
+    let mut last_error = None;
+    for addr in addrs {
+        match TcpStream::connect_timeout(addr, timeout) {
+            Ok(stream) => {
+                stream.set_read_timeout(Some(timeout))?;
+                stream.set_write_timeout(Some(timeout))?;
+                return Ok(stream);
+            }
+            Err(e) => last_error = Some(e),
+        }
+    }
+    Err(last_error.unwrap_or_else(|| io::Error::other("no addresses to connect to")))
+}
+
+/// Writes the whole request and reads the status code off the answer
+fn exchange(mut stream: impl Read + Write, request: &str) -> io::Result<u16> {
+    stream.write_all(request.as_bytes())?;
+    stream.flush()?;
+
+    let mut line = String::new();
+    BufReader::new(stream.take(MAX_STATUS_LINE)).read_line(&mut line)?;
+    line.strip_prefix("HTTP/1.")
+        .and_then(|rest| rest.split(' ').nth(1))
+        .and_then(|status| status.parse().ok())
+        .filter(|status| (100..=999).contains(status))
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "not an HTTP response"))
+}
+
+/// The parts of an http or https URL a delivery needs
+#[derive(Debug, PartialEq, Eq)]
+struct Endpoint<'a> {
+    https: bool,
+    /// Without the brackets around an IPv6 address
+    host: &'a str,
+    port: u16,
+    /// The host and port as written, for the `Host` header
+    authority: &'a str,
+    path: String,
+}
+
+impl<'a> Endpoint<'a> {
+    fn parse(url: &'a str) -> Option<Self> {
+        // They'd end up in the request line, where they could smuggle in headers
+        if url.contains(char::is_control) {
+            return None;
+        }
+        let (rest, https) = url
+            .strip_prefix("https://")
+            .map(|rest| (rest, true))
+            .or_else(|| url.strip_prefix("http://").map(|rest| (rest, false)))?;
+        let end = rest.find(['/', '?', '#']).unwrap_or(rest.len());
+        let (authority, path) = rest.split_at(end);
+        // With credentials in front, `allowed.example@elsewhere` would pass for the wrong host
+        if authority.contains(['@', '\\']) {
+            return None;
+        }
+
+        let (host, port) = match authority.strip_prefix('[') {
+            Some(ipv6) => {
+                let (host, after) = ipv6.split_once(']')?;
+                let port = if after.is_empty() {
+                    None
+                } else {
+                    Some(after.strip_prefix(':')?)
+                };
+                (host, port)
+            }
+            None => match authority.split_once(':') {
+                Some((host, port)) => (host, Some(port)),
+                None => (authority, None),
+            },
+        };
+        let default_port = if https { 443 } else { 80 };
+        let port = port.map_or(Some(default_port), |port| port.parse().ok())?;
+        if host.is_empty() {
+            return None;
+        }
+
+        let path = path.split('#').next().unwrap_or_default();
+        let path = if path.starts_with('/') {
+            path.to_string()
+        } else {
+            format!("/{path}")
+        };
+        Some(Self {
+            https,
+            host,
+            port,
+            authority,
+            path,
+        })
+    }
+}
+
+/// Every address the host resolves to. With `public_only`, which keeps a URL a request named off
+/// the server's own network, all of them have to be public ones.
+fn resolve(endpoint: &Endpoint, public_only: bool) -> Result<Vec<SocketAddr>, Failure> {
+    let host = endpoint.host;
+    let addrs = (host, endpoint.port)
+        .to_socket_addrs()
+        .map_err(|e| Failure::Temporary(format!("can't resolve {host}: {e}")))?
+        .collect::<Vec<_>>();
+    if addrs.is_empty() {
+        return Err(Failure::Temporary(format!("{host} has no addresses")));
+    }
+    if public_only && !addrs.iter().all(|addr| is_public(addr.ip())) {
+        return Err(Failure::Permanent(format!(
+            "{host} is not a public address"
+        )));
+    }
+    Ok(addrs)
+}
+
+const fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_public_v4(ip),
+        IpAddr::V6(ip) => is_public_v6(ip),
+    }
+}
+
+const fn is_public_v4(ip: Ipv4Addr) -> bool {
+    let [first, second, ..] = ip.octets();
+    !(ip.is_private()
+        || ip.is_loopback()
+        || ip.is_link_local()
+        || ip.is_unspecified()
+        || ip.is_broadcast()
+        || ip.is_documentation()
+        || ip.is_multicast()
+        || first == 0
+        // Shared address space behind carrier-grade NAT, 100.64.0.0/10
+        || (first == 100 && second & 0xc0 == 64)
+        || first >= 240)
+}
+
+const fn is_public_v6(ip: Ipv6Addr) -> bool {
+    if let Some(ip) = ip.to_ipv4_mapped() {
+        return is_public_v4(ip);
+    }
+    let first = ip.segments()[0];
+    !(ip.is_loopback()
+        || ip.is_unspecified()
+        || ip.is_multicast()
+        // Unique local fc00::/7 and link-local fe80::/10
+        || first & 0xfe00 == 0xfc00
+        || first & 0xffc0 == 0xfe80)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex, PoisonError};
+
+    struct Delivery {
+        headers: Vec<(String, String)>,
+        body: String,
+    }
+
+    impl Delivery {
+        fn header(&self, name: &str) -> Option<&str> {
+            self.headers
+                .iter()
+                .find(|(n, _)| n.eq_ignore_ascii_case(name))
+                .map(|(_, v)| v.as_str())
+        }
+    }
+
+    /// A receiver on a loopback port that answers with `statuses` in turn, then 200s
+    fn receiver(statuses: &[u16]) -> (String, Arc<Mutex<Vec<Delivery>>>) {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).expect("bind a loopback port");
+        let url = format!(
+            "http://{}/hook",
+            listener.local_addr().expect("has an address")
+        );
+        let received = Arc::new(Mutex::new(Vec::new()));
+
+        let statuses = statuses.to_vec();
+        let log = Arc::clone(&received);
+        thread::spawn(move || {
+            for (i, stream) in listener.incoming().enumerate() {
+                let Ok(mut stream) = stream else {
+                    continue;
+                };
+                let delivery = read_delivery(&mut BufReader::new(&stream));
+                log.lock()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .push(delivery);
+                let status = statuses.get(i).copied().unwrap_or(200);
+                let _ = write!(
+                    stream,
+                    "HTTP/1.1 {status} Whatever\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+                );
+            }
+        });
+        (url, received)
+    }
+
+    fn read_delivery(reader: &mut impl BufRead) -> Delivery {
+        let mut headers = Vec::new();
+        let mut first = true;
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line).expect("read a request line");
+            let line = line.trim_end();
+            if line.is_empty() {
+                break;
+            }
+            if !std::mem::take(&mut first)
+                && let Some((name, value)) = line.split_once(':')
+            {
+                headers.push((name.to_string(), value.trim().to_string()));
+            }
+        }
+        let mut delivery = Delivery {
+            headers,
+            body: String::new(),
+        };
+        let len = delivery
+            .header("content-length")
+            .and_then(|len| len.parse().ok())
+            .expect("deliveries have a Content-Length");
+        let mut body = vec![0; len];
+        reader.read_exact(&mut body).expect("read the body");
+        delivery.body = String::from_utf8(body).expect("the body is UTF-8");
+        delivery
+    }
+
+    fn settings(retries: u32) -> config::Webhook {
+        config::Webhook {
+            url: None,
+            secret: Some("hunter2".into()),
+            timeout_secs: 5,
+            retries,
+            allowed_hosts: Vec::new(),
+        }
+    }
+
+    const TIMESTAMP: u64 = 1_700_000_000;
+
+    /// Reaches the loopback receiver, as the configured URL would
+    fn configured(url: String) -> Target {
+        Target {
+            url,
+            signed: true,
+            public_only: false,
+        }
+    }
+
+    fn deliveries(received: &Mutex<Vec<Delivery>>) -> Vec<Delivery> {
+        std::mem::take(&mut *received.lock().unwrap_or_else(PoisonError::into_inner))
+    }
+
+    #[test]
+    fn signs_the_timestamp_and_body() {
+        // HMAC-SHA256 of `1700000000.<body>` keyed with `hunter2`, worked out independently
+        assert_eq!(
+            sign("hunter2", "1700000000", "{\"event\":\"summary.completed\"}"),
+            "sha256=1d3c778181088f375bda5253090169c8bcdee86d98ef49faae290dad90674906"
+        );
+        assert_ne!(
+            sign("hunter2", "1700000001", "{\"event\":\"summary.completed\"}"),
+            sign("hunter2", "1700000000", "{\"event\":\"summary.completed\"}")
+        );
+    }
+
+    #[test]
+    fn signs_configured_deliveries() {
+        let (url, received) = receiver(&[]);
+        deliver(
+            &configured(url),
+            &Payload::new("summary.completed", "https://youtu.be/x"),
+            TIMESTAMP,
+            &settings(0),
+        );
+
+        let deliveries = deliveries(&received);
+        assert_eq!(deliveries.len(), 1);
+        let delivery = &deliveries[0];
+        assert_eq!(delivery.header("x-tldr-event"), Some("summary.completed"));
+        assert_eq!(delivery.header("x-tldr-timestamp"), Some("1700000000"));
+        let expected = sign("hunter2", "1700000000", &delivery.body);
+        assert_eq!(delivery.header("x-tldr-signature"), Some(expected.as_str()));
+    }
+
+    #[test]
+    fn leaves_request_targets_unsigned() {
+        let (url, received) = receiver(&[]);
+        let target = Target {
+            url,
+            signed: false,
+            public_only: false,
+        };
+        deliver(
+            &target,
+            &Payload::new("summary.completed", "https://youtu.be/x"),
+            TIMESTAMP,
+            &settings(0),
+        );
+
+        let deliveries = deliveries(&received);
+        assert_eq!(deliveries.len(), 1);
+        assert_eq!(deliveries[0].header("x-tldr-timestamp"), Some("1700000000"));
+        assert_eq!(deliveries[0].header("x-tldr-signature"), None);
+    }
+
+    #[test]
+    fn keeps_request_targets_off_private_addresses() {
+        let (url, received) = receiver(&[]);
+        let target = Target {
+            url,
+            signed: false,
+            public_only: true,
+        };
+        deliver(
+            &target,
+            &Payload::new("summary.completed", "https://youtu.be/x"),
+            TIMESTAMP,
+            &settings(2),
+        );
+
+        assert!(deliveries(&received).is_empty());
+    }
+
+    #[test]
+    fn does_not_follow_redirects() {
+        let (url, received) = receiver(&[307]);
+        deliver(
+            &configured(url),
+            &Payload::new("summary.completed", "https://youtu.be/x"),
+            TIMESTAMP,
+            &settings(3),
+        );
+
+        assert_eq!(deliveries(&received).len(), 1);
+    }
+
+    #[test]
+    fn retries_server_errors_with_the_same_delivery() {
+        let (url, received) = receiver(&[503, 500]);
+        deliver(
+            &configured(url),
+            &Payload::new("summary.failed", "https://youtu.be/x"),
+            TIMESTAMP,
+            &settings(3),
+        );
+
+        let deliveries = deliveries(&received);
+        assert_eq!(deliveries.len(), 3);
+        let id = deliveries[0].header("x-tldr-delivery");
+        assert!(id.is_some());
+        assert!(deliveries.iter().all(|d| d.header("x-tldr-delivery") == id));
+    }
+
+    #[test]
+    fn gives_up_after_the_retries() {
+        let (url, received) = receiver(&[500, 500, 500, 500]);
+        deliver(
+            &configured(url),
+            &Payload::new("summary.failed", "https://youtu.be/x"),
+            TIMESTAMP,
+            &settings(2),
+        );
+
+        assert_eq!(deliveries(&received).len(), 3);
+    }
+
+    #[test]
+    fn does_not_retry_client_errors() {
+        let (url, received) = receiver(&[400]);
+        deliver(
+            &configured(url),
+            &Payload::new("summary.completed", "https://youtu.be/x"),
+            TIMESTAMP,
+            &settings(3),
+        );
+
+        assert_eq!(deliveries(&received).len(), 1);
+    }
+
+    #[test]
+    fn failures_carry_redacted_errors() {
+        let error = Error::BadRequest("https://example.com/?key=s3cr3t was refused".into());
+        let payload = Payload::failed("summary.failed", "https://youtu.be/x", &error);
+        assert_eq!(payload.error_code, Some("bad_request"));
+        assert_eq!(
+            payload.error.as_deref(),
+            Some("Bad request: https://example.com/?key=[REDACTED] was refused")
+        );
+    }
+
+    #[test]
+    fn finds_where_a_url_connects_to() {
+        let endpoint = |url| Endpoint::parse(url).map(|e| (e.https, e.host, e.port, e.path));
+        assert_eq!(
+            endpoint("https://hooks.example.com/tldr?x=1#top"),
+            Some((true, "hooks.example.com", 443, "/tldr?x=1".to_string()))
+        );
+        assert_eq!(
+            endpoint("http://hooks.example.com:8080"),
+            Some((false, "hooks.example.com", 8080, "/".to_string()))
+        );
+        assert_eq!(
+            endpoint("http://hooks.example.com?x=1"),
+            Some((false, "hooks.example.com", 80, "/?x=1".to_string()))
+        );
+        assert_eq!(
+            endpoint("http://[::1]:9000/"),
+            Some((false, "::1", 9000, "/".to_string()))
+        );
+        assert_eq!(
+            Endpoint::parse("http://[::1]/").map(|e| e.authority),
+            Some("[::1]")
+        );
+
+        for url in [
+            "http://hooks.example.com@127.0.0.1/",
+            "http://hooks.example.com\\@127.0.0.1/",
+            "http://hooks.example.com:notaport/",
+            "http://[::1]x/",
+            "http:///path",
+            "ftp://hooks.example.com/",
+            "http://hooks.example.com/\r\nX-Injected: 1",
+        ] {
+            assert_eq!(Endpoint::parse(url), None, "{url}");
+        }
+    }
+
+    #[test]
+    fn only_public_addresses_are_public() {
+        for ip in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "255.255.255.255",
+            "::1",
+            "::",
+            "fd00::1",
+            "fe80::1",
+            "::ffff:127.0.0.1",
+            "::ffff:169.254.169.254",
+        ] {
+            let ip: IpAddr = ip.parse().expect("a valid address");
+            assert!(!is_public(ip), "{ip}");
+        }
+        for ip in ["93.184.216.34", "1.1.1.1", "2606:4700:4700::1111"] {
+            let ip: IpAddr = ip.parse().expect("a valid address");
+            assert!(is_public(ip), "{ip}");
+        }
+    }
+
+    #[test]
+    fn refuses_named_urls_on_private_addresses() {
+        for url in [
+            "http://127.0.0.1:9/",
+            "http://localhost:9/",
+            "http://[::1]:9/",
+        ] {
+            let endpoint = Endpoint::parse(url).expect("a valid URL");
+            assert!(
+                matches!(resolve(&endpoint, true), Err(Failure::Permanent(_))),
+                "{url}"
+            );
+            assert!(resolve(&endpoint, false).is_ok(), "{url}");
+        }
+    }
+}
@@ -0,0 +1,205 @@
+use crate::{datetime, digest};
+use std::fmt;
+
+/// Bit 11 of the general purpose flags: file names are UTF-8
+const UTF8_NAMES: u16 = 1 << 11;
+const VERSION: u16 = 20;
+const LOCAL_HEADER_LEN: usize = 30;
+
+/// The archive would need the ZIP64 extensions, which aren't written: more than 65534 files, or a
+/// file, name or offset past what the 16 and 32-bit fields hold
+#[derive(Debug)]
+pub struct TooLarge;
+
+impl fmt::Display for TooLarge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "too large for a zip file without ZIP64")
+    }
+}
+
+impl std::error::Error for TooLarge {}
+
+/// Writes a zip archive in memory. Files are stored uncompressed, which is what EPUB wants for its
+/// first entry anyway, and keeps this small.
+pub struct ZipWriter {
+    out: Vec<u8>,
+    central_directory: Vec<u8>,
+    count: u16,
+    date: u16,
+    time: u16,
+}
+
+impl ZipWriter {
+    pub fn new() -> Self {
+        let (date, time) = datetime::dos_date_time(datetime::now_unix());
+        Self {
+            out: Vec::new(),
+            central_directory: Vec::new(),
+            count: 0,
+            date,
+            time,
+        }
+    }
+
+    pub fn add(&mut self, name: &str, data: &[u8]) -> Result<(), TooLarge> {
+        // An all-ones count means "see the ZIP64 record", so it's out of reach too
+        if self.count >= u16::MAX - 1 {
+            return Err(TooLarge);
+        }
+        let name_len = u16::try_from(name.len()).map_err(|_| TooLarge)?;
+        let offset = fits_u32(self.out.len())?;
+        let size = fits_u32(data.len())?;
+        // The central directory starts where this file ends, and its offset has to fit as well
+        fits_u32(self.out.len() + LOCAL_HEADER_LEN + name.len() + data.len())?;
+        let crc = digest::crc32(data);
+
+        self.out.extend_from_slice(&0x0403_4b50_u32.to_le_bytes());
+        self.push_common(crc, size, name_len, false);
+        self.out.extend_from_slice(name.as_bytes());
+        self.out.extend_from_slice(data);
+
+        self.central_directory
+            .extend_from_slice(&0x0201_4b50_u32.to_le_bytes());
+        self.push_common(crc, size, name_len, true);
+        let central = &mut self.central_directory;
+        central.extend_from_slice(&0u16.to_le_bytes()); // comment length
+        central.extend_from_slice(&0u16.to_le_bytes()); // disk number
+        central.extend_from_slice(&0u16.to_le_bytes()); // internal attributes
+        central.extend_from_slice(&0u32.to_le_bytes()); // external attributes
+        central.extend_from_slice(&offset.to_le_bytes());
+        central.extend_from_slice(name.as_bytes());
+
+        self.count += 1;
+        Ok(())
+    }
+
+    /// Bytes written so far, headers included
+    pub const fn size(&self) -> usize {
+        self.out.len() + self.central_directory.len()
+    }
+
+    /// The fields local headers and central directory entries share. Central ones start with the
+    /// version that made the file.
+    fn push_common(&mut self, crc: u32, size: u32, name_len: u16, central: bool) {
+        let buf = if central {
+            self.central_directory
+                .extend_from_slice(&VERSION.to_le_bytes());
+            &mut self.central_directory
+        } else {
+            &mut self.out
+        };
+        buf.extend_from_slice(&VERSION.to_le_bytes());
+        buf.extend_from_slice(&UTF8_NAMES.to_le_bytes());
+        buf.extend_from_slice(&0u16.to_le_bytes()); // stored, no compression
+        buf.extend_from_slice(&self.time.to_le_bytes());
+        buf.extend_from_slice(&self.date.to_le_bytes());
+        buf.extend_from_slice(&crc.to_le_bytes());
+        buf.extend_from_slice(&size.to_le_bytes()); // compressed size
+        buf.extend_from_slice(&size.to_le_bytes());
+        buf.extend_from_slice(&name_len.to_le_bytes());
+        buf.extend_from_slice(&0u16.to_le_bytes()); // extra field length
+    }
+
+    pub fn finish(mut self) -> Result<Vec<u8>, TooLarge> {
+        let directory_offset = fits_u32(self.out.len())?;
+        let directory_size = fits_u32(self.central_directory.len())?;
+        self.out.append(&mut self.central_directory);
+
+        let out = &mut self.out;
+        out.extend_from_slice(&0x0605_4b50_u32.to_le_bytes());
+        out.extend_from_slice(&0u16.to_le_bytes()); // this disk
+        out.extend_from_slice(&0u16.to_le_bytes()); // disk with the directory
+        out.extend_from_slice(&self.count.to_le_bytes());
+        out.extend_from_slice(&self.count.to_le_bytes());
+        out.extend_from_slice(&directory_size.to_le_bytes());
+        out.extend_from_slice(&directory_offset.to_le_bytes());
+        out.extend_from_slice(&0u16.to_le_bytes()); // comment length
+        Ok(self.out)
+    }
+}
+
+/// A size or offset as its 32-bit field, where all ones would mean "see the ZIP64 record"
+fn fits_u32(value: usize) -> Result<u32, TooLarge> {
+    u32::try_from(value)
+        .ok()
+        .filter(|&value| value != u32::MAX)
+        .ok_or(TooLarge)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn u16_at(bytes: &[u8], at: usize) -> usize {
+        usize::from(u16::from_le_bytes([bytes[at], bytes[at + 1]]))
+    }
+
+    fn u32_at(bytes: &[u8], at: usize) -> usize {
+        let field = u32::from_le_bytes(bytes[at..at + 4].try_into().expect("four bytes"));
+        usize::try_from(field).expect("fits in usize")
+    }
+
+    #[test]
+    fn central_directory_agrees_with_local_headers() {
+        let files: [(&str, &[u8]); 3] = [
+            ("mimetype", b"application/epub+zip"),
+            ("empty.txt", b""),
+            ("OEBPS/naïve.xhtml", b"<p>caf\xc3\xa9</p>"),
+        ];
+        let mut zip = ZipWriter::new();
+        for (name, data) in files {
+            zip.add(name, data).expect("small files fit");
+        }
+        let archive = zip.finish().expect("small archives fit");
+
+        let end = &archive[archive.len() - 22..];
+        assert_eq!(&end[..4], &0x0605_4b50_u32.to_le_bytes());
+        assert_eq!(u16_at(end, 8), files.len());
+        assert_eq!(u16_at(end, 10), files.len());
+        let (directory_size, directory_offset) = (u32_at(end, 12), u32_at(end, 16));
+        assert_eq!(directory_offset + directory_size, archive.len() - 22);
+
+        let mut at = directory_offset;
+        for (name, data) in files {
+            let central = &archive[at..];
+            assert_eq!(&central[..4], &0x0201_4b50_u32.to_le_bytes());
+            let offset = u32_at(central, 42);
+            let local = &archive[offset..];
+            assert_eq!(&local[..4], &0x0403_4b50_u32.to_le_bytes());
+
+            // Version needed through extra field length are laid out the same in both
+            assert_eq!(central[6..32], local[4..30], "{name}");
+            assert_eq!(local[14..18], digest::crc32(data).to_le_bytes());
+            assert_eq!(u32_at(local, 22), data.len());
+            assert_eq!(&central[46..46 + name.len()], name.as_bytes());
+            assert_eq!(&local[30..30 + name.len()], name.as_bytes());
+            let data_start = LOCAL_HEADER_LEN + name.len();
+            assert_eq!(&local[data_start..data_start + data.len()], data);
+
+            at += 46 + u16_at(central, 28);
+        }
+        assert_eq!(at, directory_offset + directory_size);
+    }
+
+    #[test]
+    fn refuses_more_files_than_fit_without_zip64() {
+        let mut zip = ZipWriter::new();
+        for i in 0..u16::MAX - 1 {
+            zip.add(&i.to_string(), b"").expect("room for this file");
+        }
+        assert!(zip.add("one too many", b"").is_err());
+
+        let archive = zip.finish().expect("the files that fit make an archive");
+        let end = &archive[archive.len() - 22..];
+        assert_eq!(&end[..4], &0x0605_4b50_u32.to_le_bytes());
+        assert_eq!(u16::from_le_bytes([end[10], end[11]]), u16::MAX - 1);
+    }
+
+    #[test]
+    fn refuses_names_longer_than_their_field() {
+        let mut zip = ZipWriter::new();
+        assert!(zip.add(&"a".repeat(usize::from(u16::MAX) + 1), b"").is_err());
+        zip.add(&"a".repeat(usize::from(u16::MAX)), b"")
+            .expect("the longest name that fits");
+    }
+}